@@ -1,72 +1,127 @@
+use std::collections::HashMap;
 use std::fs;
+use std::io;
+use std::marker::PhantomData;
 use std::path::Path;
 
-pub trait FileList {
-    fn get(&self, name: &str) -> Option<&'static str>;
-    fn contents(&self) -> impl Iterator<Item = (&'static str, &'static str)>;
-    fn names(&self) -> impl Iterator<Item = &'static str>;
+/// The kinds of data that asset files can contain: text or binary.
+pub trait Content: 'static {
+    /// An owned buffer for this kind of data.
+    type Owned;
+
+    /// Read a file from disk.
+    fn read(path: &Path) -> io::Result<Self::Owned>;
+}
+
+impl Content for str {
+    type Owned = String;
+
+    fn read(path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+}
+
+impl Content for [u8] {
+    type Owned = Vec<u8>;
+
+    fn read(path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+}
+
+/// The files in a set of assets, which may or may not include their contents.
+pub trait FileList<C: Content + ?Sized> {
+    fn get(&self, name: &str) -> Option<&C>;
+    fn contents(&self) -> impl Iterator<Item = (&str, &C)>;
+    fn names(&self) -> impl Iterator<Item = &str>;
+
+    /// Read a file from disk, in the directory `dir`, if it's in the list.
+    fn read(&self, dir: &str, name: &str) -> io::Result<Option<C::Owned>> {
+        if self.names().any(|n| n == name) {
+            C::read(&Path::new(dir).join(name)).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
 }
 
 type NameList = &'static [&'static str];
-type ContentList = &'static [(&'static str, &'static str)];
+type ContentList<C> = &'static [(&'static str, &'static C)];
 
-impl FileList for NameList {
-    fn get(&self, _name: &str) -> Option<&'static str> {
+impl<C: Content + ?Sized> FileList<C> for NameList {
+    fn get(&self, _name: &str) -> Option<&C> {
         None
     }
 
-    fn contents(&self) -> impl Iterator<Item = (&'static str, &'static str)> {
+    fn contents(&self) -> impl Iterator<Item = (&str, &C)> {
         std::iter::empty()
     }
 
-    fn names(&self) -> impl Iterator<Item = &'static str> {
+    fn names(&self) -> impl Iterator<Item = &str> {
         self.iter().copied()
     }
 }
 
-impl FileList for ContentList {
-    fn get(&self, name: &str) -> Option<&'static str> {
+impl<C: Content + ?Sized> FileList<C> for ContentList<C> {
+    fn get(&self, name: &str) -> Option<&C> {
         match self.iter().find(|(n, _)| *n == name) {
             Some((_, c)) => Some(c),
             None => None,
         }
     }
 
-    fn contents(&self) -> impl Iterator<Item = (&'static str, &'static str)> {
+    fn contents(&self) -> impl Iterator<Item = (&str, &C)> {
         self.iter().copied()
     }
 
-    fn names(&self) -> impl Iterator<Item = &'static str> {
+    fn names(&self) -> impl Iterator<Item = &str> {
         self.iter().map(|(n, _)| *n)
     }
 }
 
-pub struct Assets<F: FileList> {
+/// Text files built at runtime, which exist only in memory.
+impl FileList<str> for HashMap<String, String> {
+    fn get(&self, name: &str) -> Option<&str> {
+        HashMap::get(self, name).map(String::as_str)
+    }
+
+    fn contents(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.iter().map(|(n, c)| (n.as_str(), c.as_str()))
+    }
+
+    fn names(&self) -> impl Iterator<Item = &str> {
+        self.keys().map(String::as_str)
+    }
+
+    /// There's nothing on disk, so "reading" copies the contents.
+    fn read(&self, _dir: &str, name: &str) -> io::Result<Option<String>> {
+        Ok(HashMap::get(self, name).cloned())
+    }
+}
+
+pub struct Assets<F: FileList<C>, C: Content + ?Sized = str> {
     /// The directory path for this set of assets.
     pub dir: &'static str,
 
     /// The names and (possibly) contents of the assets.
     files: F,
+
+    content: PhantomData<&'static C>,
 }
 
-impl<F: FileList> Assets<F> {
+impl<F: FileList<C>, C: Content + ?Sized> Assets<F, C> {
     /// Check whether a given asset file is available (embedded or on disk).
     pub fn contains(&self, name: &str) -> bool {
         self.files.names().any(|n| n == name)
     }
 
-    /// Read an asset file from disk.
-    pub fn read(&self, name: &str) -> std::io::Result<Option<String>> {
-        if self.contains(name) {
-            let path = Path::new(self.dir).join(name);
-            fs::read_to_string(path).map(Some)
-        } else {
-            Ok(None)
-        }
+    /// Read an asset file from disk, or from memory for dynamic assets.
+    pub fn read(&self, name: &str) -> io::Result<Option<C::Owned>> {
+        self.files.read(self.dir, name)
     }
 
-    /// Read all assets from disk, returning their name and contents.
-    pub fn read_all(&self) -> impl Iterator<Item = (&'static str, std::io::Result<String>)> {
+    /// Read all assets, returning their name and contents.
+    pub fn read_all(&self) -> impl Iterator<Item = (&str, io::Result<C::Owned>)> {
         self.files.names().map(|name| match self.read(name) {
             Ok(c) => (name, Ok(c.expect("registered file not found"))),
             Err(e) => (name, Err(e)),
@@ -75,34 +130,65 @@ impl<F: FileList> Assets<F> {
 
     /// Get the embedded contents of a file. If this is a filesystem-only asset
     /// set, this always returns None.
-    pub fn get(&self, name: &str) -> Option<&'static str> {
+    pub fn get(&self, name: &str) -> Option<&C> {
         self.files.get(name)
     }
 
     /// Get all the embedded files, iterating over `(name, contents)` pairs. If
     /// this is a filesystem-only asset set, this is always empty.
-    pub fn contents(&self) -> impl Iterator<Item = (&'static str, &'static str)> {
+    pub fn contents(&self) -> impl Iterator<Item = (&str, &C)> {
         self.files.contents()
     }
 }
 
-impl Assets<ContentList> {
-    pub const fn new(dir: &'static str, contents: ContentList) -> Self {
+impl<C: Content + ?Sized> Assets<ContentList<C>, C> {
+    pub const fn new(dir: &'static str, contents: ContentList<C>) -> Self {
         Self {
             dir,
             files: contents,
+            content: PhantomData,
         }
     }
 }
 
-impl Assets<NameList> {
+impl<C: Content + ?Sized> Assets<NameList, C> {
     pub const fn new(dir: &'static str, names: NameList) -> Self {
-        Self { dir, files: names }
+        Self {
+            dir,
+            files: names,
+            content: PhantomData,
+        }
+    }
+}
+
+impl Assets<HashMap<String, String>> {
+    /// Make a set of text assets from in-memory contents, keyed by name. These
+    /// have no directory on disk, so `read` returns the same contents as `get`.
+    pub fn new(files: HashMap<String, String>) -> Self {
+        Self {
+            dir: "",
+            files,
+            content: PhantomData,
+        }
+    }
+
+    /// Add a file, replacing any existing file with the same name.
+    pub fn insert(&mut self, name: impl Into<String>, contents: impl Into<String>) {
+        self.files.insert(name.into(), contents.into());
+    }
+}
+
+impl Default for DynamicAssets {
+    fn default() -> Self {
+        Self::new(HashMap::new())
     }
 }
 
-pub type EmbeddedAssets = Assets<ContentList>;
+pub type EmbeddedAssets = Assets<ContentList<str>>;
 pub type FileAssets = Assets<NameList>;
+pub type EmbeddedBinaryAssets = Assets<ContentList<[u8]>, [u8]>;
+pub type BinaryFileAssets = Assets<NameList, [u8]>;
+pub type DynamicAssets = Assets<HashMap<String, String>>;
 
 /// Embed a list of asset files in the binary.
 #[macro_export]
@@ -120,6 +206,22 @@ macro_rules! embed_assets {
     };
 }
 
+/// Embed a list of binary asset files (e.g., images or fonts) in the binary.
+#[macro_export]
+macro_rules! embed_binary_assets {
+    ($constname:ident, $dirname:literal, [ $($filename:literal),* ]) => {
+        pub(crate) const $constname: $crate::assets::EmbeddedBinaryAssets = $crate::assets::EmbeddedBinaryAssets::new(
+            concat!(env!("CARGO_MANIFEST_DIR"), "/", $dirname),
+            &[$(
+                (
+                    $filename,
+                    include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/", $dirname, "/", $filename)),
+                ),
+            )*],
+        );
+    };
+}
+
 /// Provide access to a list of asset files in the filesystem.
 #[macro_export]
 macro_rules! file_assets {
@@ -131,6 +233,17 @@ macro_rules! file_assets {
     };
 }
 
+/// Provide access to a list of binary asset files in the filesystem.
+#[macro_export]
+macro_rules! binary_file_assets {
+    ($constname:ident, $dirname:literal, [ $($filename:literal),* ]) => {
+        pub(crate) const $constname: $crate::assets::BinaryFileAssets = $crate::assets::BinaryFileAssets::new(
+            concat!(env!("CARGO_MANIFEST_DIR"), "/", $dirname),
+            &[$( $filename, )*],
+        );
+    };
+}
+
 /// Either embed asset files or use them directly from the filesystem, depending
 /// on whether we're building in debug or release mode.
 #[macro_export]
@@ -144,10 +257,63 @@ macro_rules! assets {
     };
 }
 
+/// Like `assets!`, but for binary files.
+#[macro_export]
+macro_rules! binary_assets {
+    ($constname:ident, $dirname:literal, [ $($filename:literal),* ]) => {
+        #[cfg(debug_assertions)]
+        $crate::assets::binary_file_assets!($constname, $dirname, [ $($filename),* ]);
+
+        #[cfg(not(debug_assertions))]
+        $crate::assets::embed_binary_assets!($constname, $dirname, [ $($filename),* ]);
+    };
+}
+
 pub(crate) use assets;
 
+#[allow(unused_imports)]
+pub(crate) use binary_assets;
+
 #[allow(unused_imports)]
 pub(crate) use embed_assets;
 
+#[allow(unused_imports)]
+pub(crate) use embed_binary_assets;
+
 #[allow(unused_imports)]
 pub(crate) use file_assets;
+
+#[allow(unused_imports)]
+pub(crate) use binary_file_assets;
+
+#[cfg(test)]
+mod tests {
+    embed_binary_assets!(EMBEDDED, "templates", ["style.css"]);
+    binary_file_assets!(ON_DISK, "templates", ["style.css"]);
+
+    #[test]
+    fn binary_assets() {
+        let data = ON_DISK.read("style.css").unwrap().unwrap();
+        assert_eq!(EMBEDDED.get("style.css"), Some(&data[..]));
+        assert_eq!(ON_DISK.get("style.css"), None);
+        assert_eq!(EMBEDDED.get("missing.png"), None);
+    }
+
+    #[test]
+    fn dynamic_assets() {
+        let mut assets = super::DynamicAssets::default();
+        assets.insert("note.html", "old");
+        assets.insert("note.html", "new");
+        assert!(assets.contains("note.html"));
+        assert_eq!(assets.get("note.html"), Some("new"));
+        assert_eq!(assets.get("missing.html"), None);
+        assert_eq!(
+            assets.contents().collect::<Vec<_>>(),
+            [("note.html", "new")]
+        );
+        assert_eq!(assets.read("note.html").unwrap().as_deref(), Some("new"));
+        assert_eq!(assets.read("missing.html").unwrap(), None);
+        let all: Vec<_> = assets.read_all().map(|(n, c)| (n, c.unwrap())).collect();
+        assert_eq!(all, [("note.html", "new".to_string())]);
+    }
+}
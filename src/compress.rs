@@ -0,0 +1,89 @@
+//! Precompressed copies of a built site's text files, for static hosts that
+//! serve them with `Content-Encoding: gzip`.
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use mime_guess::mime;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Should a file be precompressed? Only text formats (HTML, CSS, JavaScript,
+/// XML, JSON, and so on) benefit; images and other binary formats are usually
+/// compressed already.
+pub fn is_text(path: &Path) -> bool {
+    let Some(mime) = mime_guess::from_path(path).first() else {
+        return false;
+    };
+    let text_subtypes = [mime::JAVASCRIPT, mime::JSON, mime::XML];
+    mime.type_() == mime::TEXT
+        || text_subtypes.contains(&mime.subtype())
+        || mime
+            .suffix()
+            .is_some_and(|s| s == mime::XML || s == mime::JSON)
+}
+
+/// Write a gzipped copy of a file next to it, with `.gz` appended to its name.
+/// Return the path of the compressed file.
+pub fn gzip_file(path: &Path) -> io::Result<PathBuf> {
+    let mut gz_path = path.as_os_str().to_owned();
+    gz_path.push(".gz");
+    let gz_path = PathBuf::from(gz_path);
+
+    let mut src = fs::File::open(path)?;
+    let mut encoder = GzEncoder::new(fs::File::create(&gz_path)?, Compression::best());
+    io::copy(&mut src, &mut encoder)?;
+    encoder.finish()?;
+    Ok(gz_path)
+}
+
+/// Write gzipped copies of all the text files in a directory tree.
+pub fn precompress(dir: &Path) -> io::Result<()> {
+    for entry in WalkDir::new(dir) {
+        let entry = entry?;
+        if entry.file_type().is_file() && is_text(entry.path()) {
+            gzip_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn text_types() {
+        for name in [
+            "a.html",
+            "style.css",
+            "app.js",
+            "feed.json",
+            "atom.xml",
+            "pic.svg",
+        ] {
+            assert!(is_text(Path::new(name)), "{name}");
+        }
+        for name in ["pic.png", "photo.jpg", "doc.pdf", "a.html.gz", "noext"] {
+            assert!(!is_text(Path::new(name)), "{name}");
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.html");
+        let html = "<p>hello</p>\n".repeat(100);
+        fs::write(&path, &html).unwrap();
+
+        let gz_path = gzip_file(&path).unwrap();
+        assert_eq!(gz_path, dir.path().join("a.html.gz"));
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(fs::File::open(&gz_path).unwrap())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, html);
+    }
+}
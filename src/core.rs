@@ -1,90 +1,413 @@
-use crate::assets::assets;
-use crate::{git, markdown, parallel};
-use anyhow::Result;
-use serde::Deserialize;
-use std::ffi::OsStr;
+use crate::assets::{DynamicAssets, assets};
+use crate::manifest::{self, BuildManifest, EntryKind, ManifestEntry, OutputFile};
+use crate::{compress, css, feed, git, markdown, parallel, related, search, sitemap, tags};
+use anyhow::{Context as _, Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
 use std::num::NonZero;
+use std::ops::RangeInclusive;
 use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
 use std::{fs, io};
 use walkdir::WalkDir;
 
 assets!(
     TEMPLATES,
     "templates",
-    ["note.html", "style.css", "livereload.js"]
+    [
+        "note.html",
+        "index.html",
+        "toc.html",
+        "tag.html",
+        "style.css",
+        "livereload.js"
+    ]
 );
 
+/// The filename for per-directory overrides of the note template.
+pub const NOTE_OVERRIDE: &str = "_note.html";
+
+/// The filename for site-wide template values, in the source directory.
+pub const SITE_VALUES: &str = "_site.yaml";
+
+/// Built-in assets that pages link to rather than include, so built sites get
+/// copies with content hashes in their names.
+const LINKED_ASSETS: &[&str] = &["style.css"];
+
+/// The note to show for missing pages, relative to the source directory.
+const NOT_FOUND_NOTE: &str = "404.md";
+
+/// A function that customizes the template environment, e.g., by registering
+/// filters and functions.
+pub type TemplateSetup = Box<dyn Fn(&mut minijinja::Environment<'static>) + Send + Sync>;
+
+/// A note that exercises the template context for validation.
+const SAMPLE_NOTE: &str = "---\ndraft: false\n---\n# Title\n\n## Section\n\nText.\n";
+
 pub struct Context {
+    /// The main source directory, which holds the configuration. Its files
+    /// take precedence over those in any other source directories.
     pub src_dir: PathBuf,
+    /// Other source directories merged into the site, from lowest to highest
+    /// precedence.
+    extra_src_dirs: Vec<PathBuf>,
     pub livereload: bool,
+    /// Treat problems that would otherwise be skipped as fatal errors.
+    pub strict: bool,
+    /// Look up commit information from git for each note.
+    pub git_info: bool,
+    /// Publish notes whose front matter date is in the future.
+    pub future: bool,
+    /// Publish notes marked as drafts in their front matter.
+    pub drafts: bool,
+    /// Let `resolve_resource` find hidden files, which are normally off-limits.
+    /// This is for previewing; builds always skip hidden files.
+    pub show_hidden: bool,
+    /// Let `resolve_resource` fall back to files whose names differ from the
+    /// requested path only in case, as on case-insensitive filesystems.
+    pub ignore_case: bool,
+    /// Minify CSS files when copying them into a built site.
+    pub minify: bool,
+    /// List the files a build generates, with hashes, in its report.
+    pub manifest: bool,
+    /// Write gzipped copies of a built site's text files.
+    pub gzip: bool,
+    /// Check that relative links in built pages point to files in the site.
+    pub check_links: bool,
+    /// Copy static files into a built site instead of hard-linking them, so
+    /// later edits to the sources can't change the site.
+    pub copy_files: bool,
+    /// Build over whatever is already in the destination directory instead of
+    /// deleting it first. Files the build doesn't produce are left alone.
+    pub keep_dest: bool,
+    /// Put the built-in stylesheet right in note pages instead of linking to
+    /// it, for pages that are viewed on their own.
+    pub inline_assets: bool,
+    /// The template for notes that don't pick one of their own.
+    pub note_template: String,
+    /// Metadata about the site as a whole, which comes from `config` unless
+    /// `new` gets some.
+    site: SiteConfig,
     pub config: Config,
-    tmpls: minijinja::Environment<'static>,
+    pub code_handlers: markdown::CodeHandlers,
+    /// The template environment, which is replaced as a whole when templates
+    /// are reloaded so rendering never needs exclusive access to the context.
+    tmpls: RwLock<Arc<minijinja::Environment<'static>>>,
+    /// The pages for notes' related-page lists, built on first use and kept
+    /// until `forget_notes`.
+    related_pages: RwLock<Option<Arc<Vec<related::Page>>>>,
+    template_files: TemplateFiles,
+    custom_templates: DynamicAssets,
+    template_setups: Vec<TemplateSetup>,
 }
 
 impl Context {
-    pub fn new(src_dir: &str, livereload: bool, config: Config) -> Self {
-        let mut ctx = Self {
+    /// Set up to render the site in `src_dir`. Its metadata, like the base
+    /// URL for feeds, comes from `config` unless `site` is given.
+    pub fn new(src_dir: &str, livereload: bool, config: Config, site: Option<SiteConfig>) -> Self {
+        let ctx = Self {
             src_dir: src_dir.into(),
-            tmpls: minijinja::Environment::new(),
+            extra_src_dirs: vec![],
+            tmpls: RwLock::default(),
+            related_pages: RwLock::default(),
+            template_files: TemplateFiles::default(),
+            custom_templates: DynamicAssets::default(),
+            template_setups: vec![],
             livereload,
+            strict: false,
+            git_info: config.git(),
+            future: false,
+            drafts: false,
+            show_hidden: false,
+            ignore_case: false,
+            minify: false,
+            manifest: false,
+            gzip: false,
+            check_links: false,
+            copy_files: false,
+            keep_dest: false,
+            inline_assets: false,
+            note_template: "note.html".into(),
+            site: site.unwrap_or_else(|| SiteConfig::from(&config)),
             config,
+            code_handlers: markdown::CodeHandlers::default(),
         };
 
+        ctx.load_templates().expect("error in built-in template");
+        ctx
+    }
+
+    /// Like `new`, but with some templates that replace the built-in ones of
+    /// the same name (e.g., `note.html`) or add new ones for them to use, and
+    /// the site's metadata from `config`. This fails if any of the templates
+    /// has a syntax error.
+    pub fn with_templates(
+        src_dir: &str,
+        livereload: bool,
+        config: Config,
+        templates: DynamicAssets,
+    ) -> Result<Self, minijinja::Error> {
+        let mut ctx = Self::new(src_dir, livereload, config, None);
+        ctx.custom_templates = templates;
+        ctx.load_templates()?;
+        Ok(ctx)
+    }
+
+    /// Customize the template environment with `setup`, which can register
+    /// filters, functions, and globals for templates to use. Customizations
+    /// are applied, in order, after the built-in ones, and again whenever the
+    /// templates are reloaded. If the templates then fail to load, `setup` is
+    /// dropped and the environment stays as it was.
+    ///
+    /// ```no_run
+    /// # use memoize::core::{Config, Context};
+    /// let mut ctx = Context::new("notes", false, Config::default(), None);
+    /// ctx.configure_templates(|env| {
+    ///     env.add_filter("shout", |s: String| s.to_uppercase());
+    ///     env.add_global("owner", "Ada");
+    /// })?;
+    /// # Ok::<(), minijinja::Error>(())
+    /// ```
+    pub fn configure_templates<F>(&mut self, setup: F) -> Result<(), minijinja::Error>
+    where
+        F: Fn(&mut minijinja::Environment<'static>) + Send + Sync + 'static,
+    {
+        self.template_setups.push(Box::new(setup));
+        self.load_templates().inspect_err(|_| {
+            self.template_setups.pop();
+        })
+    }
+
+    /// Merge other source directories into the site, replacing any set
+    /// before. They're listed from lowest to highest precedence: where two of
+    /// them have a file at the same relative path, the one later in `dirs`
+    /// wins, and the files in `src_dir` beat them all. If the templates then
+    /// fail to load, the source directories stay as they were.
+    pub fn set_extra_src_dirs<I>(&mut self, dirs: I) -> Result<(), minijinja::Error>
+    where
+        I: IntoIterator,
+        I::Item: Into<PathBuf>,
+    {
+        let old = std::mem::replace(
+            &mut self.extra_src_dirs,
+            dirs.into_iter().map(Into::into).collect(),
+        );
+        self.load_templates().inspect_err(|_| {
+            self.extra_src_dirs = old;
+        })
+    }
+
+    /// Metadata about the site as a whole, for the URLs we make for it.
+    pub fn site(&self) -> &SiteConfig {
+        &self.site
+    }
+
+    /// All the source directories, from lowest to highest precedence.
+    pub fn src_dirs(&self) -> impl DoubleEndedIterator<Item = &Path> {
+        self.extra_src_dirs
+            .iter()
+            .chain(std::iter::once(&self.src_dir))
+            .map(PathBuf::as_path)
+    }
+
+    /// Split a path within one of the source directories into that directory
+    /// and the path relative to it.
+    ///
+    /// Panics if `path` is not within any source directory.
+    fn split_src_path<'p>(&self, path: &'p Path) -> (&Path, &'p Path) {
+        // If source directories are nested, the innermost one is the base.
+        self.src_dirs()
+            .filter_map(|dir| Some((dir, path.strip_prefix(dir).ok()?)))
+            .min_by_key(|(_, rel)| rel.components().count())
+            .expect("path must be within a source directory")
+    }
+
+    /// Find the file or directory at a relative path in the source directory
+    /// with the highest precedence that has it. If none has it, this is the
+    /// path in `src_dir`.
+    fn find_src_path(&self, rel_path: &Path) -> PathBuf {
+        self.src_dirs()
+            .rev()
+            .map(|dir| dir.join(rel_path))
+            .find(|path| path.exists())
+            .unwrap_or_else(|| self.src_dir.join(rel_path))
+    }
+
+    /// Load the built-in and custom templates into a fresh environment,
+    /// forgetting any override templates we have already loaded. Renders that
+    /// are already underway finish with the old environment.
+    fn load_templates(&self) -> Result<(), minijinja::Error> {
+        let mut env = minijinja::Environment::new();
+        self.template_files.clear();
+
+        // Template overrides in the source directory are loaded on demand.
+        env.set_loader(override_loader(
+            self.src_dirs().map(Path::to_path_buf).collect(),
+            self.template_files.clone(),
+        ));
+
         // Register embedded templates, which are available in release mode.
         #[cfg(not(debug_assertions))]
         for (name, source) in TEMPLATES.contents() {
-            ctx.tmpls
-                .add_template(name, source)
-                .expect("error in embedded template");
+            env.add_template(name, source)?;
         }
 
         // In debug mode only, load templates directly from the filesystem.
         #[cfg(debug_assertions)]
-        ctx.reload_templates();
+        for (name, source) in TEMPLATES.read_all() {
+            self.template_files
+                .record(&Path::new(TEMPLATES.dir).join(name));
+            env.add_template_owned(name, source.expect("error reading template"))?;
+        }
 
-        ctx
+        for (name, source) in self.custom_templates.contents() {
+            env.add_template_owned(name.to_string(), source.to_string())?;
+        }
+
+        // The `asset` function gives a page's URL for a linked asset. Built
+        // pages get hashed names; the preview server serves assets as-is.
+        let names = Arc::new(if self.livereload {
+            BTreeMap::new()
+        } else {
+            hashed_assets(&env)
+        });
+        env.add_function(
+            "asset",
+            move |state: &minijinja::State, name: &str| -> Result<_, minijinja::Error> {
+                if !LINKED_ASSETS.contains(&name) {
+                    return Err(minijinja::Error::new(
+                        minijinja::ErrorKind::InvalidOperation,
+                        format!("unknown asset {name}"),
+                    ));
+                }
+                let url = match names.get(name) {
+                    Some(hashed) => {
+                        let page = state.lookup("url");
+                        relative_url(page.as_ref().and_then(|u| u.as_str()).unwrap_or(""), hashed)
+                    }
+                    None => format!("/{name}"),
+                };
+                Ok(minijinja::Value::from_safe_string(url))
+            },
+        );
+
+        for setup in &self.template_setups {
+            setup(&mut env);
+        }
+
+        *self.tmpls.write().unwrap() = Arc::new(env);
+        Ok(())
     }
 
-    /// Re-read all templates from the filesystem.
-    pub fn reload_templates(&mut self) {
-        self.tmpls.clear_templates();
-        for (name, source) in TEMPLATES.read_all() {
-            self.tmpls
-                .add_template_owned(name, source.expect("error reading template"))
-                .expect("error in loaded template");
+    /// The current template environment.
+    pub(crate) fn templates(&self) -> Arc<minijinja::Environment<'static>> {
+        self.tmpls.read().unwrap().clone()
+    }
+
+    /// Have any of the template files we've loaded changed on disk?
+    pub fn templates_changed(&self) -> bool {
+        self.template_files.changed()
+    }
+
+    /// Re-read templates from the filesystem if any of them have changed since
+    /// we read them. This is cheap when nothing has changed. If a template is
+    /// broken, the old templates stay in use.
+    pub fn reload_templates(&self) -> Result<(), minijinja::Error> {
+        if self.templates_changed() {
+            self.load_templates()?;
         }
+        Ok(())
     }
 
     /// Render the HTML page for a given Markdown note.
     pub fn render_note<W: io::Write>(&self, src_path: &Path, dest: &mut W) -> Result<()> {
-        // Render the note body.
+        let doc = self.read_note(src_path)?;
+        let env = self.templates();
+        let tmpl = self.note_template(&env, src_path, &doc.front_matter)?;
+        let context = self.note_context_for_doc(src_path, doc, &self.related_pages());
+        tmpl.render_to_write(context, dest)?;
+        Ok(())
+    }
+
+    /// Render the HTML page for a given Markdown note to a string.
+    pub fn render_note_to_string(&self, src_path: &Path) -> Result<String> {
+        let mut buf = vec![];
+        self.render_note(src_path, &mut buf)?;
+        Ok(String::from_utf8(buf)?)
+    }
+
+    /// Render just the HTML body of a note, without the surrounding template.
+    pub fn render_body(&self, src_path: &Path) -> Result<String> {
+        Ok(self.read_note(src_path)?.body)
+    }
+
+    /// Get the name of the template to use for a note: the one named in its
+    /// front matter, if any; otherwise the `_note.html` in the nearest
+    /// enclosing directory that has one; or else `self.note_template`.
+    fn note_template_name(&self, src_path: &Path, front_matter: &markdown::FrontMatter) -> String {
+        if let Some(name) = &front_matter.template {
+            return name.clone();
+        }
+        let (_, rel_path) = self.split_src_path(src_path);
+        for dir in rel_path.ancestors().skip(1) {
+            let name = dir.join(NOTE_OVERRIDE);
+            if self.find_src_path(&name).is_file() {
+                return name.to_string_lossy().into_owned();
+            }
+        }
+        self.note_template.clone()
+    }
+
+    /// Load the template to use for a note from `env`.
+    fn note_template<'e>(
+        &self,
+        env: &'e minijinja::Environment<'static>,
+        src_path: &Path,
+        front_matter: &markdown::FrontMatter,
+    ) -> Result<minijinja::Template<'e, 'e>> {
+        let name = self.note_template_name(src_path, front_matter);
+        env.get_template(&name)
+            .with_context(|| format!("could not load template {name} for {}", src_path.display()))
+    }
+
+    /// Build the template context for rendering a given Markdown note.
+    pub fn note_context(&self, src_path: &Path) -> Result<minijinja::Value> {
         let source = fs::read_to_string(src_path)?;
-        let (body, toc_entries) = markdown::render(&source);
+        self.note_context_for_source(src_path, source, &self.related_pages())
+    }
 
-        // Extract the top-level title, if any.
-        let title = if let Some(first_head) = toc_entries.first()
-            && first_head.level as u8 == 1
-        {
-            Some(first_head.title.clone())
-        } else {
-            None
-        };
+    /// Build the template context for a note at `src_path` whose contents are
+    /// `source`. Related pages are drawn from `pages`.
+    fn note_context_for_source(
+        &self,
+        src_path: &Path,
+        source: String,
+        pages: &[related::Page],
+    ) -> Result<minijinja::Value> {
+        let doc = self.render_source(src_path, source)?;
+        Ok(self.note_context_for_doc(src_path, doc, pages))
+    }
+
+    /// Build the template context for a note at `src_path` that has already
+    /// been rendered. Related pages are drawn from `pages`.
+    fn note_context_for_doc(
+        &self,
+        src_path: &Path,
+        doc: markdown::Rendered,
+        pages: &[related::Page],
+    ) -> minijinja::Value {
+        let title = doc.title;
 
         // Get the table of contents ready for rendering.
-        let toc: Vec<_> = toc_entries
-            .into_iter()
-            .map(|e| {
-                minijinja::context! {
-                    level => e.level as u8,
-                    id => e.id,
-                    title => e.title,
-                }
-            })
-            .collect();
+        let toc = toc_context(markdown::nest_toc(doc.toc));
 
         // Get git commit info.
-        let commit = git::last_commit(&self.src_dir, src_path).map(|c| {
-            let info = c.info();
+        let commit = self.last_commit(src_path);
+        let info = commit.as_ref().and_then(|c| c.info().ok());
+        let git = info.as_ref().map(|info| {
             minijinja::context! {
                 hash => info.hash,
                 short_hash => info.hash[..7],
@@ -94,11 +417,17 @@ impl Context {
             }
         });
 
+        // Site-wide attributes for the `<html>` tag, overridden by the note.
+        let mut html_attrs = self.config.html_attrs.clone();
+        html_attrs.extend(doc.front_matter.html_attrs.clone());
+
+        // Site-wide template values, likewise.
+        let mut site = self.config.site.clone();
+        site.extend(doc.front_matter.values.clone());
+
         // Filename info.
-        let rel_path = src_path
-            .strip_prefix(&self.src_dir)
-            .expect("note path must be within source directory")
-            .to_string_lossy();
+        let (_, rel_path) = self.split_src_path(src_path);
+        let rel_path = rel_path.to_string_lossy();
         let file_name = src_path.file_name().expect("no filename").to_string_lossy();
         let edit_link = self
             .config
@@ -106,32 +435,298 @@ impl Context {
             .as_ref()
             .map(|p| format!("{p}{rel_path}"));
 
-        // Render the template.
-        let tmpl = self.tmpls.get_template("note.html")?;
-        tmpl.render_to_write(
+        // Links to the site's feeds, if it has any.
+        let feeds: Vec<_> = match &self.site.base_url {
+            Some(base_url) => self
+                .config
+                .feeds()
+                .iter()
+                .map(|format| FeedLink {
+                    url: feed::absolute_url(base_url, self.site.feed_path(*format)),
+                    mime_type: format.mime_type(),
+                })
+                .collect(),
+            None => vec![],
+        };
+
+        // The 404 page is served at any depth, so it can't link to the
+        // stylesheet with a relative URL.
+        let not_found = self.not_found_note().is_some_and(|p| p == src_path);
+        let inline_assets = self.inline_assets || not_found;
+
+        // Other notes that share tags with this one. The 404 page stands in
+        // for missing pages, so it has no canonical URL of its own.
+        let url = self.note_url(src_path);
+        let canonical_url = self.site.absolute_url(&url).filter(|_| !not_found);
+        let breadcrumbs = self.breadcrumbs(src_path, &url);
+        let related: Vec<_> = related::related(
+            &url,
+            &doc.front_matter.tags,
+            pages,
+            self.config.related_count(),
+            self.config.related_min_shared(),
+        )
+        .into_iter()
+        .map(|page| {
             minijinja::context! {
-                title => title,
-                body => body,
-                toc => toc,
-                livereload => self.livereload,
-                git => commit,
-                path => rel_path,
-                name => file_name,
-                edit_link => edit_link,
-            },
-            dest,
-        )?;
+                title => page.title,
+                href => relative_url(&url, &page.url),
+            }
+        })
+        .collect();
 
-        Ok(())
+        minijinja::context! {
+            title => title,
+            body => doc.body,
+            excerpt => doc.excerpt,
+            toc => toc,
+            livereload => self.livereload,
+            git => git,
+            last_modified => info.as_ref().map(|i| i.date),
+            author => info.as_ref().map(|i| i.name),
+            revision => info.as_ref().map(|i| &i.hash[..7]),
+            path => rel_path,
+            name => file_name,
+            edit_link => edit_link,
+            robots => doc.front_matter.robots(),
+            html_attrs => html_attrs,
+            site => site,
+            feed_url => feeds.first().map(|f| f.url.clone()),
+            feeds => feeds,
+            tags => doc.front_matter.tags,
+            related => related,
+            url => url,
+            canonical_url => canonical_url,
+            breadcrumbs => breadcrumbs,
+            word_count => doc.word_count,
+            reading_time_minutes => markdown::reading_minutes(doc.word_count),
+            math => doc.math,
+            inline_assets => inline_assets,
+            // Front matter keys are variables too, unless we already provide
+            // one with the same name.
+            ..minijinja::Value::from_serialize(&doc.front_matter.values),
+        }
+    }
+
+    /// Collect the metadata for finding related pages from every published
+    /// note in the site. This is empty if related pages are turned off.
+    ///
+    /// Notes that can't be read are left out; they'll be reported when we try
+    /// to render them.
+    fn related_index(&self) -> Vec<related::Page> {
+        if self.config.related_count() == 0 {
+            return vec![];
+        }
+        let mut pages = vec![];
+        for note in self.notes() {
+            let Ok(note) = note else {
+                continue;
+            };
+            if !self.is_published(&note.front_matter) {
+                continue;
+            }
+            let title = note.title.unwrap_or_else(|| {
+                let stem = note.src_path.file_stem().expect("no filename");
+                stem.to_string_lossy().into_owned()
+            });
+            pages.push(related::Page {
+                url: self.note_url(&note.src_path),
+                title,
+                date: note.front_matter.date,
+                tags: note.front_matter.tags,
+            });
+        }
+        pages
+    }
+
+    /// The related-page index for rendering single notes, which is built once
+    /// and reused until `forget_notes` is called. Builds make their own.
+    fn related_pages(&self) -> Arc<Vec<related::Page>> {
+        if let Some(pages) = &*self.related_pages.read().unwrap() {
+            return pages.clone();
+        }
+        let pages = Arc::new(self.related_index());
+        *self.related_pages.write().unwrap() = Some(pages.clone());
+        pages
+    }
+
+    /// Drop what we've cached about the site's notes, like the related-page
+    /// index, after they change.
+    pub fn forget_notes(&self) {
+        *self.related_pages.write().unwrap() = None;
+    }
+
+    /// Should a note with the given front matter be published in a build?
+    /// Drafts are held back unless `self.drafts` is set, and notes dated in
+    /// the future are held back unless `self.future` is set.
+    pub fn is_published(&self, front_matter: &markdown::FrontMatter) -> bool {
+        (self.drafts || !front_matter.draft)
+            && (self.future || !front_matter.is_scheduled(jiff::Zoned::now().date()))
+    }
+
+    /// Read and render the Markdown for a note.
+    pub fn read_note(&self, src_path: &Path) -> Result<markdown::Rendered> {
+        let source = fs::read_to_string(src_path)?;
+        self.render_source(src_path, source)
+    }
+
+    /// Render the contents of a note. HTML notes are used as they are.
+    fn render_source(&self, src_path: &Path, source: String) -> Result<markdown::Rendered> {
+        if is_html(src_path) {
+            return Ok(markdown::Rendered::from_html(source));
+        }
+        markdown::render(&source, &self.code_handlers, &self.render_options(src_path))
+    }
+
+    /// Render a Markdown note to normalized Markdown, with heading IDs and
+    /// links like the ones in its page, for other tools to consume.
+    pub fn render_note_markdown(&self, src_path: &Path) -> Result<String> {
+        if is_html(src_path) {
+            bail!("{} is not a Markdown note", src_path.display());
+        }
+        let source = fs::read_to_string(src_path)?;
+        markdown::render_to_markdown(&source, &self.render_options(src_path))
+    }
+
+    /// The options for rendering the note at `src_path`.
+    fn render_options(&self, src_path: &Path) -> markdown::RenderOptions<'_> {
+        markdown::RenderOptions {
+            excerpt_separator: self.config.excerpt_separator(),
+            links: self.link_options(src_path),
+            slugs: self.config.slug_options(),
+            id_prefix: self.config.id_prefix(),
+            prefix_explicit_ids: self.config.prefix_explicit_ids(),
+            sanitize_html: self.config.sanitize_html(),
+            toc_levels: self.config.toc_levels(),
+        }
+    }
+
+    /// Is this file a note? Markdown files always are. With `html_notes`,
+    /// HTML files are too, unless there's a Markdown note with the same name,
+    /// which gets the page instead.
+    fn is_note(&self, path: &Path) -> bool {
+        match path.extension() {
+            Some(ext) if ext == "md" => true,
+            Some(ext) if ext == "html" => {
+                self.config.html_notes() && !path.with_extension("md").is_file()
+            }
+            _ => false,
+        }
+    }
+
+    /// How links should look in the page for a note.
+    fn link_options(&self, src_path: &Path) -> markdown::LinkOptions<'_> {
+        let style = if self.config.pretty_urls() {
+            markdown::LinkStyle::Pretty {
+                own_dir: self.has_own_dir(src_path),
+            }
+        } else {
+            markdown::LinkStyle::Html
+        };
+        markdown::LinkOptions {
+            style,
+            new_tab: self.config.new_tab_links(),
+            site_host: self.site.base_url.as_deref().and_then(markdown::url_host),
+            wikilinks: self.config.wikilinks(),
+        }
+    }
+
+    /// With pretty URLs, does this note's page go in a directory of its own?
+    /// Index notes stay where they are, as does the 404 page, which static
+    /// hosts expect to find at `404.html`.
+    fn has_own_dir(&self, src_path: &Path) -> bool {
+        self.config.pretty_urls()
+            && src_path.file_stem().is_some_and(|s| s != "index")
+            && self.split_src_path(src_path).1 != Path::new(NOT_FOUND_NOTE)
+    }
+
+    /// Get the last git commit that touched a file in the source directory.
+    ///
+    /// This is `None` when git info is disabled or when git fails (e.g., the
+    /// source directory is not in a repository).
+    pub fn last_commit(&self, src_path: &Path) -> Option<git::CommitData> {
+        if self.git_info {
+            let (src_dir, _) = self.split_src_path(src_path);
+            git::last_commit(src_dir, src_path).ok().flatten()
+        } else {
+            None
+        }
+    }
+
+    /// Get the note for the site's 404 page, if it has one. It gets rendered
+    /// to `404.html` like any other note, which many static hosts use for
+    /// missing pages.
+    pub fn not_found_note(&self) -> Option<PathBuf> {
+        let src_path = self.find_src_path(Path::new(NOT_FOUND_NOTE));
+        src_path.is_file().then_some(src_path)
+    }
+
+    /// Get the URL path for a note's rendered page, relative to the site root.
+    /// With pretty URLs, this is the page's directory, like `foo/`.
+    pub fn note_url(&self, src_path: &Path) -> String {
+        let url = url_path(&self.note_dest_path(src_path, Path::new("")));
+        if self.config.pretty_urls() {
+            url.strip_suffix("index.html").unwrap_or(&url).into()
+        } else {
+            url
+        }
     }
 
-    /// Render a single Markdown note file to an HTML file.
+    /// Render a single Markdown note file to an HTML file, unless it is not
+    /// yet published. If we wrote the page, return the paths of the files we
+    /// wrote along with hashes of their contents, and the page's relative
+    /// links.
+    ///
+    /// If TOC fragments are enabled, this also writes the note's table of
+    /// contents on its own to a `.toc.html` file next to the page.
     ///
     /// Both `src_path` and `dest_path` are complete paths to files, not
     /// relative to our source and destination directory.
-    fn render_note_to_file(&self, src_path: &Path, dest_path: &Path) -> Result<()> {
-        let mut out_file = fs::File::create(dest_path)?;
-        self.render_note(src_path, &mut out_file)
+    fn render_note_to_file(
+        &self,
+        src_path: &Path,
+        dest_path: &Path,
+        pages: &[related::Page],
+    ) -> Result<Option<NoteOutput>> {
+        // Never overwrite the note we're rendering.
+        if same_file(src_path, dest_path) {
+            bail!("refusing to render {} over itself", src_path.display());
+        }
+
+        let mut doc = self.read_note(src_path)?;
+        if !self.is_published(&doc.front_matter) {
+            return Ok(None);
+        }
+        let env = self.templates();
+        let mut outputs = vec![];
+        if self.has_own_dir(src_path) {
+            fs::create_dir_all(dest_path.parent().expect("note page has a directory"))?;
+        }
+        if self.config.toc_fragments() && !doc.toc.is_empty() {
+            let toc = toc_context(markdown::nest_toc(doc.toc.clone()));
+            let tmpl = env.get_template("toc.html")?;
+            let toc_path = dest_path.with_extension("toc.html");
+            let mut out_file = manifest::HashWriter::new(AtomicOutput::create(&toc_path)?);
+            tmpl.render_to_write(minijinja::context! { toc => toc }, &mut out_file)?;
+            outputs.push((toc_path, out_file.hash()));
+            out_file.into_inner().commit()?;
+        }
+
+        let links = std::mem::take(&mut doc.links);
+        let note = self
+            .keeps_notes()
+            .then(|| RenderedNote::new(src_path, &doc));
+        let tmpl = self.note_template(&env, src_path, &doc.front_matter)?;
+        let context = self.note_context_for_doc(src_path, doc, pages);
+        let mut out_file = manifest::HashWriter::new(AtomicOutput::create(dest_path)?);
+        tmpl.render_to_write(context, &mut out_file)?;
+        outputs.push((dest_path.to_path_buf(), out_file.hash()));
+        out_file.into_inner().commit()?;
+        Ok(Some(NoteOutput {
+            files: outputs,
+            links,
+            note,
+        }))
     }
 
     /// Render any resource.
@@ -143,143 +738,1283 @@ impl Context {
                 Ok(())
             }
             Resource::Note(path) => self.render_note(&path, dest),
-            Resource::Directory(path) => {
-                // TODO this is where we'd generate index pages
-                writeln!(dest, "directory: {}", path.display())?;
-                Ok(())
+            Resource::Directory(path) => self.render_directory(&path, dest),
+        }
+    }
+
+    /// Render any resource to a string. This fails for static files that
+    /// aren't valid UTF-8 text.
+    pub fn render_resource_to_string(&self, rsrc: Resource) -> Result<String> {
+        let mut buf = vec![];
+        self.render_resource(rsrc, &mut buf)?;
+        Ok(String::from_utf8(buf)?)
+    }
+
+    /// Render the index page for a directory.
+    ///
+    /// If the directory contains an `index.md` note, we render that note.
+    /// Otherwise, we generate a listing of the notes and subdirectories it
+    /// contains.
+    pub fn render_directory<W: io::Write>(&self, dir_path: &Path, dest: &mut W) -> Result<()> {
+        let (_, rel_path) = self.split_src_path(dir_path);
+        let index_path = self.find_src_path(&rel_path.join("index.md"));
+        if index_path.is_file() {
+            return self.render_note(&index_path, dest);
+        }
+
+        let context = self.directory_context(dir_path)?;
+        let env = self.templates();
+        let tmpl = env.get_template("index.html")?;
+        tmpl.render_to_write(context, dest)?;
+        Ok(())
+    }
+
+    /// Build the template context for a directory listing, which covers the
+    /// directory at the same place in every source directory.
+    fn directory_context(&self, dir_path: &Path) -> Result<minijinja::Value> {
+        let (_, rel_path) = self.split_src_path(dir_path);
+
+        // Find the entries, where ones from later source directories win.
+        let mut dir_entries = BTreeMap::new();
+        for src_dir in self.src_dirs() {
+            let dir = src_dir.join(rel_path);
+            if dir != dir_path && !dir.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(dir)? {
+                let entry = entry?;
+                dir_entries.insert(entry.file_name(), entry);
+            }
+        }
+
+        // Gather the notes and subdirectories, with titles for the notes.
+        let mut entries = vec![];
+        for (file_name, entry) in dir_entries {
+            if self.is_ignored(&file_name) {
+                continue;
+            }
+            let name = file_name.to_string_lossy().into_owned();
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                entries.push((true, name.clone(), format!("{name}/"), None));
+            } else if file_type.is_file() && self.is_note(&entry.path()) {
+                let doc = self.read_note(&entry.path())?;
+                let href = if self.has_own_dir(&entry.path()) {
+                    Path::new(&name).with_extension("").join("")
+                } else {
+                    Path::new(&name).with_extension("html")
+                };
+                let href = href.to_string_lossy().into_owned();
+                entries.push((false, name, href, doc.title));
             }
         }
+
+        // List directories first, and then sort by name.
+        entries.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        let entries: Vec<_> = entries
+            .into_iter()
+            .map(|(is_dir, name, href, title)| {
+                minijinja::context! {
+                    is_dir => is_dir,
+                    name => name,
+                    href => href,
+                    title => title,
+                }
+            })
+            .collect();
+
+        let rel_path = rel_path.to_string_lossy();
+        Ok(minijinja::context! {
+            title => format!("{rel_path}/"),
+            path => rel_path,
+            entries => entries,
+            livereload => self.livereload,
+            html_attrs => self.config.html_attrs,
+            site => self.config.site,
+        })
+    }
+
+    /// Links to the index pages of the directories that contain a note, from
+    /// the top of the site down, for the note's page at `url`. Each is labeled
+    /// with the title of the directory's `index.md`, if it has one, or else the
+    /// directory's name (or "Home" for the top).
+    ///
+    /// A built site only has pages for directories with a published
+    /// `index.md`, so the others get no `href`. The preview server lists every
+    /// directory, so there they all do.
+    fn breadcrumbs(&self, src_path: &Path, url: &str) -> Vec<minijinja::Value> {
+        let (_, rel_path) = self.split_src_path(src_path);
+        let mut dirs: Vec<_> = rel_path.ancestors().skip(1).collect();
+        dirs.reverse();
+        // An index note is its directory's page, so it isn't its own ancestor.
+        if rel_path.file_name() == Some(OsStr::new("index.md")) {
+            dirs.pop();
+        }
+
+        dirs.into_iter()
+            .map(|dir| {
+                let index = self.find_src_path(&dir.join("index.md"));
+                let meta = index
+                    .is_file()
+                    .then(|| self.note_meta(index).ok())
+                    .flatten();
+                let has_page = self.livereload
+                    || meta
+                        .as_ref()
+                        .is_some_and(|m| self.is_published(&m.front_matter));
+                let label = meta
+                    .and_then(|m| m.title)
+                    .unwrap_or_else(|| match dir.file_name() {
+                        Some(name) => name.to_string_lossy().into_owned(),
+                        None => "Home".into(),
+                    });
+                let dir_url = match url_path(dir) {
+                    path if path.is_empty() => path,
+                    path => format!("{path}/"),
+                };
+                let href = match relative_url(url, &dir_url) {
+                    href if href.is_empty() => "./".into(),
+                    href => href,
+                };
+                minijinja::context! { label => label, href => has_page.then_some(href) }
+            })
+            .collect()
+    }
+
+    /// Check that the templates only use variables that we actually provide.
+    ///
+    /// This renders each template with a representative context, treating any
+    /// reference to an undefined variable as an error.
+    pub fn validate_templates(&self) -> Result<()> {
+        let mut env = (*self.templates()).clone();
+        env.set_undefined_behavior(minijinja::UndefinedBehavior::Strict);
+
+        let note_path = self.src_dir.join("sample.md");
+        let note_context = self.note_context_for_source(&note_path, SAMPLE_NOTE.into(), &[])?;
+        env.get_template(&self.note_template)?
+            .render(note_context)
+            .context("invalid note template")?;
+
+        let dir_context = self.directory_context(&self.src_dir)?;
+        env.get_template("index.html")?
+            .render(dir_context)
+            .context("invalid index template")?;
+
+        env.get_template("tag.html")?
+            .render(tags::overview_context(self, &[]))
+            .context("invalid tag template")?;
+
+        Ok(())
     }
 
-    /// Given a path that is within `self.src_dir`, produce a mirrored path that
-    /// is at the same place is within `dest_dir`.
+    /// Given a path that is within one of the source directories, produce a
+    /// mirrored path that is at the same place is within `dest_dir`.
     ///
-    /// Panics if `src` is not within `self.src_dir`.
+    /// Panics if `src` is not within a source directory.
     fn dest_path(&self, src: &Path, dest_dir: &Path) -> PathBuf {
-        let rel_path = src
-            .strip_prefix(&self.src_dir)
-            .expect("path is within root directory");
+        let (_, rel_path) = self.split_src_path(src);
         dest_dir.join(rel_path)
     }
 
     /// Assuming `src` is the path to a Markdown note file, return its HTML
     /// destination path.
     ///
-    /// Panics if `src` is not a note file within `self.src_dir`.
+    /// Panics if `src` is not a note file within a source directory.
     fn note_dest_path(&self, src: &Path, dest_dir: &Path) -> PathBuf {
-        assert!(is_note(src), "must be a note path");
+        assert!(self.is_note(src), "must be a note path");
         let mut mirrored = self.dest_path(src, dest_dir);
-        mirrored.set_extension("html");
+        if self.has_own_dir(src) {
+            mirrored.set_extension("");
+            mirrored.push("index.html");
+        } else {
+            mirrored.set_extension("html");
+        }
         mirrored
     }
 
     /// Given a relative path to a rendered file (i.e., something that would go
     /// in the destination directory), look up the underlying resource for that
-    /// path, if one exists.
+    /// path, if one exists. With `ignore_case`, a path that doesn't exist
+    /// exactly can also match files that differ only in case. Files reached
+    /// through symlinks that lead out of the source directories don't count.
     pub fn resolve_resource(&self, rel_path: &str) -> Option<Resource> {
-        // Ensure that we actually have a safe, relative path fragment, and then
-        // join it under the source directory.
-        let rel_path = sanitize_path(rel_path)?;
-        let src_path = self.src_dir.join(&rel_path);
+        // Ensure that we actually have a safe, relative path fragment.
+        let rel_path = sanitize_path(rel_path, self.show_hidden, self.config.ignore())?;
+        self.resolve_path(&rel_path)
+            .or_else(|| {
+                if self.ignore_case {
+                    self.resolve_path(&self.match_case(&rel_path))
+                } else {
+                    None
+                }
+            })
+            .filter(|rsrc| !self.escapes_src(rsrc.path()))
+    }
+
+    /// Does a path, once symlinks are followed, lead outside all the source
+    /// directories? Broken symlinks lead nowhere, so they count too.
+    fn escapes_src(&self, path: &Path) -> bool {
+        let Ok(real_path) = path.canonicalize() else {
+            return true;
+        };
+        !self
+            .src_dirs()
+            .filter_map(|dir| dir.canonicalize().ok())
+            .any(|dir| real_path.starts_with(dir))
+    }
+
+    /// Look up the resource for a sanitized relative path, with exactly the
+    /// case it has in the source directory.
+    fn resolve_path(&self, rel_path: &Path) -> Option<Resource> {
+        let src_path = self.find_src_path(rel_path);
 
         // If the path exists verbatim within the source directory, then this is
-        // either a static file or a directory.
+        // either a static file, an HTML note, or a directory. An HTML note
+        // with a pretty URL is only available at its directory.
         if src_path.is_file() {
+            if is_html(&src_path) && self.is_note(&src_path) {
+                return (!self.has_own_dir(&src_path)).then_some(Resource::Note(src_path));
+            }
             return Some(Resource::Static(src_path));
         } else if src_path.is_dir() {
             return Some(Resource::Directory(src_path));
         }
 
-        // If this is an HTML file with a corresponding note, then we'll render it.
+        // If this is an HTML file with a corresponding note, then we'll render
+        // it. With pretty URLs, only index notes have pages like this.
         if let Some(ext) = rel_path.extension()
             && ext == "html"
+            && (!self.config.pretty_urls() || rel_path.file_stem().is_some_and(|s| s == "index"))
         {
-            let mut src_path = src_path;
-            src_path.set_extension("md");
+            let src_path = self.find_src_path(&rel_path.with_extension("md"));
             if src_path.is_file() {
                 return Some(Resource::Note(src_path));
             }
         }
 
+        // With pretty URLs, `foo/` and `foo/index.html` are the page for `foo.md`.
+        if self.config.pretty_urls() {
+            let page_dir = if rel_path.ends_with("index.html") {
+                rel_path.parent().unwrap_or(Path::new(""))
+            } else {
+                rel_path
+            };
+            if page_dir.file_name().is_some() {
+                for ext in [".md", ".html"] {
+                    let mut note_path = page_dir.as_os_str().to_os_string();
+                    note_path.push(ext);
+                    let note_path = self.find_src_path(Path::new(&note_path));
+                    if note_path.is_file()
+                        && self.is_note(&note_path)
+                        && self.has_own_dir(&note_path)
+                    {
+                        return Some(Resource::Note(note_path));
+                    }
+                }
+            }
+        }
+
         // Not found.
         None
     }
 
-    /// List all the resources in the source directory.
-    pub fn read_resources(&self) -> impl Iterator<Item = Resource> {
-        WalkDir::new(&self.src_dir)
+    /// Change the case of each component of a sanitized relative path to match
+    /// a file in the source directory, where there is one. Components with no
+    /// match are left alone.
+    fn match_case(&self, rel_path: &Path) -> PathBuf {
+        let mut matched = PathBuf::new();
+        for name in rel_path {
+            let name = self
+                .match_name_case(&matched, name)
+                .unwrap_or_else(|| name.to_os_string());
+            matched.push(name);
+        }
+        matched
+    }
+
+    /// Find the name of a file in `dir`, relative to the source directory, that
+    /// matches `name` ignoring case. Besides the file's own name, this can be
+    /// its stem with the extension from `name` in lowercase, so `FOO.HTML` can
+    /// find the note `foo.md`. Exact matches win, then matching names, then
+    /// matching stems.
+    fn match_name_case(&self, dir: &Path, name: &OsStr) -> Option<OsString> {
+        if self.find_src_path(&dir.join(name)).exists() {
+            return Some(name.to_os_string());
+        }
+        let wanted = name.to_str()?.to_lowercase();
+        let mut entries: Vec<_> = self
+            .src_dirs()
+            .filter_map(|src_dir| fs::read_dir(src_dir.join(dir)).ok())
+            .flatten()
+            .filter_map(|e| Some(e.ok()?.file_name()))
+            .filter(|n| {
+                (self.show_hidden || !ignore_filename(n)) && !matches_any(self.config.ignore(), n)
+            })
+            .collect();
+        entries.sort();
+        entries.dedup();
+
+        let same = |n: &OsStr| n.to_str().is_some_and(|n| n.to_lowercase() == wanted);
+        if let Some(entry) = entries.iter().find(|n| same(n)) {
+            return Some(entry.clone());
+        }
+        let ext = Path::new(name).extension();
+        entries.into_iter().find_map(|entry| {
+            let mut candidate = Path::new(&entry).file_stem()?.to_os_string();
+            if let Some(ext) = ext {
+                candidate.push(".");
+                candidate.push(ext.to_ascii_lowercase());
+            }
+            same(&candidate).then_some(candidate)
+        })
+    }
+
+    /// Should we skip a file or directory with this name? This includes hidden
+    /// names and ones that match the configured ignore patterns.
+    fn is_ignored(&self, name: &OsStr) -> bool {
+        ignore_filename(name) || matches_any(self.config.ignore(), name)
+    }
+
+    /// List all the resources in the source directories. Where several source
+    /// directories have something at the same relative path, only the one from
+    /// the directory with the highest precedence is listed.
+    ///
+    /// Errors from walking the directories (e.g., unreadable subdirectories)
+    /// come first; the walk continues past them. Directories come before their
+    /// contents.
+    pub fn read_resources(&self) -> impl Iterator<Item = walkdir::Result<Resource>> {
+        let mut errors = vec![];
+        let mut resources = BTreeMap::new();
+        for src_dir in self.src_dirs() {
+            for rsrc in self.walk_src_dir(src_dir) {
+                match rsrc {
+                    Ok(rsrc) => {
+                        let (_, rel_path) = self.split_src_path(rsrc.path());
+                        resources.insert(rel_path.to_path_buf(), rsrc);
+                    }
+                    Err(e) => errors.push(Err(e)),
+                }
+            }
+        }
+        errors.into_iter().chain(resources.into_values().map(Ok))
+    }
+
+    /// List the site's notes with their titles and front matter, for tools
+    /// that process notes without rendering them. This includes drafts and
+    /// unpublished notes; check `is_published` to skip them.
+    pub fn notes(&self) -> impl Iterator<Item = Result<NoteMeta>> + '_ {
+        self.read_resources().filter_map(|rsrc| match rsrc {
+            Ok(Resource::Note(src_path)) => Some(self.note_meta(src_path)),
+            Ok(_) => None,
+            Err(e) => Some(Err(e.into())),
+        })
+    }
+
+    /// Read a note's metadata. HTML notes have no title or front matter.
+    fn note_meta(&self, src_path: PathBuf) -> Result<NoteMeta> {
+        let (front_matter, title) = if is_html(&src_path) {
+            Default::default()
+        } else {
+            let source = fs::read_to_string(&src_path)
+                .with_context(|| format!("could not read {}", src_path.display()))?;
+            markdown::read_metadata(&source, &self.render_options(&src_path))
+                .with_context(|| format!("in {}", src_path.display()))?
+        };
+        Ok(NoteMeta {
+            dest_path: self.note_dest_path(&src_path, Path::new("")),
+            src_path,
+            title,
+            front_matter,
+        })
+    }
+
+    /// List all the resources in one source directory.
+    ///
+    /// Symlinks count as the files and directories they point to, as long as
+    /// those are in a source directory; others are skipped with a warning.
+    /// Symlinks that form a loop are reported as errors.
+    fn walk_src_dir<'a>(
+        &'a self,
+        src_dir: &Path,
+    ) -> impl Iterator<Item = walkdir::Result<Resource>> + 'a {
+        WalkDir::new(src_dir)
+            .follow_links(true)
             .into_iter()
-            .filter_entry(|e| !ignore_filename(e.file_name()))
+            .filter_entry(|e| {
+                if e.depth() == 0 {
+                    return true;
+                }
+                if self.is_ignored(e.file_name()) {
+                    return false;
+                }
+                if e.path_is_symlink() && self.escapes_src(e.path()) {
+                    log::warn!(
+                        "skipping {}: symlink leads outside the source directory",
+                        e.path().display()
+                    );
+                    return false;
+                }
+                true
+            })
             .filter_map(|entry| match entry {
                 Ok(entry) => {
                     if entry.file_type().is_dir() {
-                        Some(Resource::Directory(entry.path().into()))
+                        Some(Ok(Resource::Directory(entry.path().into())))
                     } else if entry.file_type().is_file() {
-                        if is_note(entry.path()) {
-                            Some(Resource::Note(entry.path().into()))
+                        if self.is_note(entry.path()) {
+                            Some(Ok(Resource::Note(entry.path().into())))
                         } else {
-                            Some(Resource::Static(entry.path().into()))
+                            Some(Ok(Resource::Static(entry.path().into())))
                         }
                     } else {
                         None
                     }
                 }
-                Err(e) => {
-                    eprintln!("directory walk error: {}", e);
-                    None
-                }
+                Err(e) => Some(Err(e)),
             })
     }
 
-    /// Render all resources in a site to a destination directory.
-    pub fn render_site(&self, threads: Option<NonZero<usize>>, dest_dir: &Path) -> Result<()> {
-        parallel::scope_with_threads(threads, |pool| {
-            remove_dir_force(dest_dir)?;
+    /// Remove a previously built site's destination directory, if it exists.
+    pub fn clean(&self, dest_dir: &Path) -> io::Result<()> {
+        remove_dir_force(dest_dir)
+    }
 
-            for rsrc in self.read_resources() {
-                match rsrc {
-                    Resource::Directory(src_path) => {
-                        fs::create_dir_all(self.dest_path(&src_path, dest_dir))?;
-                    }
-                    Resource::Static(src_path) => {
-                        hard_link_or_copy(&src_path, &self.dest_path(&src_path, dest_dir))?;
-                    }
-                    Resource::Note(src_path) => {
-                        pool.spawn(move || {
-                            let dest_path = self.note_dest_path(&src_path, dest_dir);
-                            match self.render_note_to_file(&src_path, &dest_path) {
-                                Ok(_) => (),
-                                Err(e) => {
-                                    eprintln!("error rendering note {}: {}", src_path.display(), e)
-                                }
-                            }
-                        });
-                    }
-                }
+    /// Make sure that building into `dest_dir`, which starts by cleaning it,
+    /// won't delete the source.
+    fn check_dest(&self, dest_dir: &Path) -> Result<()> {
+        for src_dir in self.src_dirs() {
+            if let (Ok(src), Ok(dest)) = (src_dir.canonicalize(), dest_dir.canonicalize())
+                && src.starts_with(&dest)
+            {
+                bail!(
+                    "destination directory {} contains the source directory {}",
+                    dest_dir.display(),
+                    src_dir.display()
+                );
             }
-
-            Ok(())
-        })
+        }
+        Ok(())
     }
-}
 
-#[derive(Debug)]
-pub enum Resource {
-    Static(PathBuf),
-    Note(PathBuf),
-    Directory(PathBuf),
-}
+    /// List what `render_site` would do to build the site into `dest_dir`,
+    /// without changing anything on disk.
+    ///
+    /// The plan covers the directories, static files, and note pages that make
+    /// up the site, plus its feeds, sitemap, search index, and tag pages. It
+    /// leaves out extras like TOC fragments and gzipped copies. Notes are read
+    /// to see whether they are published; ones that can't be read are planned
+    /// anyway, since a build would try (and fail) to render them. Unreadable
+    /// parts of the source directory are left out, or are errors in strict
+    /// mode.
+    pub fn plan_site(&self, dest_dir: &Path) -> Result<Vec<PlannedAction>> {
+        self.check_dest(dest_dir)?;
+        let mut plan = vec![];
+        if dest_dir.exists() && !self.keep_dest {
+            plan.push(PlannedAction::Remove(dest_dir.to_path_buf()));
+        }
+        for rsrc in self.read_resources() {
+            match rsrc {
+                Ok(Resource::Directory(src_path)) => {
+                    plan.push(PlannedAction::CreateDir(
+                        self.dest_path(&src_path, dest_dir),
+                    ));
+                }
+                Ok(Resource::Static(src_path)) => {
+                    let to = self.dest_path(&src_path, dest_dir);
+                    plan.push(PlannedAction::Copy { from: src_path, to });
+                }
+                Ok(Resource::Note(src_path)) => {
+                    let published = match self.read_note(&src_path) {
+                        Ok(doc) => self.is_published(&doc.front_matter),
+                        Err(_) => true,
+                    };
+                    if published {
+                        let to = self.note_dest_path(&src_path, dest_dir);
+                        plan.push(PlannedAction::Render { from: src_path, to });
+                    }
+                }
+                Err(e) if self.strict => return Err(e.into()),
+                Err(_) => (),
+            }
+        }
+        if self.site.base_url.is_some() {
+            for &format in self.config.feeds() {
+                plan.push(PlannedAction::Write(
+                    dest_dir.join(self.site.feed_path(format)),
+                ));
+            }
+            plan.push(PlannedAction::Write(dest_dir.join(&self.site.sitemap_path)));
+        }
+        if self.config.tag_pages() {
+            plan.push(PlannedAction::Write(
+                dest_dir.join(tags::TAGS_DIR).join("index.html"),
+            ));
+            for tag in tags::collect_tags(self) {
+                plan.push(PlannedAction::Write(
+                    dest_dir.join(tag.url()).join("index.html"),
+                ));
+            }
+        }
+        if let Some(path) = self.config.search_index() {
+            plan.push(PlannedAction::Write(dest_dir.join(path)));
+        }
+        for hashed in hashed_assets(&self.templates()).values() {
+            plan.push(PlannedAction::Write(dest_dir.join(hashed)));
+        }
+        Ok(plan)
+    }
+
+    /// Render all resources in a site to a destination directory.
+    ///
+    /// Small sites are rendered serially on the current thread; once the
+    /// number of notes reaches the configured threshold, we render them in
+    /// parallel.
+    ///
+    /// Static files are hard-linked into the site when possible, which is fast
+    /// but means the site shares them with the source directory. Set
+    /// `copy_files` to copy them instead.
+    ///
+    /// Parts of the source directory that can't be read are skipped and
+    /// recorded in the returned report, as are notes that fail to render. In
+    /// strict mode, the first of these problems aborts the build instead.
+    ///
+    /// Parallel rendering uses rayon if the `rayon` feature is enabled, and
+    /// our own thread pool otherwise.
+    pub fn render_site(
+        &self,
+        threads: Option<NonZero<usize>>,
+        dest_dir: &Path,
+    ) -> Result<BuildReport> {
+        self.render_site_with(parallel::Backend::default(), threads, dest_dir)
+    }
+
+    /// Like `render_site`, with a specific backend for parallel rendering.
+    fn render_site_with(
+        &self,
+        backend: parallel::Backend,
+        threads: Option<NonZero<usize>>,
+        dest_dir: &Path,
+    ) -> Result<BuildReport> {
+        self.check_dest(dest_dir)?;
+        if !self.keep_dest {
+            self.clean(dest_dir)?;
+        }
+        let mut report = BuildReport::default();
+
+        // Mirror the directory structure and static files, and collect the
+        // notes to render.
+        let entries = Mutex::new(vec![]);
+        let mut notes = vec![];
+        for rsrc in self.read_resources() {
+            match rsrc {
+                Ok(Resource::Directory(src_path)) => {
+                    fs::create_dir_all(self.dest_path(&src_path, dest_dir))?;
+                }
+                Ok(Resource::Static(src_path)) => {
+                    let dest_path = self.dest_path(&src_path, dest_dir);
+                    // Stylesheets that aren't UTF-8 are copied as they are.
+                    let css = if self.minify && src_path.extension().is_some_and(|e| e == "css") {
+                        String::from_utf8(fs::read(&src_path)?).ok()
+                    } else {
+                        None
+                    };
+                    let hash = if let Some(css) = css {
+                        let css = css::minify(&css);
+                        replace_atomically(&dest_path, |tmp| fs::write(tmp, &css))?;
+                        manifest::hash_bytes(css.as_bytes())
+                    } else {
+                        hard_link_or_copy(&src_path, &dest_path, self.copy_files)?;
+                        if self.manifest {
+                            manifest::hash_file(&dest_path)?
+                        } else {
+                            0
+                        }
+                    };
+                    if self.manifest {
+                        entries.lock().unwrap().push(self.manifest_entry(
+                            &src_path,
+                            EntryKind::Static,
+                            dest_dir,
+                            vec![(dest_path, hash)],
+                        ));
+                    }
+                    report.files += 1;
+                }
+                Ok(Resource::Note(src_path)) => notes.push(src_path),
+                Err(e) if self.strict => return Err(e.into()),
+                Err(e) => {
+                    let path = e.path().unwrap_or(&self.src_dir).to_path_buf();
+                    report.errors.push((path, e.into()));
+                }
+            }
+        }
+
+        // With pretty URLs, `foo.md` and `foo/index.md` both want the page at
+        // `foo/index.html`. The index note keeps it.
+        if self.config.pretty_urls() {
+            let index_pages: BTreeMap<_, _> = notes
+                .iter()
+                .filter(|src_path| !self.has_own_dir(src_path))
+                .map(|src_path| (self.note_dest_path(src_path, dest_dir), src_path.clone()))
+                .collect();
+            let mut kept = vec![];
+            for src_path in notes {
+                let dest_path = self.note_dest_path(&src_path, dest_dir);
+                match index_pages.get(&dest_path) {
+                    Some(index) if *index != src_path => {
+                        let e = anyhow!("its page is also the page for {}", index.display());
+                        if self.strict {
+                            return Err(
+                                e.context(format!("could not render {}", src_path.display()))
+                            );
+                        }
+                        report.errors.push((src_path, e));
+                    }
+                    _ => kept.push(src_path),
+                }
+            }
+            notes = kept;
+        }
+
+        // Gather tags from all the notes first so each can list related pages.
+        let pages = self.related_index();
+
+        let rendered = AtomicUsize::new(0);
+        let failures = Mutex::new(vec![]);
+        let links = Mutex::new(vec![]);
+        let rendered_notes = Mutex::new(vec![]);
+        let render = |src_path: &Path| {
+            // In strict mode, stop rendering after the first failure.
+            if self.strict && !failures.lock().unwrap().is_empty() {
+                return;
+            }
+            let dest_path = self.note_dest_path(src_path, dest_dir);
+            match self.render_note_to_file(src_path, &dest_path, &pages) {
+                Ok(Some(output)) => {
+                    rendered.fetch_add(1, Ordering::Relaxed);
+                    if self.check_links {
+                        let page_links = (src_path.to_path_buf(), dest_path, output.links);
+                        links.lock().unwrap().push(page_links);
+                    }
+                    if self.manifest {
+                        let entry =
+                            self.manifest_entry(src_path, EntryKind::Note, dest_dir, output.files);
+                        entries.lock().unwrap().push(entry);
+                    }
+                    if let Some(note) = output.note {
+                        rendered_notes.lock().unwrap().push(note);
+                    }
+                }
+                Ok(None) => (),
+                Err(e) => failures.lock().unwrap().push((src_path.to_path_buf(), e)),
+            }
+        };
+        if self.is_parallel(notes.len()) {
+            parallel::for_each_with_threads(backend, threads, &notes, |src_path| render(src_path));
+        } else {
+            for src_path in &notes {
+                render(src_path);
+            }
+        }
+        report.notes = rendered.into_inner();
+        report.failures = failures.into_inner().unwrap();
+        report.manifest.entries = entries.into_inner().unwrap();
+        report
+            .manifest
+            .entries
+            .sort_by(|a, b| a.source.cmp(&b.source));
+        // Report the failure that stopped the build, which came first.
+        if self.strict
+            && let Some((path, e)) = report.failures.drain(..).next()
+        {
+            return Err(e.context(format!("could not render {}", path.display())));
+        }
+
+        // Guard against deploying an empty site.
+        let min_pages = self.config.min_pages();
+        if report.notes < min_pages {
+            bail!(
+                "rendered {} pages, fewer than the minimum of {min_pages}",
+                report.notes
+            );
+        }
+
+        // Generate the feed and sitemap, which need absolute URLs.
+        let mut generated = vec![];
+        let notes = rendered_notes.into_inner().unwrap();
+        if let Some(base_url) = &self.site.base_url {
+            feed::render_feed(self, base_url, &notes, dest_dir)?;
+            sitemap::render_sitemap(self, base_url, dest_dir)?;
+            for &format in self.config.feeds() {
+                generated.push(dest_dir.join(self.site.feed_path(format)));
+            }
+            generated.push(dest_dir.join(&self.site.sitemap_path));
+        }
+        if self.config.tag_pages() {
+            generated.extend(tags::render_tag_pages(self, dest_dir)?);
+        }
+        if let Some(path) = self.config.search_index() {
+            search::render_index(self, path, &notes, dest_dir)?;
+            generated.push(dest_dir.join(path));
+        }
+        generated.extend(self.write_assets(dest_dir)?);
+        if self.manifest {
+            for path in generated {
+                let hash = manifest::hash_file(&path)?;
+                let relative = path
+                    .strip_prefix(dest_dir)
+                    .expect("output is in destination");
+                let output = OutputFile::new(url_path(relative), hash);
+                report.manifest.generated.push(output);
+            }
+            report
+                .manifest
+                .generated
+                .sort_by(|a, b| a.path.cmp(&b.path));
+        }
+
+        // Look for links to missing files once everything has been written.
+        for (src_path, dest_path, urls) in links.into_inner().unwrap() {
+            for url in urls {
+                if !link_exists(dest_dir, &dest_path, &url) {
+                    report.broken_links.push((src_path.clone(), url));
+                }
+            }
+        }
+        report.broken_links.sort();
+
+        if self.gzip {
+            compress::precompress(dest_dir)?;
+        }
+
+        Ok(report)
+    }
+
+    /// Write the built-in assets that pages link to, under their hashed names.
+    /// Return the paths of the files.
+    fn write_assets(&self, dest_dir: &Path) -> Result<Vec<PathBuf>> {
+        let env = self.templates();
+        let mut paths = vec![];
+        for (name, hashed) in hashed_assets(&env) {
+            let source = env.get_template(&name)?.source().to_string();
+            let dest_path = dest_dir.join(hashed);
+            replace_atomically(&dest_path, |tmp| fs::write(tmp, &source))?;
+            paths.push(dest_path);
+        }
+        Ok(paths)
+    }
+
+    /// Describe the files generated from a source file for the build manifest.
+    fn manifest_entry(
+        &self,
+        src_path: &Path,
+        kind: EntryKind,
+        dest_dir: &Path,
+        outputs: Vec<(PathBuf, u64)>,
+    ) -> ManifestEntry {
+        let relative = |path: &Path, base: &Path| {
+            url_path(
+                path.strip_prefix(base)
+                    .expect("path is within its directory"),
+            )
+        };
+        ManifestEntry {
+            source: relative(src_path, self.split_src_path(src_path).0),
+            kind,
+            outputs: outputs
+                .into_iter()
+                .map(|(path, hash)| OutputFile::new(relative(&path, dest_dir), hash))
+                .collect(),
+        }
+    }
+
+    /// Do the site-wide files, like the feed and the search index, need
+    /// summaries of the rendered notes?
+    fn keeps_notes(&self) -> bool {
+        self.site.base_url.is_some() || self.config.search_index().is_some()
+    }
+
+    /// Should we use a thread pool to render this many notes?
+    fn is_parallel(&self, note_count: usize) -> bool {
+        note_count >= self.config.parallel_threshold()
+    }
+}
+
+/// A link to one of the site's feeds, for the template context.
+#[derive(Debug, Serialize)]
+struct FeedLink {
+    url: String,
+    mime_type: &'static str,
+}
+
+/// A summary of the results of building a site.
+#[derive(Debug, Default)]
+pub struct BuildReport {
+    /// The number of notes rendered to pages.
+    pub notes: usize,
+
+    /// The number of static files copied.
+    pub files: usize,
+
+    /// Notes that failed to render, with the error for each.
+    pub failures: Vec<(PathBuf, anyhow::Error)>,
+
+    /// Problems that were skipped over during the build, with the path of the
+    /// affected file or directory.
+    pub errors: Vec<(PathBuf, anyhow::Error)>,
+
+    /// The files generated from each source file, if the context asked for a
+    /// manifest.
+    pub manifest: BuildManifest,
+
+    /// Relative links in notes that don't point to any file in the built site,
+    /// if the context asked to check them.
+    pub broken_links: Vec<(PathBuf, String)>,
+}
+
+impl BuildReport {
+    /// Log everything that went wrong during the build.
+    pub fn log_problems(&self) {
+        for (path, e) in &self.errors {
+            log::warn!("skipped {}: {e}", path.display());
+        }
+        for (path, e) in &self.failures {
+            log::error!("error rendering note {}: {e}", path.display());
+        }
+        for (path, url) in &self.broken_links {
+            log::warn!("broken link in {}: {url}", path.display());
+        }
+        match self.broken_links.len() {
+            0 => (),
+            1 => log::error!("found 1 broken link"),
+            n => log::error!("found {n} broken links"),
+        }
+    }
+}
+
+/// A step in building a site, as planned by `Context::plan_site`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PlannedAction {
+    /// Delete the existing destination directory and everything in it.
+    Remove(PathBuf),
+
+    /// Create a directory.
+    CreateDir(PathBuf),
+
+    /// Copy (or hard-link) a static file.
+    Copy { from: PathBuf, to: PathBuf },
+
+    /// Render a note to a page.
+    Render { from: PathBuf, to: PathBuf },
+
+    /// Generate a file that doesn't come from any one source file, like a feed.
+    Write(PathBuf),
+}
+
+/// What we wrote when rendering a note to a file.
+struct NoteOutput {
+    /// The files written, with hashes of their contents.
+    files: Vec<(PathBuf, u64)>,
+
+    /// The page's relative links.
+    links: Vec<String>,
+
+    /// The note's summary, if the site-wide files need it.
+    note: Option<RenderedNote>,
+}
+
+/// A published note as the page pass rendered it, kept for the files that
+/// cover the whole site so they don't render every note again.
+#[derive(Debug)]
+pub struct RenderedNote {
+    pub src_path: PathBuf,
+    /// The note's title, or its filename if it has none.
+    pub title: String,
+    pub date: Option<jiff::civil::Date>,
+    /// The HTML body.
+    pub body: String,
+    /// A short HTML summary of the note.
+    pub excerpt: String,
+    /// The note's prose as plain text.
+    pub text: String,
+}
+
+impl RenderedNote {
+    pub(crate) fn new(src_path: &Path, doc: &markdown::Rendered) -> Self {
+        let title = doc.title.clone().unwrap_or_else(|| {
+            let stem = src_path.file_stem().expect("no filename");
+            stem.to_string_lossy().into_owned()
+        });
+        Self {
+            src_path: src_path.to_path_buf(),
+            title,
+            date: doc.front_matter.date,
+            body: doc.body.clone(),
+            excerpt: doc.excerpt.clone(),
+            text: doc.text.clone(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Resource {
+    Static(PathBuf),
+    Note(PathBuf),
+    Directory(PathBuf),
+}
+
+impl Resource {
+    /// The resource's path in the source directory.
+    pub fn path(&self) -> &Path {
+        match self {
+            Resource::Static(path) | Resource::Note(path) | Resource::Directory(path) => path,
+        }
+    }
+}
+
+/// A note's metadata, read without rendering the note.
+#[derive(Debug)]
+pub struct NoteMeta {
+    /// The note's path in the source directory.
+    pub src_path: PathBuf,
+
+    /// The page's path, relative to the destination directory.
+    pub dest_path: PathBuf,
+
+    /// The note's title, from its first heading if that's an `h1`.
+    pub title: Option<String>,
+
+    /// The note's front matter, which says whether it's published, among
+    /// other things.
+    pub front_matter: markdown::FrontMatter,
+}
+
+/// Render a Markdown document to a complete HTML page, using the built-in note
+/// template and the default configuration, without setting up a site.
+///
+/// ```
+/// # use memoize::core::render_markdown_str;
+/// let html = render_markdown_str("# Hello\n\nSee [the guide](guide.md).\n")?;
+/// assert!(html.contains("<title>Hello</title>"));
+/// assert!(html.contains("<a href=\"guide.html\">"));
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn render_markdown_str(source: &str) -> Result<String> {
+    render_standalone(Path::new("note.md"), source)
+}
+
+/// Render a Markdown file to a complete HTML page, like `render_markdown_str`.
+/// Templates and configuration in the file's directory are not used.
+pub fn render_markdown_file(path: &Path) -> Result<String> {
+    let source = fs::read_to_string(path)?;
+    let name = path.file_name().map_or(Path::new("note.md"), Path::new);
+    render_standalone(name, &source)
+}
+
+/// Render a note, named `name`, on its own. Its context gets an empty source
+/// directory, so nothing else on disk (like a `_note.html` or `index.md` in
+/// the current directory) can find its way into the page.
+fn render_standalone(name: &Path, source: &str) -> Result<String> {
+    let config = Config {
+        git: Some(false),
+        ..Config::default()
+    };
+    let src_dir = tempfile::tempdir()?;
+    let src_str = src_dir
+        .path()
+        .to_str()
+        .context("temporary path is not UTF-8")?;
+    let mut ctx = Context::new(src_str, false, config, None);
+    ctx.inline_assets = true;
+    let src_path = src_dir.path().join(name);
+    let context = ctx.note_context_for_source(&src_path, source.into(), &[])?;
+    Ok(ctx.templates().get_template("note.html")?.render(context)?)
+}
+
+/// Convert a relative path into a URL path, with `/` separators.
+pub fn url_path(path: &Path) -> String {
+    let parts: Vec<_> = path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    parts.join("/")
+}
+
+/// Make a link from the page at one site-relative URL path to another.
+fn relative_url(from: &str, to: &str) -> String {
+    let depth = from.matches('/').count();
+    format!("{}{to}", "../".repeat(depth))
+}
+
+/// Map each linked asset to a filename with a hash of its contents in `env`,
+/// like `style.0123abcd.css` for `style.css`.
+fn hashed_assets(env: &minijinja::Environment) -> BTreeMap<String, String> {
+    LINKED_ASSETS
+        .iter()
+        .map(|&name| {
+            let tmpl = env.get_template(name).expect("asset is missing");
+            let hash = format!("{:016x}", manifest::hash_bytes(tmpl.source().as_bytes()));
+            let path = Path::new(name);
+            let stem = path.file_stem().expect("no filename").to_string_lossy();
+            let hashed = match path.extension() {
+                Some(ext) => format!("{stem}.{}.{}", &hash[..8], ext.to_string_lossy()),
+                None => format!("{stem}.{}", &hash[..8]),
+            };
+            (name.to_string(), hashed)
+        })
+        .collect()
+}
+
+/// The files that templates were read from, with their modification times as
+/// of when we read them, so we can tell when templates need reloading.
+#[derive(Clone, Default)]
+struct TemplateFiles(Arc<Mutex<BTreeMap<PathBuf, Option<SystemTime>>>>);
+
+impl TemplateFiles {
+    /// Note that we're about to read a template file.
+    fn record(&self, path: &Path) {
+        self.0.lock().unwrap().insert(path.into(), modified(path));
+    }
+
+    fn changed(&self) -> bool {
+        let files = self.0.lock().unwrap();
+        files.iter().any(|(path, mtime)| modified(path) != *mtime)
+    }
+
+    fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
+
+/// Get a file's modification time, if it exists.
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Make a template loader for note template overrides in the source directories.
+/// Overrides are named by their path relative to the source directories, and
+/// ones in later directories win. The loader records the files it reads in
+/// `files`.
+fn override_loader(
+    src_dirs: Vec<PathBuf>,
+    files: TemplateFiles,
+) -> impl Fn(&str) -> Result<Option<String>, minijinja::Error> + Send + Sync + 'static {
+    move |name| {
+        let path = Path::new(name);
+        let relative = path.components().all(|c| matches!(c, Component::Normal(_)));
+        if !relative || path.file_name() != Some(OsStr::new(NOTE_OVERRIDE)) {
+            return Ok(None);
+        }
+        // Record the path in the main source directory if there's no such
+        // file yet, so we notice when one appears.
+        let candidates: Vec<_> = src_dirs.iter().rev().map(|d| d.join(path)).collect();
+        let path = match candidates.iter().find(|p| p.exists()) {
+            Some(path) => path,
+            None => &candidates[0],
+        };
+        files.record(path);
+        match fs::read_to_string(path) {
+            Ok(source) => Ok(Some(source)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(minijinja::Error::new(
+                minijinja::ErrorKind::InvalidOperation,
+                format!("could not read template {name}"),
+            )
+            .with_source(e)),
+        }
+    }
+}
+
+/// Prepare a nested table of contents for use in a template.
+fn toc_context(nodes: Vec<markdown::TocNode>) -> Vec<minijinja::Value> {
+    nodes
+        .into_iter()
+        .map(|node| {
+            minijinja::context! {
+                level => node.entry.level as u8,
+                id => node.entry.id,
+                title => node.entry.title,
+                children => toc_context(node.children),
+            }
+        })
+        .collect()
+}
 
 /// Try to hard-link `from` at `to`, falling back to a copy if the link fails
-/// (e.g., the two paths are on different filesystems). This always removes the
-/// current file at `to`.
-fn hard_link_or_copy(from: &Path, to: &Path) -> std::io::Result<Option<u64>> {
-    if to.exists() {
-        fs::remove_file(to)?;
+/// (e.g., the two paths are on different filesystems). With `copy`, always
+/// copy instead. This always replaces the current file at `to`, atomically.
+///
+/// If `from` is a symlink, we link or copy the file it points to, since a
+/// copy of the symlink itself might not point anywhere from `to`.
+fn hard_link_or_copy(from: &Path, to: &Path, copy: bool) -> std::io::Result<Option<u64>> {
+    let target;
+    let from = if from.is_symlink() {
+        target = from.canonicalize()?;
+        &target
+    } else {
+        from
+    };
+    replace_atomically(to, |tmp| {
+        if !copy && fs::hard_link(from, tmp).is_ok() {
+            return Ok(None);
+        }
+        // Copies keep the file's permissions, like an executable bit.
+        fs::copy(from, tmp).map(Some)
+    })
+}
+
+/// Does a relative link on the page at `page` point to a file in the built
+/// site in `dest_dir`? Links to directories need an `index.html` in the
+/// directory, as most static hosts expect.
+fn link_exists(dest_dir: &Path, page: &Path, url: &str) -> bool {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    if path.is_empty() {
+        return true; // Just a query or fragment on the same page.
+    }
+    let path = percent_decode(path);
+
+    // Resolve the link within the site, which it must not climb out of.
+    let (base, path) = match path.strip_prefix('/') {
+        Some(path) => (PathBuf::new(), path),
+        None => {
+            let dir = page.parent().unwrap_or(dest_dir);
+            let base = dir.strip_prefix(dest_dir).unwrap_or(Path::new(""));
+            (base.to_path_buf(), path.as_str())
+        }
+    };
+    let mut target = base;
+    for part in path.split('/') {
+        match part {
+            "" | "." => (),
+            ".." => {
+                if !target.pop() {
+                    return false;
+                }
+            }
+            _ => target.push(part),
+        }
+    }
+
+    let target = dest_dir.join(target);
+    if target.is_dir() {
+        target.join("index.html").is_file()
+    } else {
+        target.exists()
+    }
+}
+
+/// Decode `%XX` escapes in a URL path. Invalid escapes are left as they are.
+fn percent_decode(s: &str) -> String {
+    let mut bytes = vec![];
+    let mut rest = s.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        let hex = tail
+            .get(..2)
+            .filter(|h| h.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (b, hex) {
+            (b'%', Some(byte)) => {
+                bytes.push(byte);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(b);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Counts temporary files, so concurrent writes never share one.
+static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Pick an unused, hidden name for a temporary file next to `path`.
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().expect("no filename").to_string_lossy();
+    let n = TEMP_FILES.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!(".{name}.{}-{n}.tmp", std::process::id()))
+}
+
+/// Create a file at `path` by making it under a temporary name with `make`
+/// and then renaming it into place. Anyone reading `path` sees either the old
+/// file or the complete new one. If `make` fails, the old file is left alone.
+///
+/// Renaming also means we never write through an old file that is a hard link
+/// to a static file in the source directory.
+pub(crate) fn replace_atomically<T>(
+    path: &Path,
+    make: impl FnOnce(&Path) -> io::Result<T>,
+) -> io::Result<T> {
+    let tmp = temp_path(path);
+    let result = make(&tmp).and_then(|value| {
+        fs::rename(&tmp, path)?;
+        Ok(value)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// A file in the destination directory that we write under a temporary name
+/// and then rename into place with `commit`, like `replace_atomically`. If it's
+/// dropped without being committed (e.g., because rendering failed partway),
+/// the temporary file is removed and any old file is left alone.
+struct AtomicOutput {
+    /// The open temporary file, until it's committed.
+    file: Option<fs::File>,
+    tmp: PathBuf,
+    path: PathBuf,
+    committed: bool,
+}
+
+impl AtomicOutput {
+    fn create(path: &Path) -> io::Result<Self> {
+        let tmp = temp_path(path);
+        Ok(Self {
+            file: Some(fs::File::create(&tmp)?),
+            tmp,
+            path: path.to_path_buf(),
+            committed: false,
+        })
+    }
+
+    /// Replace the file at the destination with everything written so far.
+    fn commit(mut self) -> io::Result<()> {
+        // Close the file first; some platforms can't rename open files.
+        self.file = None;
+        fs::rename(&self.tmp, &self.path)?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl io::Write for AtomicOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.as_mut().expect("file is open").write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.as_mut().expect("file is open").flush()
+    }
+}
+
+impl Drop for AtomicOutput {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.file = None;
+            let _ = fs::remove_file(&self.tmp);
+        }
     }
-    match fs::hard_link(from, to) {
-        Ok(_) => Ok(None),
-        Err(_) => fs::copy(from, to).map(Some),
+}
+
+/// Do two paths refer to the same existing file?
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
     }
 }
 
@@ -292,6 +2027,44 @@ fn remove_dir_force(path: &Path) -> std::io::Result<()> {
     }
 }
 
+/// Does a file or directory name match any of some glob patterns? Patterns can
+/// use `*` for any run of characters and `?` for any one character.
+pub fn matches_any(patterns: &[String], name: &OsStr) -> bool {
+    let name = name.to_string_lossy();
+    patterns.iter().any(|pattern| glob_match(pattern, &name))
+}
+
+/// Match a whole string against a glob pattern.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    // On a mismatch, backtrack to let the last `*` swallow one more character.
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
 /// Should we skip a given file from the rendering process? We skip hidden
 /// files (prefixed with .) and ones starting with _, which are special.
 pub fn ignore_filename(name: &OsStr) -> bool {
@@ -299,20 +2072,44 @@ pub fn ignore_filename(name: &OsStr) -> bool {
     (bytes != b"." && bytes.starts_with(b".")) || bytes.starts_with(b"_")
 }
 
-/// Does this source filename look like a Markdown note file?
-fn is_note(path: &Path) -> bool {
-    matches!(path.extension(), Some(e) if e == "md")
+/// Does this source filename look like an HTML file?
+fn is_html(path: &Path) -> bool {
+    matches!(path.extension(), Some(e) if e == "html")
+}
+
+/// Is this filename a reserved device name on Windows, like `CON` or
+/// `com1.txt`? Opening these reaches a device rather than a file.
+fn is_windows_device(name: &OsStr) -> bool {
+    const DEVICES: &[&str] = &["CON", "PRN", "AUX", "NUL"];
+    let name = name.to_string_lossy();
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    if DEVICES.iter().any(|d| stem.eq_ignore_ascii_case(d)) {
+        return true;
+    }
+    match stem.get(..3) {
+        Some(prefix)
+            if prefix.eq_ignore_ascii_case("COM") || prefix.eq_ignore_ascii_case("LPT") =>
+        {
+            matches!(stem.as_bytes()[3..], [b'1'..=b'9'])
+        }
+        _ => false,
+    }
 }
 
 /// Validate and relative-ize a requested path. If we return a path, it is now
 /// safe to `join` with a base directory without "escaping" that directory. May
-/// return `None` for any disallowed path.
-fn sanitize_path(path: &str) -> Option<PathBuf> {
+/// return `None` for any disallowed path, which includes Windows device names,
+/// names matching the `ignore` patterns, and, unless `show_hidden` is set,
+/// hidden files.
+fn sanitize_path(path: &str, show_hidden: bool, ignore: &[String]) -> Option<PathBuf> {
     let mut path_buf = PathBuf::new();
     for comp in Path::new(path).components() {
         match comp {
             Component::Normal(c) => {
-                if ignore_filename(c) {
+                if (!show_hidden && ignore_filename(c))
+                    || matches_any(ignore, c)
+                    || is_windows_device(c)
+                {
                     return None;
                 } else {
                     path_buf.push(c);
@@ -328,58 +2125,2071 @@ fn sanitize_path(path: &str) -> Option<PathBuf> {
     Some(path_buf)
 }
 
-#[derive(Debug, Default, Deserialize)]
-pub struct Config {
-    edit_link_prefix: Option<String>,
+/// Metadata about the site as a whole, shared by everything that makes URLs
+/// for it: feeds, the sitemap, and notes' canonical URLs.
+#[derive(Debug, Clone)]
+pub struct SiteConfig {
+    /// The public URL where the site is hosted, if known. Feeds, the sitemap,
+    /// and canonical URLs need this.
+    pub base_url: Option<String>,
+    /// The name of the site.
+    pub title: String,
+    /// A sentence or two about the site.
+    pub description: Option<String>,
+    /// Where the Atom feed goes, relative to the destination directory.
+    pub atom_path: String,
+    /// Where the JSON Feed goes, relative to the destination directory.
+    pub json_feed_path: String,
+    /// Where the sitemap goes, relative to the destination directory.
+    pub sitemap_path: String,
 }
 
-impl Config {
-    pub fn load(src_dir: &Path) -> Result<Self> {
-        match fs::read_to_string(src_dir.join("_config.toml")) {
-            // Silently proceed if the file isn't found, but crash on other errors.
-            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
-            Err(e) => Err(e)?,
-            Ok(s) => Ok(toml::from_str(&s)?),
+impl SiteConfig {
+    /// Where a feed in the given format goes, relative to the destination
+    /// directory.
+    pub fn feed_path(&self, format: feed::FeedFormat) -> &str {
+        match format {
+            feed::FeedFormat::Atom => &self.atom_path,
+            feed::FeedFormat::Json => &self.json_feed_path,
         }
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 
-    #[test]
-    fn absolute() {
-        assert_eq!(sanitize_path("/hi.txt"), Some("hi.txt".into()));
+    /// The absolute URL for a path relative to the site root, if we know the
+    /// base URL.
+    pub fn absolute_url(&self, path: &str) -> Option<String> {
+        let base_url = self.base_url.as_ref()?;
+        Some(feed::absolute_url(base_url, path))
     }
+}
 
-    #[test]
-    fn relative() {
-        assert_eq!(sanitize_path("hi.txt"), Some("hi.txt".into()));
+impl From<&Config> for SiteConfig {
+    fn from(config: &Config) -> Self {
+        Self {
+            base_url: config.base_url.clone(),
+            title: config.title().to_string(),
+            description: config.description.clone(),
+            atom_path: config.atom_path.as_deref().unwrap_or("atom.xml").into(),
+            json_feed_path: config
+                .json_feed_path
+                .as_deref()
+                .unwrap_or("feed.json")
+                .into(),
+            sitemap_path: config
+                .sitemap_path
+                .as_deref()
+                .unwrap_or("sitemap.xml")
+                .into(),
+        }
     }
+}
 
-    #[test]
-    fn with_dir() {
-        assert_eq!(sanitize_path("/dir/hi.txt"), Some("dir/hi.txt".into()));
+impl Default for SiteConfig {
+    fn default() -> Self {
+        Self::from(&Config::default())
     }
+}
 
-    #[test]
-    fn dot_dot() {
-        assert_eq!(sanitize_path("/../hi.txt"), None);
+/// Get the path to a file the build writes at `path`, relative to the
+/// destination directory, and make sure its parent directory exists. The
+/// path must stay inside the site.
+pub fn output_path(dest_dir: &Path, path: &str) -> Result<PathBuf> {
+    let path = Path::new(path);
+    if !path.components().all(|c| matches!(c, Component::Normal(_))) {
+        bail!("output path {} is not inside the site", path.display());
+    }
+    let dest_path = dest_dir.join(path);
+    if let Some(parent) = dest_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(dest_path)
+}
+
+/// Sites with fewer notes than this are rendered serially by default.
+const DEFAULT_PARALLEL_THRESHOLD: usize = 16;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    edit_link_prefix: Option<String>,
+    parallel_threshold: Option<usize>,
+    base_url: Option<String>,
+    title: Option<String>,
+    description: Option<String>,
+    atom_path: Option<String>,
+    json_feed_path: Option<String>,
+    sitemap_path: Option<String>,
+    min_pages: Option<usize>,
+    git: Option<bool>,
+    toc_fragments: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_toc_levels")]
+    toc_levels: Option<RangeInclusive<markdown::HeadingLevel>>,
+    feeds: Option<Vec<feed::FeedFormat>>,
+    excerpt_separator: Option<String>,
+    related_count: Option<usize>,
+    related_min_shared: Option<usize>,
+    pretty_urls: Option<bool>,
+    html_notes: Option<bool>,
+    new_tab_links: Option<bool>,
+    wikilinks: Option<bool>,
+    sanitize_html: Option<bool>,
+    ascii_slugs: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_separator")]
+    slug_separator: Option<char>,
+    slug_lowercase: Option<bool>,
+    id_prefix: Option<String>,
+    prefix_explicit_ids: Option<bool>,
+    search_index: Option<String>,
+    tag_pages: Option<bool>,
+    #[serde(default)]
+    ignore: Vec<String>,
+    #[serde(default)]
+    watch: Vec<PathBuf>,
+    #[serde(default)]
+    html_attrs: BTreeMap<String, String>,
+    /// Values for every template, from `_site.yaml`.
+    #[serde(skip)]
+    site: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Deserialize a slug separator, which can't be a letter, digit, or space,
+/// since it would then run together with the words it separates.
+fn deserialize_separator<'de, D>(deserializer: D) -> Result<Option<char>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let separator = Option::<char>::deserialize(deserializer)?;
+    if let Some(c) = separator
+        && (c.is_alphanumeric() || c.is_whitespace())
+    {
+        return Err(serde::de::Error::custom(format!(
+            "slug separator {c:?} is not punctuation"
+        )));
+    }
+    Ok(separator)
+}
+
+/// Deserialize the range of heading levels for tables of contents, written as
+/// the lowest and highest levels, like `[2, 3]`.
+fn deserialize_toc_levels<'de, D>(
+    deserializer: D,
+) -> Result<Option<RangeInclusive<markdown::HeadingLevel>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let Some([min, max]) = Option::<[usize; 2]>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    let level = |n: usize| {
+        markdown::HeadingLevel::try_from(n)
+            .map_err(|_| serde::de::Error::custom(format!("no heading level {n}")))
+    };
+    if min > max {
+        return Err(serde::de::Error::custom(format!(
+            "heading levels {min} to {max} are backward"
+        )));
+    }
+    Ok(Some(level(min)?..=level(max)?))
+}
+
+impl Config {
+    pub fn load(src_dir: &Path) -> Result<Self> {
+        let mut config: Self = match fs::read_to_string(src_dir.join("_config.toml")) {
+            // Silently proceed if the file isn't found, but crash on other errors.
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => Err(e)?,
+            Ok(s) => toml::from_str(&s)?,
+        };
+        match fs::read_to_string(src_dir.join(SITE_VALUES)) {
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => Err(e)?,
+            Ok(s) => {
+                config.site = serde_yaml::from_str::<Option<_>>(&s)
+                    .with_context(|| format!("could not parse {SITE_VALUES}"))?
+                    .unwrap_or_default();
+            }
+        }
+        Ok(config)
+    }
+
+    /// Site-wide values for templates, which notes' front matter can override.
+    pub fn site(&self) -> &serde_json::Map<String, serde_json::Value> {
+        &self.site
+    }
+
+    /// The public URL where the site is hosted, if known.
+    pub fn base_url(&self) -> Option<&str> {
+        self.base_url.as_deref()
+    }
+
+    /// The feed formats to write, when a base URL is configured.
+    pub fn feeds(&self) -> &[feed::FeedFormat] {
+        self.feeds.as_deref().unwrap_or(&[feed::FeedFormat::Atom])
+    }
+
+    /// The marker that ends a note's excerpt, unless its front matter picks
+    /// another.
+    pub fn excerpt_separator(&self) -> &str {
+        self.excerpt_separator
+            .as_deref()
+            .unwrap_or(markdown::DEFAULT_EXCERPT_SEPARATOR)
+    }
+
+    /// The name of the site as a whole.
+    pub fn title(&self) -> &str {
+        self.title.as_deref().unwrap_or("Notes")
+    }
+
+    /// Should we look up git commit information for notes?
+    pub fn git(&self) -> bool {
+        self.git.unwrap_or(false)
+    }
+
+    /// Should we write each note's table of contents to its own file?
+    pub fn toc_fragments(&self) -> bool {
+        self.toc_fragments.unwrap_or(false)
+    }
+
+    /// The levels of the headings that go in notes' tables of contents.
+    pub fn toc_levels(&self) -> RangeInclusive<markdown::HeadingLevel> {
+        self.toc_levels.clone().unwrap_or(markdown::ALL_TOC_LEVELS)
+    }
+
+    /// Should notes' pages go in their own directories, like `foo/index.html`?
+    pub fn pretty_urls(&self) -> bool {
+        self.pretty_urls.unwrap_or(false)
+    }
+
+    /// Should HTML files be wrapped in the note template, like Markdown notes?
+    pub fn html_notes(&self) -> bool {
+        self.html_notes.unwrap_or(false)
+    }
+
+    /// Should links to other sites open in a new tab?
+    pub fn new_tab_links(&self) -> bool {
+        self.new_tab_links.unwrap_or(false)
+    }
+
+    /// Should we remove raw HTML from notes?
+    pub fn sanitize_html(&self) -> bool {
+        self.sanitize_html.unwrap_or(false)
+    }
+
+    /// Should `[[Some Note]]` be a link to another note?
+    pub fn wikilinks(&self) -> bool {
+        self.wikilinks.unwrap_or(false)
+    }
+
+    /// How many related notes to list on each page, by shared tags. The
+    /// default, 0, turns related notes off.
+    pub fn related_count(&self) -> usize {
+        self.related_count.unwrap_or(0)
+    }
+
+    /// How many tags a note must share with another to count as related.
+    pub fn related_min_shared(&self) -> usize {
+        self.related_min_shared.unwrap_or(1)
+    }
+
+    /// Glob patterns for more file and directory names to skip, besides
+    /// hidden ones.
+    pub fn ignore(&self) -> &[String] {
+        &self.ignore
+    }
+
+    /// How to make heading IDs.
+    pub fn slug_options(&self) -> markdown::SlugOptions {
+        let defaults = markdown::SlugOptions::default();
+        markdown::SlugOptions {
+            style: if self.ascii_slugs.unwrap_or(false) {
+                markdown::SlugStyle::Ascii
+            } else {
+                markdown::SlugStyle::Unicode
+            },
+            separator: self.slug_separator.unwrap_or(defaults.separator),
+            lowercase: self.slug_lowercase.unwrap_or(defaults.lowercase),
+            ..defaults
+        }
+    }
+
+    /// A prefix for generated heading IDs, to keep them apart from IDs on pages
+    /// that embed notes.
+    pub fn id_prefix(&self) -> &str {
+        self.id_prefix.as_deref().unwrap_or("")
+    }
+
+    /// Should explicit heading IDs get `id_prefix` too?
+    pub fn prefix_explicit_ids(&self) -> bool {
+        self.prefix_explicit_ids.unwrap_or(false)
+    }
+
+    /// Where to write a JSON search index in the built site, if anywhere.
+    pub fn search_index(&self) -> Option<&str> {
+        self.search_index.as_deref()
+    }
+
+    /// Should builds generate a page for each tag, plus an overview?
+    pub fn tag_pages(&self) -> bool {
+        self.tag_pages.unwrap_or(false)
+    }
+
+    /// Attributes for every page's `<html>` tag.
+    pub fn html_attrs(&self) -> &BTreeMap<String, String> {
+        &self.html_attrs
+    }
+
+    /// Extra directories for the preview server to watch, relative to the
+    /// source directory.
+    pub fn watch(&self) -> &[PathBuf] {
+        &self.watch
+    }
+
+    /// The fewest pages a build may produce without failing.
+    pub fn min_pages(&self) -> usize {
+        self.min_pages.unwrap_or(1)
+    }
+
+    /// The minimum number of notes for which we render in parallel.
+    pub fn parallel_threshold(&self) -> usize {
+        self.parallel_threshold
+            .unwrap_or(DEFAULT_PARALLEL_THRESHOLD)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn absolute() {
+        assert_eq!(sanitize_path("/hi.txt", false, &[]), Some("hi.txt".into()));
+    }
+
+    #[test]
+    fn relative() {
+        assert_eq!(sanitize_path("hi.txt", false, &[]), Some("hi.txt".into()));
+    }
+
+    #[test]
+    fn with_dir() {
+        assert_eq!(
+            sanitize_path("/dir/hi.txt", false, &[]),
+            Some("dir/hi.txt".into())
+        );
+    }
+
+    #[test]
+    fn dot_dot() {
+        assert_eq!(sanitize_path("/../hi.txt", false, &[]), None);
     }
 
     #[test]
     fn dot_hidden_file() {
-        assert_eq!(sanitize_path(".hi.txt"), None);
+        assert_eq!(sanitize_path(".hi.txt", false, &[]), None);
     }
 
     #[test]
     fn underscore_hidden_file() {
-        assert_eq!(sanitize_path("_hi.txt"), None);
+        assert_eq!(sanitize_path("_hi.txt", false, &[]), None);
     }
 
     #[test]
     fn underscore_hidden_dir() {
-        assert_eq!(sanitize_path("foo/_bar/hi.txt"), None);
+        assert_eq!(sanitize_path("foo/_bar/hi.txt", false, &[]), None);
+    }
+
+    #[test]
+    fn show_hidden() {
+        assert_eq!(
+            sanitize_path("foo/_bar/.hi.txt", true, &[]),
+            Some("foo/_bar/.hi.txt".into())
+        );
+        assert_eq!(sanitize_path("/../hi.txt", true, &[]), None);
+    }
+
+    #[test]
+    fn windows_devices() {
+        assert_eq!(sanitize_path("CON", false, &[]), None);
+        assert_eq!(sanitize_path("dir/con.txt", false, &[]), None);
+        assert_eq!(sanitize_path("COM9", true, &[]), None);
+        assert_eq!(sanitize_path("Lpt1.tar.gz", false, &[]), None);
+        assert_eq!(
+            sanitize_path("console.md", false, &[]),
+            Some("console.md".into())
+        );
+        assert_eq!(
+            sanitize_path("com10.txt", false, &[]),
+            Some("com10.txt".into())
+        );
+        assert_eq!(
+            sanitize_path("nullable/a.md", false, &[]),
+            Some("nullable/a.md".into())
+        );
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob_match("node_modules", "node_modules"));
+        assert!(glob_match("*.tmp", "notes.tmp"));
+        assert!(glob_match("*.tmp", ".tmp"));
+        assert!(!glob_match("*.tmp", "notes.tmp.md"));
+        assert!(glob_match("draft-?.md", "draft-1.md"));
+        assert!(!glob_match("draft-?.md", "draft-10.md"));
+        assert!(glob_match("*a*b*", "xxaxxbxx"));
+        assert!(!glob_match("*a*b", "xxbxxa"));
+        assert!(glob_match("*", ""));
+    }
+
+    #[test]
+    fn ignore_patterns() {
+        let site = testing::Site::new(&[
+            ("a.md", "# A\n"),
+            ("scratch.tmp", "junk"),
+            ("node_modules/pkg/readme.md", "# Pkg\n"),
+            ("sub/b.md", "# B\n"),
+        ]);
+        let ctx = site.context(Config {
+            ignore: vec!["node_modules".into(), "*.tmp".into()],
+            ..Config::default()
+        });
+        let (report, tree) = site.build(&ctx);
+        assert_eq!(report.notes, 2);
+        assert_eq!(report.files, 0);
+        assert!(tree.contains_key("sub/b.html"));
+        assert!(!tree.keys().any(|k| k.starts_with("node_modules")));
+
+        let ignore = ctx.config.ignore();
+        assert_eq!(
+            sanitize_path("node_modules/pkg/readme.md", false, ignore),
+            None
+        );
+        assert_eq!(sanitize_path("scratch.tmp", true, ignore), None);
+        assert_eq!(
+            sanitize_path("sub/b.md", false, ignore),
+            Some("sub/b.md".into())
+        );
+    }
+
+    #[test]
+    fn resolve_hidden_note() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("_draft.md"), "# Draft\n").unwrap();
+        let mut ctx = Context::new(dir.path().to_str().unwrap(), false, Config::default(), None);
+        assert!(ctx.resolve_resource("_draft.html").is_none());
+
+        ctx.show_hidden = true;
+        assert!(matches!(
+            ctx.resolve_resource("_draft.html"),
+            Some(Resource::Note(_))
+        ));
+    }
+
+    #[test]
+    fn resolve_ignore_case() {
+        let site = testing::Site::new(&[
+            ("Notes/Foo.md", "# Foo\n"),
+            ("Notes/pic.PNG", "not really a PNG"),
+            ("Notes/bar.html", "<p>Bar</p>"),
+            ("_secret.md", "# Secret\n"),
+        ]);
+        let src = site.src.path();
+        let mut ctx = site.context(Config::default());
+        assert!(ctx.resolve_resource("notes/foo.html").is_none());
+
+        ctx.ignore_case = true;
+        let resolve = |path| ctx.resolve_resource(path);
+        assert_eq!(
+            resolve("notes/foo.html"),
+            Some(Resource::Note(src.join("Notes/Foo.md")))
+        );
+        assert_eq!(
+            resolve("NOTES/FOO.HTML"),
+            Some(Resource::Note(src.join("Notes/Foo.md")))
+        );
+        assert_eq!(
+            resolve("notes/Pic.png"),
+            Some(Resource::Static(src.join("Notes/pic.PNG")))
+        );
+        assert_eq!(
+            resolve("nOtEs"),
+            Some(Resource::Directory(src.join("Notes")))
+        );
+        assert!(matches!(
+            resolve("Notes/Bar.HTML"),
+            Some(Resource::Static(_))
+        ));
+        assert!(resolve("notes/missing.html").is_none());
+        assert!(resolve("_Secret.html").is_none());
+    }
+
+    #[test]
+    fn note_metadata() {
+        let site = testing::Site::new(&[
+            (
+                "a.md",
+                "---\ntags: [x]\ndraft: true\n---\n# First `A`\n\nText.\n",
+            ),
+            ("pic.png", "png"),
+            ("sub/b.md", "## Not a title\n"),
+            ("sub/index.md", "# Sub\n"),
+        ]);
+        let ctx = site.context(Config {
+            pretty_urls: Some(true),
+            ..Config::default()
+        });
+        let notes: Vec<_> = ctx.notes().map(Result::unwrap).collect();
+        let summary: Vec<_> = notes
+            .iter()
+            .map(|n| (n.dest_path.as_path(), n.title.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (Path::new("a/index.html"), Some("First A")),
+                (Path::new("sub/b/index.html"), None),
+                (Path::new("sub/index.html"), Some("Sub")),
+            ]
+        );
+        assert_eq!(notes[0].src_path, site.src.path().join("a.md"));
+        assert_eq!(notes[0].front_matter.tags, ["x"]);
+        assert!(!ctx.is_published(&notes[0].front_matter));
+    }
+
+    #[test]
+    fn directory_listing() {
+        let site = testing::Site::new(&[
+            ("a.md", "# Alpha\n"),
+            ("b.md", "No heading.\n"),
+            ("_draft.md", "# Draft\n"),
+            (".hidden.md", "# Hidden\n"),
+            ("pic.png", "png"),
+            ("zed/c.md", "# C\n"),
+            ("sub/index.md", "# Sub Index\n"),
+            ("sub/d.md", "# D\n"),
+        ]);
+        let ctx = site.context(Config::default());
+        let render = |path| {
+            let rsrc = ctx.resolve_resource(path).unwrap();
+            ctx.render_resource_to_string(rsrc).unwrap()
+        };
+
+        // Directories come first, then notes, labeled with their titles.
+        let listing = render("");
+        let links: Vec<_> = listing
+            .match_indices("<a href=")
+            .map(|(i, _)| &listing[i..listing[i..].find("</a>").unwrap() + i])
+            .collect();
+        assert_eq!(
+            links,
+            [
+                "<a href=\"sub&#x2f;\">sub",
+                "<a href=\"zed&#x2f;\">zed",
+                "<a href=\"a.html\">Alpha",
+                "<a href=\"b.html\">b.md",
+            ]
+        );
+
+        // A directory with an `index.md` gets that note instead.
+        let index = render("sub");
+        assert!(index.contains("<title>Sub Index</title>"));
+        assert!(!index.contains("d.html"));
+    }
+
+    #[test]
+    fn merged_sources() {
+        let theme = testing::Site::new(&[("b.md", "# Theme B\n"), ("e.md", "# E\n")]);
+        let shared = testing::Site::new(&[
+            ("a.md", "# Shared A\n"),
+            ("b.md", "# B\n"),
+            ("pic.png", "shared"),
+            ("dir/c.md", "# C\n"),
+            ("dir/_note.html", "<p>{{ title }} from shared</p>"),
+        ]);
+        let site = testing::Site::new(&[
+            ("a.md", "# Notes A\n"),
+            ("pic.png", "notes"),
+            ("dir/d.md", "# D\n"),
+        ]);
+        let (shared_src, src) = (shared.src.path(), site.src.path());
+        let mut ctx = site.context(Config {
+            git: Some(false),
+            ..Config::default()
+        });
+        ctx.set_extra_src_dirs([theme.src.path(), shared_src])
+            .unwrap();
+
+        // Later source directories win, and the rest fill in the gaps.
+        let resolve = |path| ctx.resolve_resource(path);
+        assert_eq!(resolve("a.html"), Some(Resource::Note(src.join("a.md"))));
+        assert_eq!(
+            resolve("b.html"),
+            Some(Resource::Note(shared_src.join("b.md")))
+        );
+        assert_eq!(
+            resolve("pic.png"),
+            Some(Resource::Static(src.join("pic.png")))
+        );
+        assert_eq!(
+            resolve("dir/c.html"),
+            Some(Resource::Note(shared_src.join("dir/c.md")))
+        );
+        assert_eq!(
+            resolve("e.html"),
+            Some(Resource::Note(theme.src.path().join("e.md")))
+        );
+        assert!(resolve("dir/missing.html").is_none());
+
+        // Directory listings include both directories' notes.
+        let listing = ctx
+            .render_resource_to_string(resolve("dir").unwrap())
+            .unwrap();
+        assert!(listing.contains("href=\"c.html\""));
+        assert!(listing.contains("href=\"d.html\""));
+
+        let (report, tree) = site.build(&ctx);
+        assert!(report.failures.is_empty());
+        assert_eq!(report.notes, 5);
+        assert!(tree["a.html"].contains("Notes A"));
+        assert!(tree["b.html"].contains("<title>B</title>"));
+        assert_eq!(tree["pic.png"], "notes");
+        assert_eq!(tree["dir/c.html"], "<p>C from shared</p>");
+        assert_eq!(tree["dir/d.html"], "<p>D from shared</p>");
+    }
+
+    #[test]
+    fn render_standalone() {
+        let html = render_markdown_str("# Hello\n\nSee [the guide](guide.md).\n").unwrap();
+        assert!(html.contains("<title>Hello</title>"));
+        assert!(html.contains("<a href=\"guide.html\">"));
+
+        let site = testing::Site::new(&[("notes/_note.html", "override"), ("notes/a.md", "# A\n")]);
+        let html = render_markdown_file(&site.src.path().join("notes/a.md")).unwrap();
+        assert!(html.contains("<title>A</title>"));
+        assert!(html.contains(">a.md</dd>"));
+    }
+
+    #[test]
+    fn render_to_string() {
+        let site = testing::Site::new(&[("a.md", "# Hi\n"), ("notes.txt", "plain")]);
+        fs::write(site.src.path().join("blob.bin"), [0xff, 0xfe]).unwrap();
+        let ctx = site.context(Config::default());
+
+        let page = ctx
+            .render_note_to_string(&site.src.path().join("a.md"))
+            .unwrap();
+        assert!(page.contains("<h1 id=\"hi\">Hi"));
+        let text = ctx.resolve_resource("notes.txt").unwrap();
+        assert_eq!(ctx.render_resource_to_string(text).unwrap(), "plain");
+        let blob = ctx.resolve_resource("blob.bin").unwrap();
+        assert!(ctx.render_resource_to_string(blob).is_err());
+    }
+
+    #[test]
+    fn custom_templates() {
+        let site = testing::Site::new(&[("a.md", "# Hi\n")]);
+        let mut templates = DynamicAssets::default();
+        templates.insert("note.html", "{% include \"title.html\" %}: {{ body|safe }}");
+        templates.insert("title.html", "<b>{{ title }}</b>");
+        let src_dir = site.src.path().to_str().unwrap();
+        let ctx = Context::with_templates(src_dir, false, Config::default(), templates).unwrap();
+
+        let render = |ctx: &Context| ctx.render_note_to_string(&site.src.path().join("a.md"));
+        assert!(render(&ctx).unwrap().starts_with("<b>Hi</b>: <h1"));
+        ctx.load_templates().unwrap();
+        assert!(render(&ctx).unwrap().starts_with("<b>Hi</b>: <h1"));
+
+        let mut broken = DynamicAssets::default();
+        broken.insert("note.html", "{% if %}");
+        assert!(Context::with_templates(src_dir, false, Config::default(), broken).is_err());
+    }
+
+    #[test]
+    fn template_setup() {
+        let site = testing::Site::new(&[("a.md", "# Hi\n")]);
+        let mut templates = DynamicAssets::default();
+        templates.insert("note.html", "{{ title|shout }} by {{ owner }}");
+        let src_dir = site.src.path().to_str().unwrap();
+        let mut ctx =
+            Context::with_templates(src_dir, false, Config::default(), templates).unwrap();
+        ctx.configure_templates(|env| {
+            env.add_filter("shout", |s: String| s.to_uppercase());
+            env.add_global("owner", "Ada");
+        })
+        .unwrap();
+
+        let render = |ctx: &Context| ctx.render_note_to_string(&site.src.path().join("a.md"));
+        assert_eq!(render(&ctx).unwrap(), "HI by Ada");
+        ctx.load_templates().unwrap();
+        assert_eq!(render(&ctx).unwrap(), "HI by Ada");
+    }
+
+    #[test]
+    fn note_template_names() {
+        let site = testing::Site::new(&[
+            ("a.md", "# A\n"),
+            ("talk.md", "---\ntemplate: slides.html\n---\n# Talk\n"),
+            ("bad.md", "---\ntemplate: missing.html\n---\n# Bad\n"),
+        ]);
+        let mut templates = DynamicAssets::default();
+        templates.insert("slides.html", "slides: {{ title }}");
+        let src_dir = site.src.path().to_str().unwrap();
+        let mut ctx =
+            Context::with_templates(src_dir, false, Config::default(), templates).unwrap();
+        let render = |ctx: &Context, name| ctx.render_note_to_string(&site.src.path().join(name));
+
+        assert!(render(&ctx, "a.md").unwrap().contains("<title>A</title>"));
+        assert_eq!(render(&ctx, "talk.md").unwrap(), "slides: Talk");
+        let err = render(&ctx, "bad.md").unwrap_err();
+        assert!(format!("{err:#}").contains("could not load template missing.html"));
+
+        ctx.note_template = "slides.html".into();
+        assert_eq!(render(&ctx, "a.md").unwrap(), "slides: A");
+    }
+
+    #[test]
+    fn draft_robots() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("draft.md"), "---\ndraft: true\n---\n# hi").unwrap();
+        fs::write(dir.path().join("note.md"), "# hi").unwrap();
+        let ctx = Context::new(dir.path().to_str().unwrap(), false, Config::default(), None);
+
+        let robots = |name| {
+            let context = ctx.note_context(&dir.path().join(name)).unwrap();
+            context.get_attr("robots").unwrap().to_string()
+        };
+        assert_eq!(robots("draft.md"), "noindex,nofollow");
+        assert_eq!(robots("note.md"), "index,follow");
+    }
+
+    #[test]
+    fn html_attrs() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = "---\nhtml_attrs:\n  data-theme: dark\n---\n# hi";
+        fs::write(dir.path().join("note.md"), source).unwrap();
+        let mut config = Config::default();
+        config.html_attrs.insert("lang".into(), "en".into());
+        config
+            .html_attrs
+            .insert("data-theme".into(), "light".into());
+        let ctx = Context::new(dir.path().to_str().unwrap(), false, config, None);
+
+        let context = ctx.note_context(&dir.path().join("note.md")).unwrap();
+        let attrs = context.get_attr("html_attrs").unwrap();
+        assert_eq!(attrs.get_attr("lang").unwrap().to_string(), "en");
+        assert_eq!(attrs.get_attr("data-theme").unwrap().to_string(), "dark");
+    }
+
+    #[test]
+    fn site_values() {
+        let site = testing::Site::new(&[
+            (
+                "_site.yaml",
+                "site_title: My Notes\nauthor: Site\nnav:\n  - {title: Home, url: /}\n",
+            ),
+            (
+                "_note.html",
+                "{{ site.site_title }}|{{ site.author }}|{{ site.nav[0].title }}",
+            ),
+            ("a.md", "# A\n"),
+            ("b.md", "---\nauthor: Me\n---\n# B\n"),
+        ]);
+        let ctx = site.context(Config::load(site.src.path()).unwrap());
+        let render = |name| {
+            ctx.render_note_to_string(&site.src.path().join(name))
+                .unwrap()
+        };
+        assert_eq!(render("a.md"), "My Notes|Site|Home");
+        assert_eq!(render("b.md"), "My Notes|Me|Home");
+
+        // The file is optional, but it must be valid if it exists.
+        let empty = tempfile::tempdir().unwrap();
+        assert!(Config::load(empty.path()).unwrap().site().is_empty());
+        fs::write(empty.path().join(SITE_VALUES), "nav: [").unwrap();
+        assert!(Config::load(empty.path()).is_err());
+    }
+
+    #[test]
+    fn front_matter_values() {
+        let site = testing::Site::new(&[
+            (
+                "_note.html",
+                "{{ seo.description }}|{{ seo.images[1] }}|{{ title }}|{{ tags|join(',') }}",
+            ),
+            (
+                "a.md",
+                "---\nseo:\n  description: About A\n  images: [x.png, y.png]\ntitle: Ignored\ntags: [t]\n---\n# A\n",
+            ),
+            (
+                "b.md",
+                "+++\ntitle = \"Ignored\"\n[seo]\ndescription = \"About B\"\nimages = [\"z.png\", \"w.png\"]\n+++\n# B\n",
+            ),
+        ]);
+        let ctx = site.context(Config::default());
+        let render = |name| {
+            ctx.render_note_to_string(&site.src.path().join(name))
+                .unwrap()
+        };
+        // Built-in variables like `title` win over front matter keys.
+        assert_eq!(render("a.md"), "About A|y.png|A|t");
+        assert_eq!(render("b.md"), "About B|w.png|B|");
+    }
+
+    #[test]
+    fn breadcrumbs() {
+        let site = testing::Site::new(&[
+            (
+                "_note.html",
+                "{% for c in breadcrumbs %}{{ c.label }}={{ (c.href or \"-\")|safe }} {% endfor %}",
+            ),
+            ("index.md", "---\ndraft: true\n---\n# Start\n"),
+            ("top.md", "# Top\n"),
+            ("rust/index.md", "# All About Rust\n"),
+            ("rust/traits/dyn.md", "# Dyn\n"),
+        ]);
+        let ctx = site.context(Config::default());
+        let render = |name| {
+            ctx.render_note_to_string(&site.src.path().join(name))
+                .unwrap()
+        };
+        // Only published index notes give their directories pages.
+        assert_eq!(
+            render("rust/traits/dyn.md"),
+            "Start=- All About Rust=../../rust/ traits=- "
+        );
+        assert_eq!(render("rust/index.md"), "Start=- ");
+        assert_eq!(render("top.md"), "Start=- ");
+
+        let config: Config = toml::from_str("pretty_urls = true").unwrap();
+        let ctx = site.context(config);
+        assert_eq!(
+            ctx.render_note_to_string(&site.src.path().join("rust/traits/dyn.md"))
+                .unwrap(),
+            "Start=- All About Rust=../../../rust/ traits=- "
+        );
+
+        // The preview server has a page for every directory.
+        let ctx = Context::new(
+            site.src.path().to_str().unwrap(),
+            true,
+            Config::default(),
+            None,
+        );
+        assert_eq!(
+            ctx.render_note_to_string(&site.src.path().join("rust/traits/dyn.md"))
+                .unwrap(),
+            "Start=../../ All About Rust=../../rust/ traits=../../rust/traits/ "
+        );
+    }
+
+    #[test]
+    fn git_info() {
+        let dir = tempfile::tempdir().unwrap();
+        let git = |args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .current_dir(dir.path())
+                .args(args)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success());
+        };
+        git(&["init", "-q"]);
+        fs::write(dir.path().join("tracked.md"), "# hi").unwrap();
+        git(&["add", "tracked.md"]);
+        git(&[
+            "-c",
+            "user.name=Ada",
+            "-c",
+            "user.email=ada@example.com",
+            "commit",
+            "-q",
+            "-m",
+            "add note",
+        ]);
+        fs::write(dir.path().join("untracked.md"), "# hi").unwrap();
+
+        // Sites outside git don't pay for looking, so it's off by default.
+        let src_dir = dir.path().to_str().unwrap();
+        let ctx = Context::new(src_dir, false, Config::default(), None);
+        let context = ctx.note_context(&dir.path().join("tracked.md")).unwrap();
+        assert!(context.get_attr("revision").unwrap().is_none());
+
+        let config: Config = toml::from_str("git = true").unwrap();
+        let ctx = Context::new(src_dir, false, config, None);
+        let context = ctx.note_context(&dir.path().join("tracked.md")).unwrap();
+        assert_eq!(context.get_attr("author").unwrap().to_string(), "Ada");
+        assert_eq!(context.get_attr("revision").unwrap().len(), Some(7));
+        let date = context.get_attr("last_modified").unwrap().to_string();
+        assert!(date.parse::<jiff::civil::Date>().is_ok());
+
+        let context = ctx.note_context(&dir.path().join("untracked.md")).unwrap();
+        assert!(context.get_attr("author").unwrap().is_none());
+        assert!(context.get_attr("last_modified").unwrap().is_none());
+    }
+
+    #[test]
+    fn parallel_threshold() {
+        let config = Config {
+            parallel_threshold: Some(3),
+            ..Config::default()
+        };
+        let ctx = Context::new(".", false, config, None);
+        assert!(!ctx.is_parallel(2));
+        assert!(ctx.is_parallel(3));
+    }
+
+    #[test]
+    fn build_site() {
+        let site = testing::Site::new(&[
+            ("a.md", "# Hello\n\nSee [b](sub/b.md).\n"),
+            ("sub/b.md", "Just text.\n"),
+            ("sub/pic.png", "not really a PNG"),
+            ("_hidden.md", "# Hidden\n"),
+            (".secret", "shh"),
+            ("_partials/x.txt", "x"),
+        ]);
+        let ctx = site.context(Config::default());
+        let (report, tree) = site.build(&ctx);
+
+        assert_eq!(report.notes, 2);
+        assert_eq!(report.files, 1);
+        assert!(report.errors.is_empty());
+
+        let style = &hashed_assets(&ctx.templates())["style.css"];
+        let paths: Vec<_> = tree.keys().map(String::as_str).collect();
+        assert_eq!(
+            paths,
+            ["a.html", style, "sub/", "sub/b.html", "sub/pic.png"]
+        );
+        assert!(tree["a.html"].contains("<h1 id=\"hello\">"));
+        assert!(tree["a.html"].contains("href=\"sub/b.html\""));
+        assert!(tree["sub/b.html"].contains("<p>Just text.</p>"));
+        assert_eq!(tree["sub/pic.png"], "not really a PNG");
+    }
+
+    #[test]
+    fn minify_css() {
+        let css = "/* Colors. */\nbody {\n    color: black;\n}\n";
+        let site = testing::Site::new(&[("a.md", "# A\n"), ("style.css", css)]);
+        let latin1 = b"/* Caf\xe9. */ p { color : red }";
+        fs::write(site.src.path().join("latin1.css"), latin1).unwrap();
+        let mut ctx = site.context(Config::default());
+
+        let (_, tree) = site.build(&ctx);
+        assert_eq!(tree["style.css"], css);
+
+        ctx.minify = true;
+        let dest = tempfile::tempdir().unwrap();
+        let report = ctx.render_site(None, dest.path()).unwrap();
+        assert_eq!(report.files, 2);
+        let read = |name| fs::read(dest.path().join(name)).unwrap();
+        assert_eq!(read("style.css"), b"body{color:black}");
+        assert_eq!(read("latin1.css"), latin1);
+    }
+
+    #[test]
+    fn gzip_outputs() {
+        let site = testing::Site::new(&[
+            ("a.md", "# A\n"),
+            ("sub/b.md", "# B\n"),
+            ("style.css", "body {}"),
+            ("pic.png", "not really a PNG"),
+        ]);
+        let mut ctx = site.context(Config::default());
+        let (_, tree) = site.build(&ctx);
+        assert!(!tree.keys().any(|k| k.ends_with(".gz")));
+
+        ctx.gzip = true;
+        let (_, tree) = site.build(&ctx);
+        let style = format!("{}.gz", hashed_assets(&ctx.templates())["style.css"]);
+        let gzipped: Vec<_> = tree.keys().filter(|k| k.ends_with(".gz")).collect();
+        let mut expected = vec!["a.html.gz", "style.css.gz", &style, "sub/b.html.gz"];
+        expected.sort();
+        assert_eq!(gzipped, expected);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel_backends() {
+        let mut files = vec![("pic.png".to_string(), "not really a PNG".to_string())];
+        for i in 0..40 {
+            let note = format!("# Note {i}\n\nSee [the next](n{}.md).\n", i + 1);
+            files.push((format!("dir{}/n{i}.md", i % 4), note));
+        }
+        let files: Vec<_> = files
+            .iter()
+            .map(|(p, c)| (p.as_str(), c.as_str()))
+            .collect();
+        let site = testing::Site::new(&files);
+        let config: Config = toml::from_str("parallel_threshold = 1").unwrap();
+        let ctx = site.context(config);
+
+        let build = |backend| {
+            let dest = tempfile::tempdir().unwrap();
+            let dest_dir = dest.path().join("_site");
+            let report = ctx
+                .render_site_with(backend, NonZero::new(4), &dest_dir)
+                .unwrap();
+            assert_eq!(report.notes, 40);
+            testing::read_tree(&dest_dir)
+        };
+        assert_eq!(
+            build(parallel::Backend::Crossbeam),
+            build(parallel::Backend::Rayon)
+        );
+    }
+
+    #[test]
+    fn hashed_style() {
+        let site = testing::Site::new(&[
+            ("a.md", "# A\n"),
+            ("sub/b.md", "# B\n"),
+            ("404.md", "# Not Found\n"),
+        ]);
+        let mut ctx = site.context(Config::default());
+        ctx.manifest = true;
+        let (report, tree) = site.build(&ctx);
+
+        let (name, css) = tree
+            .iter()
+            .find(|(k, _)| k.starts_with("style.") && k.ends_with(".css"))
+            .expect("no stylesheet");
+        let hash = &name["style.".len()..name.len() - ".css".len()];
+        assert_eq!(hash.len(), 8);
+        assert!(hash.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(
+            *hash,
+            format!("{:016x}", manifest::hash_bytes(css.as_bytes()))[..8]
+        );
+        assert!(tree["a.html"].contains(&format!("<link rel=\"stylesheet\" href=\"{name}\" />")));
+        assert!(tree["sub/b.html"].contains(&format!("href=\"../{name}\"")));
+        let generated: Vec<_> = report.manifest.generated.iter().map(|o| &o.path).collect();
+        assert_eq!(generated, [name]);
+
+        // Pages that can be viewed anywhere carry their own styles.
+        assert!(tree["404.html"].contains("<style>"));
+        assert!(!tree["404.html"].contains(name.as_str()));
+        let html = render_markdown_str("# Hello\n").unwrap();
+        assert!(html.contains("<style>"));
+        assert!(!html.contains("stylesheet"));
+
+        // The preview server serves the stylesheet as-is.
+        let ctx = Context::new(
+            site.src.path().to_str().unwrap(),
+            true,
+            Config::default(),
+            None,
+        );
+        let html = ctx
+            .render_note_to_string(&site.src.path().join("sub/b.md"))
+            .unwrap();
+        assert!(html.contains("href=\"/style.css\""));
+    }
+
+    #[test]
+    fn math_scripts() {
+        let site = testing::Site::new(&[("a.md", "So $$x$$ holds.\n"), ("b.md", "No math.\n")]);
+        let (_, tree) = site.build(&site.context(Config::default()));
+        let a = &tree["a.html"];
+        assert_eq!(a.matches("integrity=\"sha384-").count(), 3);
+        assert_eq!(a.matches("crossorigin=\"anonymous\"").count(), 3);
+        assert!(a.contains("<span class=\"math display\">"));
+        assert!(!tree["b.html"].contains("katex"));
+    }
+
+    #[test]
+    fn copy_files() {
+        let site = testing::Site::new(&[("run.sh", "echo old\n"), ("a.md", "# A\n")]);
+        let script = site.src.path().join("run.sh");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let mut ctx = site.context(Config::default());
+        ctx.copy_files = true;
+        let dest = tempfile::tempdir().unwrap();
+        ctx.render_site(None, dest.path()).unwrap();
+
+        let copy = dest.path().join("run.sh");
+        assert_eq!(
+            fs::metadata(&copy).unwrap().permissions(),
+            fs::metadata(&script).unwrap().permissions()
+        );
+        fs::write(&script, "echo new\n").unwrap();
+        assert_eq!(fs::read_to_string(&copy).unwrap(), "echo old\n");
+    }
+
+    #[test]
+    fn check_links() {
+        let site = testing::Site::new(&[
+            (
+                "a.md",
+                "# A\n\n[b](sub/b.md) [top](#a) [web](https://example.com/x.md) \
+                 [q](?x=1) ![pic](my%20pic.png)\n",
+            ),
+            ("my pic.png", "not really a PNG"),
+            (
+                "sub/b.md",
+                "# B\n\n[a](../a.md#a) [gone](missing.md) [root](/a.md) [dir](../sub/)\n\
+                 [out](../../a.md) ![typo](../pic.png)\n",
+            ),
+        ]);
+        let mut ctx = site.context(Config::default());
+        let (report, _) = site.build(&ctx);
+        assert!(report.broken_links.is_empty());
+
+        ctx.check_links = true;
+        let (report, _) = site.build(&ctx);
+        let b = site.src.path().join("sub/b.md");
+        assert_eq!(
+            report.broken_links,
+            [
+                (b.clone(), "../../a.html".into()),
+                (b.clone(), "../pic.png".into()),
+                (b.clone(), "../sub/".into()),
+                (b, "missing.html".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn html_notes() {
+        let site = testing::Site::new(&[
+            ("a.md", "# A\n"),
+            ("page.html", "<p>Hand-written.</p>"),
+            ("both.md", "# Both\n"),
+            ("both.html", "<p>Ignored.</p>"),
+        ]);
+        let (report, tree) = site.build(&site.context(Config::default()));
+        assert_eq!((report.notes, report.files), (2, 2));
+        assert_eq!(tree["page.html"], "<p>Hand-written.</p>");
+
+        let ctx = site.context(Config {
+            html_notes: Some(true),
+            ..Config::default()
+        });
+        let (report, tree) = site.build(&ctx);
+        assert_eq!((report.notes, report.files), (3, 1));
+        assert!(tree["a.html"].contains("<h1 id=\"a\">A"));
+        assert!(tree["page.html"].starts_with("<!doctype html>"));
+        assert!(tree["page.html"].contains("<main><p>Hand-written.</p></main>"));
+        assert!(tree["both.html"].contains("<h1 id=\"both\">Both"));
+        let both = fs::read_to_string(site.src.path().join("both.html")).unwrap();
+        assert_eq!(both, "<p>Ignored.</p>");
+
+        // The source is served wrapped, and only once.
+        let page = site.src.path().join("page.html");
+        assert!(matches!(
+            ctx.resolve_resource("page.html"),
+            Some(Resource::Note(p)) if p == page
+        ));
+        assert!(matches!(
+            ctx.resolve_resource("both.html"),
+            Some(Resource::Static(_))
+        ));
+        assert!(
+            ctx.render_note_to_string(&page)
+                .unwrap()
+                .contains("<main><p>Hand-written.")
+        );
+    }
+
+    #[test]
+    fn pretty_html_notes() {
+        let site = testing::Site::new(&[("index.md", "# Home\n"), ("page.html", "<p>Hi.</p>")]);
+        let ctx = site.context(Config {
+            html_notes: Some(true),
+            pretty_urls: Some(true),
+            ..Config::default()
+        });
+        let (_, tree) = site.build(&ctx);
+        assert!(tree["page/index.html"].contains("<main><p>Hi.</p></main>"));
+        assert!(!tree.contains_key("page.html"));
+        assert!(matches!(
+            ctx.resolve_resource("page/"),
+            Some(Resource::Note(_))
+        ));
+        assert!(ctx.resolve_resource("page.html").is_none());
+    }
+
+    #[test]
+    fn pretty_urls() {
+        let site = testing::Site::new(&[
+            ("index.md", "# Home\n\nSee [a](a.md) and [b](sub/b.md).\n"),
+            ("a.md", "# A\n\n![pic](pic.png) [home](index.md)\n"),
+            ("pic.png", "not really a PNG"),
+            ("sub/index.md", "# Sub\n\n[b](b.md)\n"),
+            ("sub/b.md", "# B\n\n[a](../a.md#top)\n"),
+            ("404.md", "# Lost\n"),
+        ]);
+        let ctx = site.context(Config {
+            pretty_urls: Some(true),
+            ..Config::default()
+        });
+        let (report, tree) = site.build(&ctx);
+        assert_eq!(report.notes, 5);
+
+        let pages: Vec<_> = tree
+            .keys()
+            .map(String::as_str)
+            .filter(|p| p.ends_with(".html"))
+            .collect();
+        assert_eq!(
+            pages,
+            [
+                "404.html",
+                "a/index.html",
+                "index.html",
+                "sub/b/index.html",
+                "sub/index.html"
+            ]
+        );
+        assert!(tree["index.html"].contains("href=\"a/\""));
+        assert!(tree["index.html"].contains("href=\"sub/b/\""));
+        assert!(tree["a/index.html"].contains("src=\"../pic.png\""));
+        assert!(tree["a/index.html"].contains("href=\"../\""));
+        assert!(tree["sub/index.html"].contains("href=\"b/\""));
+        assert!(tree["sub/b/index.html"].contains("href=\"../../a/#top\""));
+
+        assert_eq!(ctx.note_url(&site.src.path().join("sub/b.md")), "sub/b/");
+        assert_eq!(ctx.note_url(&site.src.path().join("sub/index.md")), "sub/");
+        assert_eq!(ctx.note_url(&site.src.path().join("index.md")), "");
+    }
+
+    #[test]
+    fn pretty_url_collision() {
+        let site = testing::Site::new(&[
+            ("foo.md", "# Plain\n"),
+            ("foo/index.md", "# Index\n"),
+            ("404.md", "# Lost\n"),
+        ]);
+        let mut ctx = site.context(Config {
+            pretty_urls: Some(true),
+            ..Config::default()
+        });
+        let (report, tree) = site.build(&ctx);
+        assert_eq!(report.notes, 2);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].0, site.src.path().join("foo.md"));
+        assert!(tree["foo/index.html"].contains("<title>Index</title>"));
+
+        // The listing links to each page where the build puts it.
+        let listing = ctx
+            .render_resource_to_string(Resource::Directory(site.src.path().to_path_buf()))
+            .unwrap();
+        assert!(listing.contains("href=\"404.html\""));
+        assert!(!listing.contains("href=\"404&#x2f;\""));
+
+        ctx.strict = true;
+        let dest = tempfile::tempdir().unwrap();
+        assert!(ctx.render_site(None, dest.path()).is_err());
+    }
+
+    #[test]
+    fn resolve_pretty_urls() {
+        let site = testing::Site::new(&[("a.md", "# A\n"), ("sub/index.md", "# Sub\n")]);
+        let ctx = site.context(Config {
+            pretty_urls: Some(true),
+            ..Config::default()
+        });
+        let note = |path| match ctx.resolve_resource(path) {
+            Some(Resource::Note(src_path)) => Some(src_path),
+            _ => None,
+        };
+        assert_eq!(note("a/"), Some(site.src.path().join("a.md")));
+        assert_eq!(note("a/index.html"), Some(site.src.path().join("a.md")));
+        assert_eq!(
+            note("sub/index.html"),
+            Some(site.src.path().join("sub/index.md"))
+        );
+        assert_eq!(note("a.html"), None);
+        assert_eq!(note("sub/index/"), None);
+    }
+
+    #[test]
+    fn new_tab_links() {
+        let source = "[a](https://notes.example.com/a.html) [b](https://example.org/) [c](c.md)\n";
+        let site = testing::Site::new(&[("index.md", source)]);
+        let ctx = site.context(Config {
+            new_tab_links: Some(true),
+            base_url: Some("https://notes.example.com/".into()),
+            ..Config::default()
+        });
+        let body = ctx.render_body(&site.src.path().join("index.md")).unwrap();
+        assert_eq!(
+            body,
+            "<p><a href=\"https://notes.example.com/a.html\">a</a> \
+             <a href=\"https://example.org/\" target=\"_blank\" rel=\"noopener noreferrer\">b</a> \
+             <a href=\"c.html\">c</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn canonical_urls() {
+        let site = testing::Site::new(&[
+            ("a.md", "# A\n"),
+            ("sub/index.md", "# Sub\n"),
+            ("sub/deep/b.md", "# B\n"),
+            ("404.md", "# Not Found\n"),
+        ]);
+        let canonical = |ctx: &Context, path: &str| {
+            let context = ctx.note_context(&site.src.path().join(path)).unwrap();
+            let url = context.get_attr("canonical_url").unwrap();
+            url.as_str().map(str::to_string)
+        };
+
+        let ctx = site.context(Config::default());
+        assert_eq!(canonical(&ctx, "sub/deep/b.md"), None);
+
+        let config: Config = toml::from_str("base_url = \"https://example.com/notes/\"").unwrap();
+        let ctx = site.context(config);
+        assert_eq!(
+            canonical(&ctx, "a.md").as_deref(),
+            Some("https://example.com/notes/a.html")
+        );
+        assert_eq!(
+            canonical(&ctx, "sub/index.md").as_deref(),
+            Some("https://example.com/notes/sub/index.html")
+        );
+        assert_eq!(
+            canonical(&ctx, "sub/deep/b.md").as_deref(),
+            Some("https://example.com/notes/sub/deep/b.html")
+        );
+        let page = ctx
+            .render_note_to_string(&site.src.path().join("sub/deep/b.md"))
+            .unwrap();
+        assert!(page.contains("<link rel=\"canonical\" href=\"https:&#x2f;&#x2f;example.com"));
+
+        // The 404 page shows up at other pages' URLs.
+        assert_eq!(canonical(&ctx, "404.md"), None);
+        let page = ctx
+            .render_note_to_string(&site.src.path().join("404.md"))
+            .unwrap();
+        assert!(!page.contains("rel=\"canonical\""));
+
+        let config = || -> Config {
+            toml::from_str("base_url = \"https://example.com\"\npretty_urls = true").unwrap()
+        };
+        let mirror = SiteConfig {
+            base_url: Some("https://mirror.example.org/".into()),
+            ..SiteConfig::from(&config())
+        };
+        let ctx = site.context(config());
+        assert_eq!(
+            canonical(&ctx, "sub/deep/b.md").as_deref(),
+            Some("https://example.com/sub/deep/b/")
+        );
+        let src_dir = site.src.path().to_str().unwrap();
+        let ctx = Context::new(src_dir, false, config(), Some(mirror));
+        assert_eq!(
+            canonical(&ctx, "sub/deep/b.md").as_deref(),
+            Some("https://mirror.example.org/sub/deep/b/")
+        );
+    }
+
+    #[test]
+    fn site_output_paths() {
+        let site = testing::Site::new(&[("a.md", "---\ndate: 2024-01-01\n---\n# A\n")]);
+        let config = || -> Config {
+            toml::from_str(
+                "base_url = \"https://example.com/\"\n\
+                 description = \"Lab notes.\"\n\
+                 feeds = [\"atom\", \"json\"]\n\
+                 atom_path = \"feeds/atom.xml\"\n\
+                 json_feed_path = \"feeds/feed.json\"\n\
+                 sitemap_path = \"maps/sitemap.xml\"\n",
+            )
+            .unwrap()
+        };
+        let src_dir = site.src.path().to_str().unwrap();
+        let lab = SiteConfig {
+            title: "The Lab".into(),
+            ..SiteConfig::from(&config())
+        };
+        let ctx = Context::new(src_dir, false, config(), Some(lab.clone()));
+        let (_, tree) = site.build(&ctx);
+
+        assert!(!tree.contains_key("atom.xml"));
+        assert!(!tree.contains_key("sitemap.xml"));
+        assert!(tree["maps/sitemap.xml"].contains("<loc>https://example.com/a.html</loc>"));
+        let atom = &tree["feeds/atom.xml"];
+        assert!(atom.contains("<title>The Lab</title>"));
+        assert!(atom.contains("<subtitle>Lab notes.</subtitle>"));
+        assert!(atom.contains("<link rel=\"self\" href=\"https://example.com/feeds/atom.xml\"/>"));
+        let json: serde_json::Value = serde_json::from_str(&tree["feeds/feed.json"]).unwrap();
+        assert_eq!(json["description"], "Lab notes.");
+        assert_eq!(json["feed_url"], "https://example.com/feeds/feed.json");
+        assert!(tree["a.html"].contains("example.com&#x2f;feeds&#x2f;atom.xml"));
+
+        let outside = SiteConfig {
+            sitemap_path: "../sitemap.xml".into(),
+            ..lab
+        };
+        let ctx = Context::new(src_dir, false, config(), Some(outside));
+        let dest = tempfile::tempdir().unwrap();
+        assert!(ctx.render_site(None, dest.path()).is_err());
+    }
+
+    #[test]
+    fn wikilinks() {
+        let source = "See [[Other Note|the other]] and [[sub/b#Part Two]].\n";
+        let site = testing::Site::new(&[("a.md", source)]);
+        let src_path = site.src.path().join("a.md");
+
+        let ctx = site.context(Config::default());
+        let body = ctx.render_body(&src_path).unwrap();
+        assert!(body.contains("[[Other Note|the other]]"));
+
+        let ctx = site.context(Config {
+            wikilinks: Some(true),
+            ..Config::default()
+        });
+        assert_eq!(
+            ctx.render_body(&src_path).unwrap(),
+            "<p>See <a href=\"Other%20Note.html\">the other</a> and \
+             <a href=\"sub/b.html#part-two\">sub/b#Part Two</a>.</p>\n"
+        );
+
+        let ctx = site.context(Config {
+            wikilinks: Some(true),
+            pretty_urls: Some(true),
+            ..Config::default()
+        });
+        assert!(
+            ctx.render_body(&src_path)
+                .unwrap()
+                .contains("<a href=\"../Other%20Note/\">")
+        );
+    }
+
+    #[test]
+    fn ascii_slugs() {
+        let site = testing::Site::new(&[("a.md", "# Café\n")]);
+        let ctx = site.context(Config::default());
+        let page = ctx
+            .render_note_to_string(&site.src.path().join("a.md"))
+            .unwrap();
+        assert!(page.contains("href=\"#café\""));
+
+        let ctx = site.context(Config {
+            ascii_slugs: Some(true),
+            ..Config::default()
+        });
+        let page = ctx
+            .render_note_to_string(&site.src.path().join("a.md"))
+            .unwrap();
+        assert!(page.contains("<h1 id=\"cafe\">"));
+        assert!(page.contains("href=\"#cafe\""));
+    }
+
+    #[test]
+    fn slug_config() {
+        let site = testing::Site::new(&[("a.md", "# ¡Hello, World!\n")]);
+        let config: Config =
+            toml::from_str("slug_separator = \"_\"\nslug_lowercase = false").unwrap();
+        let ctx = site.context(config);
+        let body = ctx.render_body(&site.src.path().join("a.md")).unwrap();
+        assert!(body.starts_with("<h1 id=\"Hello_World\">"));
+
+        assert!(toml::from_str::<Config>("slug_separator = \"--\"").is_err());
+        assert!(toml::from_str::<Config>("slug_separator = \"x\"").is_err());
+        assert!(toml::from_str::<Config>("slug_separator = \" \"").is_err());
+    }
+
+    #[test]
+    fn id_prefix_config() {
+        let site = testing::Site::new(&[("a.md", "# Intro\n\n## Setup {#setup}\n\n[[#Intro]]\n")]);
+        let config: Config = toml::from_str("id_prefix = \"n-\"\nwikilinks = true").unwrap();
+        let ctx = site.context(config);
+        let doc = ctx.read_note(&site.src.path().join("a.md")).unwrap();
+        let ids: Vec<_> = doc.toc.iter().map(|e| e.id.as_deref()).collect();
+        assert_eq!(ids, [Some("n-intro"), Some("setup")]);
+        assert!(doc.body.contains("<a href=\"#n-intro\">"));
+
+        let config: Config =
+            toml::from_str("id_prefix = \"n-\"\nprefix_explicit_ids = true").unwrap();
+        let ctx = site.context(config);
+        let doc = ctx.read_note(&site.src.path().join("a.md")).unwrap();
+        let ids: Vec<_> = doc.toc.iter().map(|e| e.id.as_deref()).collect();
+        assert_eq!(ids, [Some("n-intro"), Some("n-setup")]);
+        assert!(doc.body.contains("<h2 id=\"n-setup\">"));
+    }
+
+    #[test]
+    fn build_manifest() {
+        let site = testing::Site::new(&[
+            ("a.md", "# A\n\n## Part\n"),
+            ("sub/pic.png", "not really a PNG"),
+        ]);
+        let mut ctx = site.context(Config {
+            toc_fragments: Some(true),
+            ..Config::default()
+        });
+        let (report, _) = site.build(&ctx);
+        assert!(report.manifest.entries.is_empty());
+
+        ctx.manifest = true;
+        let (report, tree) = site.build(&ctx);
+        let json: serde_json::Value = serde_json::from_str(&report.manifest.to_json()).unwrap();
+        let entries = json["entries"].as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+
+        assert_eq!(entries[0]["source"], "a.md");
+        assert_eq!(entries[0]["kind"], "note");
+        let outputs = entries[0]["outputs"].as_array().unwrap();
+        let paths: Vec<_> = outputs
+            .iter()
+            .map(|o| o["path"].as_str().unwrap())
+            .collect();
+        assert_eq!(paths, ["a.toc.html", "a.html"]);
+        let page_hash = format!("{:016x}", manifest::hash_bytes(tree["a.html"].as_bytes()));
+        assert_eq!(outputs[1]["hash"], page_hash.as_str());
+
+        assert_eq!(entries[1]["source"], "sub/pic.png");
+        assert_eq!(entries[1]["kind"], "static");
+        assert_eq!(entries[1]["outputs"][0]["path"], "sub/pic.png");
+        let pic_hash = format!("{:016x}", manifest::hash_bytes(b"not really a PNG"));
+        assert_eq!(entries[1]["outputs"][0]["hash"], pic_hash.as_str());
+    }
+
+    #[test]
+    fn render_serial_and_parallel() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..3 {
+            fs::write(dir.path().join(format!("{i}.md")), format!("# Note {i}")).unwrap();
+        }
+        let dest = tempfile::tempdir().unwrap();
+        for threshold in [1, 10] {
+            let config = Config {
+                parallel_threshold: Some(threshold),
+                ..Config::default()
+            };
+            let ctx = Context::new(dir.path().to_str().unwrap(), false, config, None);
+            let dest_dir = dest.path().join(threshold.to_string());
+            ctx.render_site(None, &dest_dir).unwrap();
+            for i in 0..3 {
+                let page = fs::read_to_string(dest_dir.join(format!("{i}.html"))).unwrap();
+                assert!(page.contains(&format!("<title>Note {i}</title>")));
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn unreadable_directory() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let locked = dir.path().join("locked");
+        fs::create_dir(&locked).unwrap();
+        fs::write(locked.join("secret.md"), "# hi").unwrap();
+        fs::write(dir.path().join("note.md"), "# hi").unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+
+        // Privileged users can read the directory anyway, so there's nothing
+        // to test.
+        if fs::read_dir(&locked).is_ok() {
+            return;
+        }
+
+        let dest = tempfile::tempdir().unwrap();
+        let mut ctx = Context::new(dir.path().to_str().unwrap(), false, Config::default(), None);
+        let report = ctx.render_site(None, &dest.path().join("out")).unwrap();
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].0, locked);
+        assert!(dest.path().join("out/note.html").is_file());
+
+        ctx.strict = true;
+        assert!(ctx.render_site(None, &dest.path().join("out")).is_err());
+
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn min_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("image.png"), "").unwrap();
+        let dest = tempfile::tempdir().unwrap();
+
+        let ctx = Context::new(dir.path().to_str().unwrap(), false, Config::default(), None);
+        assert!(ctx.render_site(None, dest.path()).is_err());
+
+        let config = Config {
+            min_pages: Some(0),
+            ..Config::default()
+        };
+        let ctx = Context::new(dir.path().to_str().unwrap(), false, config, None);
+        let report = ctx.render_site(None, dest.path()).unwrap();
+        assert_eq!(report.notes, 0);
+        assert_eq!(report.files, 1);
+    }
+
+    #[test]
+    fn note_template_override() {
+        let dir = tempfile::tempdir().unwrap();
+        for sub in ["sub/deeper", "other"] {
+            fs::create_dir_all(dir.path().join(sub)).unwrap();
+        }
+        fs::write(dir.path().join("_note.html"), "root: {{ title }}").unwrap();
+        fs::write(dir.path().join("sub/_note.html"), "sub: {{ title }}").unwrap();
+        for note in ["a.md", "sub/b.md", "sub/deeper/c.md", "other/d.md"] {
+            fs::write(dir.path().join(note), "# Hi\n").unwrap();
+        }
+        let ctx = Context::new(dir.path().to_str().unwrap(), false, Config::default(), None);
+
+        let render = |note: &str| ctx.render_note_to_string(&dir.path().join(note)).unwrap();
+        assert_eq!(render("a.md"), "root: Hi");
+        assert_eq!(render("sub/b.md"), "sub: Hi");
+        assert_eq!(render("sub/deeper/c.md"), "sub: Hi");
+        assert_eq!(render("other/d.md"), "root: Hi");
+    }
+
+    #[test]
+    fn reload_changed_templates() {
+        let site = testing::Site::new(&[("_note.html", "old: {{ title }}"), ("a.md", "# Hi\n")]);
+        let ctx = site.context(Config::default());
+        let render = |ctx: &Context| {
+            ctx.render_note_to_string(&site.src.path().join("a.md"))
+                .unwrap()
+        };
+        assert_eq!(render(&ctx), "old: Hi");
+        assert!(!ctx.templates_changed());
+
+        // Nothing changed, so the cached template stays.
+        ctx.reload_templates().unwrap();
+        assert_eq!(render(&ctx), "old: Hi");
+
+        let path = site.src.path().join("_note.html");
+        fs::write(&path, "new: {{ title }}").unwrap();
+        let later = SystemTime::now() + std::time::Duration::from_secs(10);
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert!(ctx.templates_changed());
+        ctx.reload_templates().unwrap();
+        assert!(!ctx.templates_changed());
+        assert_eq!(render(&ctx), "new: Hi");
+    }
+
+    #[test]
+    fn concurrent_renders() {
+        let site = testing::Site::new(&[("_note.html", "{{ title }}"), ("a.md", "# Hi\n")]);
+        let ctx = Arc::new(site.context(Config::default()));
+        let path = site.src.path().join("a.md");
+
+        // Render from several threads at once, one of which also keeps
+        // reloading the templates.
+        let (tx, rx) = std::sync::mpsc::channel();
+        for i in 0..4 {
+            let (ctx, path, tx) = (ctx.clone(), path.clone(), tx.clone());
+            std::thread::spawn(move || {
+                for _ in 0..20 {
+                    if i == 0 {
+                        ctx.load_templates().unwrap();
+                    }
+                    assert_eq!(ctx.render_note_to_string(&path).unwrap(), "Hi");
+                }
+                tx.send(()).unwrap();
+            });
+        }
+        for _ in 0..4 {
+            rx.recv_timeout(std::time::Duration::from_secs(30))
+                .expect("renders did not finish");
+        }
+    }
+
+    #[test]
+    fn future_notes() {
+        let src = tempfile::tempdir().unwrap();
+        let dest = tempfile::tempdir().unwrap();
+        let tomorrow = jiff::Zoned::now().date().tomorrow().unwrap();
+        fs::write(src.path().join("now.md"), "# Now\n").unwrap();
+        let source = format!("---\ndate: {tomorrow}\n---\n# Later\n");
+        fs::write(src.path().join("later.md"), source).unwrap();
+        let mut ctx = Context::new(src.path().to_str().unwrap(), false, Config::default(), None);
+
+        let report = ctx.render_site(None, dest.path()).unwrap();
+        assert_eq!(report.notes, 1);
+        assert!(dest.path().join("now.html").exists());
+        assert!(!dest.path().join("later.html").exists());
+
+        ctx.future = true;
+        let report = ctx.render_site(None, dest.path()).unwrap();
+        assert_eq!(report.notes, 2);
+        assert!(dest.path().join("later.html").exists());
+    }
+
+    #[test]
+    fn drafts_excluded() {
+        let site = testing::Site::new(&[
+            ("note.md", "# Note\n"),
+            ("draft.md", "---\ndraft: true\n---\n# Draft\n"),
+        ]);
+        let ctx = site.context(Config::default());
+        let (report, tree) = site.build(&ctx);
+        assert_eq!(report.notes, 1);
+        assert!(tree.contains_key("note.html"));
+        assert!(!tree.contains_key("draft.html"));
+    }
+
+    #[test]
+    fn drafts_included() {
+        let site = testing::Site::new(&[
+            ("note.md", "# Note\n"),
+            ("draft.md", "---\ndraft: true\n---\n# Draft\n"),
+        ]);
+        let mut ctx = site.context(Config::default());
+        ctx.drafts = true;
+        let (report, tree) = site.build(&ctx);
+        assert_eq!(report.notes, 2);
+        assert!(tree["draft.html"].contains("noindex,nofollow"));
+    }
+
+    #[test]
+    fn toc_fragments() {
+        let src = tempfile::tempdir().unwrap();
+        let dest = tempfile::tempdir().unwrap();
+        fs::write(src.path().join("a.md"), "# Title\n\n## Intro\n").unwrap();
+        fs::write(src.path().join("b.md"), "No headings.\n").unwrap();
+        let config = Config {
+            toc_fragments: Some(true),
+            ..Config::default()
+        };
+        let ctx = Context::new(src.path().to_str().unwrap(), false, config, None);
+        ctx.render_site(None, dest.path()).unwrap();
+
+        let toc = fs::read_to_string(dest.path().join("a.toc.html")).unwrap();
+        assert!(toc.starts_with("<ul class=\"toc\">"));
+        assert!(toc.contains("href=\"#title\""));
+        assert!(toc.contains("href=\"#intro\""));
+        assert!(!dest.path().join("b.toc.html").exists());
+    }
+
+    #[test]
+    fn toc_levels_config() {
+        let site = testing::Site::new(&[("a.md", "# Title\n\n## Intro\n\n#### Aside\n")]);
+        let ctx = site.context(toml::from_str("toc_levels = [2, 3]").unwrap());
+        let (_, tree) = site.build(&ctx);
+        let page = &tree["a.html"];
+        assert!(page.contains("<title>Title</title>"));
+        let toc = &page[page.find("<ul class=\"toc\">").unwrap()..page.find("</nav>").unwrap()];
+        assert!(toc.contains("<a href=\"#intro\">Intro</a>"));
+        assert!(!toc.contains("#title"));
+        assert!(!toc.contains("#aside"));
+        assert!(page.contains("<h4 id=\"aside\">Aside"));
+
+        for bad in ["[0, 2]", "[2, 7]", "[3, 2]", "[2]"] {
+            assert!(toml::from_str::<Config>(&format!("toc_levels = {bad}")).is_err());
+        }
+    }
+
+    #[test]
+    fn related_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        let notes = [
+            ("a.md", "[x, y]"),
+            ("sub/b.md", "[x, y, z]"),
+            ("c.md", "[y]"),
+            ("d.md", "[w]"),
+            ("e.md", "[]"),
+        ];
+        for (name, tags) in notes {
+            fs::write(dir.path().join(name), format!("---\ntags: {tags}\n---\n")).unwrap();
+        }
+        let config = Config {
+            related_count: Some(5),
+            ..Config::default()
+        };
+        let ctx = Context::new(dir.path().to_str().unwrap(), false, config, None);
+
+        let related = |name: &str| {
+            let context = ctx.note_context(&dir.path().join(name)).unwrap();
+            let related = context.get_attr("related").unwrap();
+            related
+                .try_iter()
+                .unwrap()
+                .map(|page| page.get_attr("href").unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(related("a.md"), ["sub/b.html", "c.html"]);
+        assert_eq!(related("sub/b.md"), ["../a.html", "../c.html"]);
+        assert!(related("d.md").is_empty());
+        assert!(related("e.md").is_empty());
+
+        // The index is kept until the notes change.
+        fs::write(dir.path().join("e.md"), "---\ntags: [w]\n---\n").unwrap();
+        assert!(related("d.md").is_empty());
+        ctx.forget_notes();
+        assert_eq!(related("d.md"), ["e.html"]);
+    }
+
+    #[test]
+    fn related_future_notes() {
+        let site = testing::Site::new(&[
+            ("a.md", "---\ntags: [x]\n---\n# A\n"),
+            ("b.md", "---\ntags: [x]\ndate: 2999-01-01\n---\n# B\n"),
+        ]);
+        let mut ctx = site.context(Config {
+            related_count: Some(5),
+            ..Config::default()
+        });
+        let (_, tree) = site.build(&ctx);
+        assert!(!tree["a.html"].contains("b.html"));
+
+        ctx.future = true;
+        let (_, tree) = site.build(&ctx);
+        assert!(tree["a.html"].contains("b.html"));
+    }
+
+    #[test]
+    fn failed_render_keeps_output() {
+        let site = testing::Site::new(&[
+            ("a.md", "---\ntemplate: broken.html\n---\n# A\n"),
+            ("static.txt", "new"),
+        ]);
+        let templates = DynamicAssets::new(std::collections::HashMap::from([(
+            "broken.html".to_string(),
+            "<p>{{ title }}</p>{{ no_such_function() }}".to_string(),
+        )]));
+        let ctx = Context::with_templates(
+            site.src.path().to_str().unwrap(),
+            false,
+            Config::default(),
+            templates,
+        )
+        .unwrap();
+
+        let dest = tempfile::tempdir().unwrap();
+        let dest_path = dest.path().join("a.html");
+        fs::write(&dest_path, "old").unwrap();
+        assert!(
+            ctx.render_note_to_file(&site.src.path().join("a.md"), &dest_path, &[])
+                .is_err()
+        );
+        assert_eq!(
+            testing::read_tree(dest.path()),
+            testing::Tree::from([("a.html".to_string(), "old".to_string())])
+        );
+
+        // Successful copies replace the old file, and leave nothing else.
+        let static_path = dest.path().join("static.txt");
+        fs::write(&static_path, "old").unwrap();
+        hard_link_or_copy(&site.src.path().join("static.txt"), &static_path, true).unwrap();
+        assert_eq!(fs::read_to_string(&static_path).unwrap(), "new");
+        assert_eq!(testing::read_tree(dest.path()).len(), 2);
+    }
+
+    #[test]
+    fn keep_dest() {
+        let site = testing::Site::new(&[("a.md", "# A\n"), ("pic.png", "new")]);
+        let mut ctx = site.context(Config {
+            git: Some(false),
+            ..Config::default()
+        });
+        let dest = tempfile::tempdir().unwrap();
+        fs::write(dest.path().join("CNAME"), "notes.example.com").unwrap();
+        fs::write(dest.path().join("a.html"), "old").unwrap();
+        fs::write(dest.path().join("pic.png"), "old").unwrap();
+
+        // Outputs are replaced, and other files stay.
+        ctx.keep_dest = true;
+        assert!(
+            !ctx.plan_site(dest.path())
+                .unwrap()
+                .contains(&PlannedAction::Remove(dest.path().into()))
+        );
+        ctx.render_site(None, dest.path()).unwrap();
+        let tree = testing::read_tree(dest.path());
+        assert_eq!(tree["CNAME"], "notes.example.com");
+        assert!(tree["a.html"].contains("<title>A</title>"));
+        assert_eq!(tree["pic.png"], "new");
+
+        ctx.keep_dest = false;
+        ctx.render_site(None, dest.path()).unwrap();
+        assert!(!dest.path().join("CNAME").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks() {
+        use std::os::unix::fs::symlink;
+        let site = testing::Site::new(&[("a.md", "# A\n"), ("dir/pic.png", "picture")]);
+        let outside = testing::Site::new(&[("secret.txt", "secret")]);
+        let src = site.src.path();
+        symlink("..", src.join("dir/loop")).unwrap();
+        symlink("dir/pic.png", src.join("alias.png")).unwrap();
+        symlink(outside.src.path(), src.join("escape")).unwrap();
+        symlink(
+            outside.src.path().join("secret.txt"),
+            src.join("secret.txt"),
+        )
+        .unwrap();
+        symlink("missing.md", src.join("broken.md")).unwrap();
+        let ctx = site.context(Config {
+            git: Some(false),
+            ..Config::default()
+        });
+
+        // The walk finishes, reporting the loop, and copies what links point
+        // to only if it's in the source directory.
+        let (report, tree) = site.build(&ctx);
+        assert!(report.failures.is_empty());
+        assert!(
+            report
+                .errors
+                .iter()
+                .any(|(path, _)| path.ends_with("dir/loop"))
+        );
+        let style = &hashed_assets(&ctx.templates())["style.css"];
+        assert_eq!(
+            tree.keys().collect::<Vec<_>>(),
+            ["a.html", "alias.png", "dir/", "dir/pic.png", style]
+        );
+        assert_eq!(tree["alias.png"], "picture");
+
+        assert_eq!(
+            ctx.resolve_resource("alias.png"),
+            Some(Resource::Static(src.join("alias.png")))
+        );
+        assert!(ctx.resolve_resource("escape/secret.txt").is_none());
+        assert!(ctx.resolve_resource("secret.txt").is_none());
+        assert!(ctx.resolve_resource("escape").is_none());
+    }
+
+    #[test]
+    fn render_over_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let src_path = dir.path().join("index.md");
+        fs::write(&src_path, "# Hi\n").unwrap();
+        let ctx = Context::new(dir.path().to_str().unwrap(), false, Config::default(), None);
+
+        assert!(ctx.render_note_to_file(&src_path, &src_path, &[]).is_err());
+        let alias = dir.path().join(".").join("index.md");
+        assert!(ctx.render_note_to_file(&src_path, &alias, &[]).is_err());
+        assert_eq!(fs::read_to_string(&src_path).unwrap(), "# Hi\n");
+
+        // Building into a directory that contains the source is an error, too.
+        assert!(ctx.render_site(None, dir.path()).is_err());
+        assert!(ctx.render_site(None, &dir.path().join("..")).is_err());
+        assert!(src_path.exists());
+    }
+
+    #[test]
+    fn count_failures() {
+        let site =
+            testing::Site::new(&[("good.md", "# Good\n"), ("bad.md", "---\ndraft: [\n---\n")]);
+        let ctx = site.context(Config::default());
+        let (report, tree) = site.build(&ctx);
+        assert_eq!(report.notes, 1);
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].0, site.src.path().join("bad.md"));
+        assert!(tree.contains_key("good.html"));
+    }
+
+    #[test]
+    fn strict_failure() {
+        let site =
+            testing::Site::new(&[("good.md", "# Good\n"), ("bad.md", "---\ndraft: [\n---\n")]);
+        let mut ctx = site.context(Config::default());
+        ctx.strict = true;
+        let dest = tempfile::tempdir().unwrap();
+        let err = ctx.render_site(None, dest.path()).unwrap_err();
+        assert!(err.to_string().contains("bad.md"));
+    }
+
+    #[test]
+    fn render_body() {
+        let dir = tempfile::tempdir().unwrap();
+        let src_path = dir.path().join("a.md");
+        fs::write(&src_path, "---\ndraft: true\n---\n# Hi\n\nSome *text*.\n").unwrap();
+        let ctx = Context::new(dir.path().to_str().unwrap(), false, Config::default(), None);
+
+        let body = ctx.render_body(&src_path).unwrap();
+        assert!(body.starts_with("<h1 id=\"hi\">"));
+        assert!(body.contains("<p>Some <em>text</em>.</p>"));
+        assert!(!body.contains("<html"));
+        assert!(!body.contains("<nav>"));
+        assert!(!body.contains("draft"));
+    }
+
+    #[test]
+    fn plan_site() {
+        let site = testing::Site::new(&[
+            ("a.md", "# A\n"),
+            ("sub/b.md", "# B\n"),
+            ("pic.png", "not really a PNG"),
+            ("draft.md", "---\ndraft: true\n---\n# Draft\n"),
+        ]);
+        let dest = tempfile::tempdir().unwrap();
+        let dest_dir = dest.path().join("_site");
+        fs::create_dir(&dest_dir).unwrap();
+        fs::write(dest_dir.join("stale.html"), "old").unwrap();
+        let config: Config = toml::from_str("search_index = \"search.json\"").unwrap();
+        let ctx = site.context(config);
+
+        let src_before = testing::read_tree(site.src.path());
+        let dest_before = testing::read_tree(&dest_dir);
+        let mut plan = ctx.plan_site(&dest_dir).unwrap();
+        assert_eq!(testing::read_tree(site.src.path()), src_before);
+        assert_eq!(testing::read_tree(&dest_dir), dest_before);
+
+        let src = site.src.path();
+        plan.sort();
+        assert_eq!(
+            plan,
+            [
+                PlannedAction::Remove(dest_dir.clone()),
+                PlannedAction::CreateDir(dest_dir.clone()),
+                PlannedAction::CreateDir(dest_dir.join("sub")),
+                PlannedAction::Copy {
+                    from: src.join("pic.png"),
+                    to: dest_dir.join("pic.png"),
+                },
+                PlannedAction::Render {
+                    from: src.join("a.md"),
+                    to: dest_dir.join("a.html"),
+                },
+                PlannedAction::Render {
+                    from: src.join("sub/b.md"),
+                    to: dest_dir.join("sub/b.html"),
+                },
+                PlannedAction::Write(dest_dir.join("search.json")),
+                PlannedAction::Write(dest_dir.join(&hashed_assets(&ctx.templates())["style.css"])),
+            ]
+        );
+    }
+
+    #[test]
+    fn clean() {
+        let src = tempfile::tempdir().unwrap();
+        let dest = tempfile::tempdir().unwrap();
+        let dest_dir = dest.path().join("_site");
+        fs::write(src.path().join("a.md"), "# A\n").unwrap();
+        let ctx = Context::new(src.path().to_str().unwrap(), false, Config::default(), None);
+
+        ctx.render_site(None, &dest_dir).unwrap();
+        assert!(dest_dir.join("a.html").exists());
+        ctx.clean(&dest_dir).unwrap();
+        assert!(!dest_dir.exists());
+
+        // Cleaning again is fine.
+        ctx.clean(&dest_dir).unwrap();
+    }
+
+    #[test]
+    fn validate_templates() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut ctx = Context::new(dir.path().to_str().unwrap(), false, Config::default(), None);
+        assert!(ctx.validate_templates().is_ok());
+
+        Arc::get_mut(ctx.tmpls.get_mut().unwrap())
+            .unwrap()
+            .add_template("note.html", "{{ title }} {{ bogus }}")
+            .unwrap();
+        assert!(ctx.validate_templates().is_err());
     }
 }
@@ -0,0 +1,150 @@
+//! A small CSS minifier for static stylesheets.
+
+/// Characters that never need whitespace around them.
+const PUNCTUATION: &[char] = &['{', '}', ';', ','];
+
+/// Minify a stylesheet by removing comments and collapsing whitespace. The
+/// contents of strings and `url(...)` values are left intact.
+pub fn minify(css: &str) -> String {
+    let mut out = String::with_capacity(css.len());
+    let mut space = false;
+    let mut rest = css;
+    while let Some(c) = rest.chars().next() {
+        let len = if let Some(after) = rest.strip_prefix("/*") {
+            // Comments separate tokens just like whitespace does.
+            space = true;
+            match after.find("*/") {
+                Some(end) => end + 4,
+                None => rest.len(),
+            }
+        } else if c.is_whitespace() {
+            space = true;
+            c.len_utf8()
+        } else {
+            let len = if c == '"' || c == '\'' {
+                string_len(rest, c)
+            } else if is_unquoted_url(&out, rest) {
+                rest.find(')').map_or(rest.len(), |end| end + 1)
+            } else {
+                c.len_utf8()
+            };
+            // Space before a declaration's colon never matters, but in a
+            // selector like `a :hover` it does.
+            if c == ':' && in_declaration(rest) {
+                space = false;
+            }
+            push_token(&mut out, &rest[..len], space);
+            space = false;
+            len
+        };
+        rest = &rest[len..];
+    }
+    out
+}
+
+/// Append a token to the output, with a single space before it if there was
+/// whitespace in the source and the space might matter.
+fn push_token(out: &mut String, token: &str, space: bool) {
+    let first = token.chars().next();
+    if let (true, Some(last), Some(first)) = (space, out.chars().last(), first)
+        && !PUNCTUATION.contains(&last)
+        && !matches!(last, ':' | '(')
+        && !PUNCTUATION.contains(&first)
+        && first != ')'
+    {
+        out.push(' ');
+    }
+
+    // The last declaration in a block doesn't need a semicolon.
+    if token == "}" && out.ends_with(';') {
+        out.pop();
+    }
+    out.push_str(token);
+}
+
+/// The length of the quoted string at the start of `s`, including its quotes.
+fn string_len(s: &str, quote: char) -> usize {
+    let mut chars = s.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if c == quote {
+            return i + c.len_utf8();
+        }
+    }
+    s.len()
+}
+
+/// Is the colon at the start of `rest` part of a declaration rather than a
+/// selector? A selector ends by opening a block.
+fn in_declaration(rest: &str) -> bool {
+    rest.find(['{', ';', '}'])
+        .is_none_or(|i| !rest[i..].starts_with('{'))
+}
+
+/// Does `rest` start with a `url(...)` token whose argument is not a quoted
+/// string? Such arguments are copied verbatim.
+fn is_unquoted_url(out: &str, rest: &str) -> bool {
+    let is_url = rest
+        .get(..4)
+        .is_some_and(|p| p.eq_ignore_ascii_case("url("));
+    let in_ident = out
+        .chars()
+        .last()
+        .is_some_and(|c| c.is_alphanumeric() || c == '-' || c == '_');
+    is_url && !in_ident && !rest[4..].trim_start().starts_with(['"', '\''])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::minify;
+
+    #[test]
+    fn comments_and_whitespace() {
+        let css = "/* Page layout. */\nbody {\n    margin: 0 auto;  /* centered */\n    font: 1em/1.4 serif;\n}\n\na:hover,\na:focus { color : red ; }\n";
+        let min = minify(css);
+        assert_eq!(
+            min,
+            "body{margin:0 auto;font:1em/1.4 serif}a:hover,a:focus{color:red}"
+        );
+        assert!(min.len() < css.len());
+        assert_eq!(min.matches('{').count(), min.matches('}').count());
+    }
+
+    #[test]
+    fn selectors_and_values() {
+        assert_eq!(minify("nav  :is(a, b) > li {}"), "nav :is(a,b) > li{}");
+        assert_eq!(
+            minify("@media print { p :first-child { margin : 0 } }"),
+            "@media print{p :first-child{margin:0}}"
+        );
+        assert_eq!(
+            minify("p { width: calc(100% - 2em) }"),
+            "p{width:calc(100% - 2em)}"
+        );
+        assert_eq!(
+            minify("@media (min-width: 600px) {\n  p { margin: 0 }\n}"),
+            "@media (min-width:600px){p{margin:0}}"
+        );
+    }
+
+    #[test]
+    fn strings_and_urls() {
+        assert_eq!(
+            minify("a::after { content: \"/* not  a comment */\"; }"),
+            "a::after{content:\"/* not  a comment */\"}"
+        );
+        assert_eq!(
+            minify("q { quotes: '\\'  ' \"x\"; }"),
+            "q{quotes:'\\'  ' \"x\"}"
+        );
+        assert_eq!(
+            minify("div { background: url(img/a//b.png)  no-repeat; }"),
+            "div{background:url(img/a//b.png) no-repeat}"
+        );
+        assert_eq!(
+            minify("div { background: URL( \"a b.png\" ); }"),
+            "div{background:URL(\"a b.png\")}"
+        );
+    }
+}
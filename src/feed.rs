@@ -0,0 +1,332 @@
+use crate::core::{Context, RenderedNote, SiteConfig, output_path};
+use anyhow::Result;
+use jiff::civil::Date;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// A file format for the site's feed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedFormat {
+    /// An [Atom](https://www.rfc-editor.org/rfc/rfc4287) feed.
+    Atom,
+    /// A [JSON Feed](https://www.jsonfeed.org/version/1.1/).
+    Json,
+}
+
+impl FeedFormat {
+    /// The MIME type for the feed, for linking to it.
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            FeedFormat::Atom => "application/atom+xml",
+            FeedFormat::Json => "application/feed+json",
+        }
+    }
+
+    /// Write a feed in this format containing `entries`.
+    pub fn write<W: Write>(
+        self,
+        site: &SiteConfig,
+        base_url: &str,
+        entries: &[FeedEntry],
+        dest: &mut W,
+    ) -> io::Result<()> {
+        match self {
+            FeedFormat::Atom => write_atom(site, base_url, entries, dest),
+            FeedFormat::Json => write_json(site, base_url, entries, dest),
+        }
+    }
+}
+
+/// A note's entry in the feed.
+#[derive(Debug)]
+pub struct FeedEntry {
+    pub title: String,
+    pub url: String,
+    pub date: Date,
+    pub content: String,
+    /// The note's excerpt, as HTML.
+    pub summary: String,
+}
+
+/// Gather feed entries for the published notes that the build rendered,
+/// newest first.
+///
+/// A note's date comes from its front matter if available. Otherwise, we use
+/// the date of its last git commit, or failing that, its modification time.
+pub fn collect_entries(
+    ctx: &Context,
+    base_url: &str,
+    notes: &[RenderedNote],
+) -> Result<Vec<FeedEntry>> {
+    let mut entries = vec![];
+    for note in notes {
+        let date = match note.date {
+            Some(date) => date,
+            None => last_modified(ctx, &note.src_path)?,
+        };
+        entries.push(FeedEntry {
+            title: note.title.clone(),
+            url: absolute_url(base_url, &ctx.note_url(&note.src_path)),
+            date,
+            content: note.body.clone(),
+            summary: note.excerpt.clone(),
+        });
+    }
+
+    // Newest first. Break ties by URL to keep the order deterministic.
+    entries.sort_by(|a, b| b.date.cmp(&a.date).then_with(|| a.url.cmp(&b.url)));
+    Ok(entries)
+}
+
+/// Get the date when a note was last changed: the date of its last git commit,
+/// or failing that, its modification time. Feeds use this for notes that don't
+/// specify a date in their front matter.
+pub fn last_modified(ctx: &Context, src_path: &Path) -> Result<Date> {
+    if let Some(commit) = ctx.last_commit(src_path)
+        && let Ok(info) = commit.info()
+        && let Ok(date) = info.date.parse()
+    {
+        return Ok(date);
+    }
+    let mtime = fs::metadata(src_path)?.modified()?;
+    let timestamp = jiff::Timestamp::try_from(mtime)?;
+    Ok(timestamp.to_zoned(jiff::tz::TimeZone::UTC).date())
+}
+
+/// Join a site-relative URL path onto the site's base URL.
+pub fn absolute_url(base_url: &str, path: &str) -> String {
+    format!("{}/{}", base_url.trim_end_matches('/'), path)
+}
+
+/// Write an Atom feed containing `entries`, with the title, description, and
+/// location from `site`.
+pub fn write_atom<W: Write>(
+    site: &SiteConfig,
+    base_url: &str,
+    entries: &[FeedEntry],
+    dest: &mut W,
+) -> io::Result<()> {
+    let updated = entries.iter().map(|e| e.date).max();
+
+    writeln!(dest, r#"<?xml version="1.0" encoding="utf-8"?>"#)?;
+    writeln!(dest, r#"<feed xmlns="http://www.w3.org/2005/Atom">"#)?;
+    writeln!(dest, "  <title>{}</title>", escape_xml(&site.title))?;
+    if let Some(description) = &site.description {
+        writeln!(dest, "  <subtitle>{}</subtitle>", escape_xml(description))?;
+    }
+    writeln!(dest, "  <id>{}</id>", escape_xml(base_url))?;
+    writeln!(dest, r#"  <link href="{}"/>"#, escape_xml(base_url))?;
+    let self_url = absolute_url(base_url, &site.atom_path);
+    writeln!(
+        dest,
+        r#"  <link rel="self" href="{}"/>"#,
+        escape_xml(&self_url)
+    )?;
+    if let Some(updated) = updated {
+        writeln!(dest, "  <updated>{updated}T00:00:00Z</updated>")?;
+    }
+    for entry in entries {
+        writeln!(dest, "  <entry>")?;
+        writeln!(dest, "    <title>{}</title>", escape_xml(&entry.title))?;
+        writeln!(dest, "    <id>{}</id>", escape_xml(&entry.url))?;
+        writeln!(dest, r#"    <link href="{}"/>"#, escape_xml(&entry.url))?;
+        writeln!(dest, "    <updated>{}T00:00:00Z</updated>", entry.date)?;
+        if !entry.summary.is_empty() {
+            writeln!(
+                dest,
+                r#"    <summary type="html">{}</summary>"#,
+                escape_xml(&entry.summary)
+            )?;
+        }
+        writeln!(
+            dest,
+            r#"    <content type="html">{}</content>"#,
+            escape_xml(&entry.content)
+        )?;
+        writeln!(dest, "  </entry>")?;
+    }
+    writeln!(dest, "</feed>")?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct JsonFeed<'a> {
+    version: &'static str,
+    title: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<&'a str>,
+    home_page_url: &'a str,
+    feed_url: String,
+    items: Vec<JsonFeedItem<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonFeedItem<'a> {
+    id: &'a str,
+    url: &'a str,
+    title: &'a str,
+    content_html: &'a str,
+    date_published: String,
+}
+
+/// Write a JSON Feed containing `entries`, with the title, description, and
+/// location from `site`.
+pub fn write_json<W: Write>(
+    site: &SiteConfig,
+    base_url: &str,
+    entries: &[FeedEntry],
+    dest: &mut W,
+) -> io::Result<()> {
+    let feed = JsonFeed {
+        version: "https://jsonfeed.org/version/1.1",
+        title: &site.title,
+        description: site.description.as_deref(),
+        home_page_url: base_url,
+        feed_url: absolute_url(base_url, &site.json_feed_path),
+        items: entries
+            .iter()
+            .map(|entry| JsonFeedItem {
+                id: &entry.url,
+                url: &entry.url,
+                title: &entry.title,
+                content_html: &entry.content,
+                date_published: format!("{}T00:00:00Z", entry.date),
+            })
+            .collect(),
+    };
+    serde_json::to_writer_pretty(&mut *dest, &feed)?;
+    writeln!(dest)
+}
+
+/// Generate the site's feeds, in each configured format, in the destination
+/// directory.
+pub fn render_feed(
+    ctx: &Context,
+    base_url: &str,
+    notes: &[RenderedNote],
+    dest_dir: &Path,
+) -> Result<()> {
+    let entries = collect_entries(ctx, base_url, notes)?;
+    for &format in ctx.config.feeds() {
+        let dest_path = output_path(dest_dir, ctx.site().feed_path(format))?;
+        let mut file = io::BufWriter::new(fs::File::create(dest_path)?);
+        format.write(ctx.site(), base_url, &entries, &mut file)?;
+        file.flush()?;
+    }
+    Ok(())
+}
+
+/// Escape text for inclusion in XML content or attribute values.
+pub fn escape_xml(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Config;
+    use crate::testing;
+
+    fn sample_site() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let notes = [
+            ("old.md", "2023-01-05", "Old & Busted"),
+            ("new.md", "2024-06-01", "Less < More"),
+            ("mid.md", "2023-11-20", "Middle"),
+        ];
+        for (name, date, title) in notes {
+            let source = format!("---\ndate: {date}\n---\n# {title}\n\nSome *text*.\n");
+            fs::write(dir.path().join(name), source).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn feed_order() {
+        let dir = sample_site();
+        let ctx = Context::new(dir.path().to_str().unwrap(), false, Config::default(), None);
+
+        let notes = testing::rendered_notes(&ctx);
+        let entries = collect_entries(&ctx, "https://example.com/", &notes).unwrap();
+        let mut buf = vec![];
+        let site = SiteConfig::default();
+        write_atom(&site, "https://example.com/", &entries, &mut buf).unwrap();
+        let xml = String::from_utf8(buf).unwrap();
+
+        let doc = roxmltree::Document::parse(&xml).unwrap();
+        let titles: Vec<_> = doc
+            .descendants()
+            .filter(|n| n.has_tag_name("entry"))
+            .map(|n| {
+                let title = n.children().find(|c| c.has_tag_name("title")).unwrap();
+                title.text().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(titles, ["Less < More", "Middle", "Old & Busted"]);
+
+        let summary = doc
+            .descendants()
+            .find(|n| n.has_tag_name("summary"))
+            .unwrap();
+        assert_eq!(summary.text(), Some("<p>Some <em>text</em>.</p>\n"));
+
+        let ids: Vec<_> = doc
+            .descendants()
+            .filter(|n| n.has_tag_name("id"))
+            .map(|n| n.text().unwrap())
+            .collect();
+        assert_eq!(
+            ids,
+            [
+                "https://example.com/",
+                "https://example.com/new.html",
+                "https://example.com/mid.html",
+                "https://example.com/old.html",
+            ]
+        );
+    }
+
+    #[test]
+    fn json_feed() {
+        let dir = sample_site();
+        let ctx = Context::new(dir.path().to_str().unwrap(), false, Config::default(), None);
+
+        let notes = testing::rendered_notes(&ctx);
+        let entries = collect_entries(&ctx, "https://example.com/", &notes).unwrap();
+        let mut buf = vec![];
+        let site = SiteConfig::default();
+        write_json(&site, "https://example.com/", &entries, &mut buf).unwrap();
+        let feed: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+
+        assert_eq!(feed["version"], "https://jsonfeed.org/version/1.1");
+        assert_eq!(feed["feed_url"], "https://example.com/feed.json");
+        assert!(feed.get("description").is_none());
+        let items = feed["items"].as_array().unwrap();
+        let titles: Vec<_> = items.iter().map(|i| i["title"].as_str().unwrap()).collect();
+        assert_eq!(titles, ["Less < More", "Middle", "Old & Busted"]);
+        let urls: Vec<_> = items.iter().map(|i| i["url"].as_str().unwrap()).collect();
+        assert_eq!(
+            urls,
+            [
+                "https://example.com/new.html",
+                "https://example.com/mid.html",
+                "https://example.com/old.html",
+            ]
+        );
+        assert_eq!(items[0]["date_published"], "2024-06-01T00:00:00Z");
+    }
+}
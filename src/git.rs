@@ -1,3 +1,4 @@
+use std::io;
 use std::path::Path;
 use std::process::Command;
 
@@ -12,22 +13,30 @@ pub struct CommitInfo<'a> {
 }
 
 impl CommitData {
-    pub fn info<'a>(&'a self) -> CommitInfo<'a> {
+    pub fn info<'a>(&'a self) -> io::Result<CommitInfo<'a>> {
+        let malformed = || io::Error::new(io::ErrorKind::InvalidData, "malformed git output");
         let res = str::from_utf8(&self.0)
-            .expect("git output must be UTF-8")
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "git output must be UTF-8"))?
             .strip_suffix("\n")
-            .expect("missing newline at end of output");
+            .ok_or_else(malformed)?;
         let mut parts = res.splitn(4, " ");
-        CommitInfo {
-            hash: parts.next().unwrap(),
-            date: parts.next().unwrap(),
-            email: parts.next().unwrap(),
-            name: parts.next().unwrap(),
+        let info = CommitInfo {
+            hash: parts.next().ok_or_else(malformed)?,
+            date: parts.next().ok_or_else(malformed)?,
+            email: parts.next().ok_or_else(malformed)?,
+            name: parts.next().ok_or_else(malformed)?,
+        };
+        if info.hash.len() < 7 {
+            return Err(malformed());
         }
+        Ok(info)
     }
 }
 
-pub fn last_commit(repo: &Path, file: &Path) -> Option<CommitData> {
+/// Get the most recent commit that modified `file` in the repository at
+/// `repo`. Untracked files have no commit. It is an error if git fails, e.g.,
+/// because it isn't installed or `repo` is not a git repository.
+pub fn last_commit(repo: &Path, file: &Path) -> io::Result<Option<CommitData>> {
     let output = Command::new("git")
         .current_dir(repo)
         .args([
@@ -37,15 +46,46 @@ pub fn last_commit(repo: &Path, file: &Path) -> Option<CommitData> {
             "--",
             file.to_str().expect("path must be UTF-8"),
         ])
-        .output()
-        .ok()?;
+        .output()?;
 
-    // TODO check exit status?
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(format!("git failed: {}", stderr.trim())));
+    }
 
     if output.stdout.is_empty() {
         // Untracked file.
-        None
+        Ok(None)
     } else {
-        Some(CommitData(output.stdout))
+        Ok(Some(CommitData(output.stdout)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_info() {
+        let data = CommitData(b"0123456789abcdef 2024-06-01 ada@example.com Ada L\n".to_vec());
+        let info = data.info().unwrap();
+        assert_eq!(info.hash, "0123456789abcdef");
+        assert_eq!(info.date, "2024-06-01");
+        assert_eq!(info.email, "ada@example.com");
+        assert_eq!(info.name, "Ada L");
+    }
+
+    #[test]
+    fn empty_output() {
+        assert!(CommitData(vec![]).info().is_err());
+        assert!(CommitData(b"\n".to_vec()).info().is_err());
+    }
+
+    #[test]
+    fn not_a_repo() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("note.md");
+        std::fs::write(&file, "# hi").unwrap();
+        assert!(last_commit(dir.path(), &file).is_err());
     }
 }
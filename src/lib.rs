@@ -0,0 +1,20 @@
+//! Static rendering for a Markdown knowledge base.
+
+pub mod assets;
+pub mod compress;
+pub mod core;
+pub mod css;
+pub mod feed;
+pub mod git;
+pub mod manifest;
+pub mod markdown;
+pub mod parallel;
+pub mod rebuild;
+pub mod related;
+pub mod search;
+pub mod serve;
+pub mod sitemap;
+pub mod tags;
+#[cfg(test)]
+mod testing;
+pub mod watch;
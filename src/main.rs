@@ -1,16 +1,11 @@
-pub mod assets;
-pub mod core;
-pub mod git;
-pub mod markdown;
-pub mod parallel;
-pub mod serve;
-pub mod watch;
-
 use argh::FromArgs;
-use core::{Config, Context};
-use std::io;
+use memoize::core::{self, Config, Context, PlannedAction};
+use memoize::{rebuild, serve};
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::num::NonZero;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process;
 
 #[derive(FromArgs)]
 /// a static knowledge base
@@ -18,9 +13,10 @@ struct Memoize {
     #[argh(subcommand)]
     mode: Command,
 
-    #[argh(option, default = "String::from(\".\")")]
-    /// source directory
-    source: String,
+    #[argh(option)]
+    /// source directory (may be repeated to merge several, with later ones
+    /// taking precedence; the last holds the configuration)
+    source: Vec<String>,
 
     #[argh(option, default = "String::from(\"_site\")")]
     /// destination directory
@@ -34,6 +30,9 @@ enum Command {
     Show(ShowCommand),
     List(ListCommand),
     Serve(ServeCommand),
+    Watch(WatchCommand),
+    Check(CheckCommand),
+    Clean(CleanCommand),
 }
 
 #[derive(FromArgs)]
@@ -43,6 +42,48 @@ struct BuildCommand {
     #[argh(option, short = 'j')]
     /// number of threads to use for build
     threads: Option<NonZero<usize>>,
+
+    #[argh(switch)]
+    /// fail on any error instead of skipping the affected files
+    strict: bool,
+
+    #[argh(switch)]
+    /// include notes dated in the future
+    future: bool,
+
+    #[argh(switch)]
+    /// include notes marked as drafts
+    drafts: bool,
+
+    #[argh(switch)]
+    /// minify CSS files from the source directory
+    minify: bool,
+
+    #[argh(option)]
+    /// write a JSON manifest of the generated files, with content hashes, to
+    /// this path
+    manifest: Option<PathBuf>,
+
+    #[argh(switch)]
+    /// also write gzipped copies of text files, like `foo.html.gz`
+    gzip: bool,
+
+    #[argh(switch)]
+    /// report relative links that point to files missing from the site
+    check_links: bool,
+
+    #[argh(switch)]
+    /// copy static files into the site instead of hard-linking them
+    copy: bool,
+
+    #[argh(switch)]
+    /// keep files already in the destination directory instead of deleting
+    /// them, replacing only the ones the build produces
+    no_clean: bool,
+
+    #[argh(switch)]
+    /// list what the build would do without changing any files
+    dry_run: bool,
 }
 
 #[derive(FromArgs)]
@@ -52,6 +93,10 @@ struct ShowCommand {
     #[argh(positional)]
     /// a relative path to the file to render
     path: String,
+
+    #[argh(switch)]
+    /// print a note as normalized Markdown instead of HTML
+    markdown: bool,
 }
 
 #[derive(FromArgs)]
@@ -62,34 +107,242 @@ struct ListCommand {}
 #[derive(FromArgs)]
 /// run a web server
 #[argh(subcommand, name = "serve")]
-struct ServeCommand {}
+struct ServeCommand {
+    #[argh(option, default = "IpAddr::V4(Ipv4Addr::LOCALHOST)")]
+    /// address to listen on
+    host: IpAddr,
+
+    #[argh(option, short = 'p', default = "3000")]
+    /// port to listen on
+    port: u16,
+
+    #[argh(option, default = "NonZero::new(64 * 1024).unwrap()")]
+    /// size in bytes of the chunks used to stream static files
+    chunk_size: NonZero<usize>,
+
+    #[argh(option)]
+    /// maximum number of large static files to stream at once
+    max_streams: Option<NonZero<usize>>,
+
+    #[argh(switch)]
+    /// also serve files whose names start with `.` or `_`
+    show_hidden: bool,
+
+    #[argh(switch)]
+    /// match requested paths to files that differ only in case
+    ignore_case: bool,
+
+    #[argh(option)]
+    /// another directory to watch for changes (may be repeated)
+    watch: Vec<PathBuf>,
+
+    #[argh(switch)]
+    /// open the site in a web browser
+    open: bool,
+}
+
+#[derive(FromArgs)]
+/// rebuild the site whenever its sources change
+#[argh(subcommand, name = "watch")]
+struct WatchCommand {
+    #[argh(option, short = 'j')]
+    /// number of threads to use for each build
+    threads: Option<NonZero<usize>>,
+
+    #[argh(switch)]
+    /// include notes dated in the future
+    future: bool,
+
+    #[argh(switch)]
+    /// include notes marked as drafts
+    drafts: bool,
+
+    #[argh(option)]
+    /// another directory to watch for changes (may be repeated)
+    watch: Vec<PathBuf>,
+}
+
+#[derive(FromArgs)]
+/// remove the built site
+#[argh(subcommand, name = "clean")]
+struct CleanCommand {}
+
+#[derive(FromArgs)]
+/// check the templates for errors
+#[argh(subcommand, name = "check")]
+struct CheckCommand {}
+
+/// Set up logging to stderr. Messages look like the ones from other command-line
+/// tools, and the `RUST_LOG` environment variable can choose what to show
+/// (e.g., `RUST_LOG=debug` to see each request to the preview server).
+fn init_logging() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
+        .format(|buf, record| {
+            let prefix = match record.level() {
+                log::Level::Error => "error: ",
+                log::Level::Warn => "warning: ",
+                log::Level::Info => "",
+                log::Level::Debug => "debug: ",
+                log::Level::Trace => "trace: ",
+            };
+            writeln!(buf, "{prefix}{}", record.args())
+        })
+        .init();
+}
+
+/// Print the steps of a build, one per line.
+fn print_plan(ctx: &Context, dest_path: &Path) {
+    let plan = match ctx.plan_site(dest_path) {
+        Ok(plan) => plan,
+        Err(e) => {
+            log::error!("{e:#}");
+            process::exit(1);
+        }
+    };
+    for action in plan {
+        match action {
+            PlannedAction::Remove(path) => println!("remove {}", path.display()),
+            PlannedAction::CreateDir(path) => println!("mkdir  {}", path.display()),
+            PlannedAction::Copy { from, to } => {
+                println!("copy   {} -> {}", from.display(), to.display())
+            }
+            PlannedAction::Render { from, to } => {
+                println!("render {} -> {}", from.display(), to.display())
+            }
+            PlannedAction::Write(path) => println!("write  {}", path.display()),
+        }
+    }
+}
 
 fn main() {
+    init_logging();
     let args: Memoize = argh::from_env();
-    let config = Config::load(Path::new(&args.source)).unwrap();
-    let ctx = Context::new(&args.source, matches!(args.mode, Command::Serve(_)), config);
+    let (src_dir, extra_src_dirs) = match args.source.split_last() {
+        Some((last, rest)) => (last.as_str(), rest),
+        None => (".", &[][..]),
+    };
+    let config = Config::load(Path::new(src_dir)).unwrap();
+    let mut ctx = Context::new(
+        src_dir,
+        matches!(args.mode, Command::Serve(_)),
+        config,
+        None,
+    );
+    if let Err(e) = ctx.set_extra_src_dirs(extra_src_dirs) {
+        log::error!("{e:#}");
+        process::exit(1);
+    }
     match args.mode {
         Command::Build(cmd) => {
+            ctx.strict = cmd.strict;
+            ctx.future = cmd.future;
+            ctx.drafts = cmd.drafts;
+            ctx.minify = cmd.minify;
+            ctx.manifest = cmd.manifest.is_some();
+            ctx.gzip = cmd.gzip;
+            ctx.check_links = cmd.check_links;
+            ctx.copy_files = cmd.copy;
+            ctx.keep_dest = cmd.no_clean;
             let dest_path = Path::new(&args.dest);
-            ctx.render_site(cmd.threads, dest_path).unwrap()
+            if cmd.dry_run {
+                print_plan(&ctx, dest_path);
+                return;
+            }
+            match ctx.render_site(cmd.threads, dest_path) {
+                Ok(report) => {
+                    report.log_problems();
+                    if let Some(path) = cmd.manifest
+                        && let Err(e) = std::fs::write(&path, report.manifest.to_json())
+                    {
+                        log::error!("could not write manifest {}: {e}", path.display());
+                        process::exit(1);
+                    }
+                    if !report.failures.is_empty() || !report.broken_links.is_empty() {
+                        process::exit(1);
+                    }
+                }
+                Err(e) => {
+                    log::error!("{e:#}");
+                    process::exit(1);
+                }
+            }
         }
         Command::Show(cmd) => match ctx.resolve_resource(&cmd.path) {
+            Some(core::Resource::Note(path)) if cmd.markdown => {
+                match ctx.render_note_markdown(&path) {
+                    Ok(markdown) => print!("{markdown}"),
+                    Err(e) => {
+                        log::error!("{e:#}");
+                        process::exit(1);
+                    }
+                }
+            }
+            Some(_) if cmd.markdown => {
+                log::error!("not a note");
+                process::exit(1);
+            }
             Some(rsrc) => {
-                ctx.render_resource(rsrc, &mut io::stdout()).unwrap();
+                // The page goes to stdout, away from the site's stylesheet.
+                ctx.inline_assets = true;
+                if let Err(e) = ctx.render_resource(rsrc, &mut io::stdout()) {
+                    log::error!("{e:#}");
+                    process::exit(1);
+                }
+            }
+            None => {
+                log::error!("not found");
+                process::exit(1);
             }
-            None => eprintln!("not found"),
         },
         Command::List(_) => {
             for rsrc in ctx.read_resources() {
                 match rsrc {
-                    core::Resource::Directory(path) => println!("dir  {}", path.display()),
-                    core::Resource::Static(path) => println!("file {}", path.display()),
-                    core::Resource::Note(path) => println!("note {}", path.display()),
+                    Ok(core::Resource::Directory(path)) => println!("dir  {}", path.display()),
+                    Ok(core::Resource::Static(path)) => println!("file {}", path.display()),
+                    Ok(core::Resource::Note(path)) => println!("note {}", path.display()),
+                    Err(e) => log::warn!("directory walk error: {e}"),
                 }
             }
         }
-        Command::Serve(_) => {
-            serve::serve(ctx);
+        Command::Serve(cmd) => {
+            ctx.show_hidden = cmd.show_hidden;
+            ctx.ignore_case = cmd.ignore_case;
+            // The preview server shows every note, so they all count as
+            // published, even in related-note lists.
+            ctx.drafts = true;
+            ctx.future = true;
+            let opts = serve::ServeOptions {
+                addr: SocketAddr::new(cmd.host, cmd.port),
+                chunk_size: cmd.chunk_size,
+                max_streams: cmd.max_streams,
+                watch: cmd.watch,
+                open: cmd.open,
+            };
+            if let Err(e) = serve::serve(ctx, opts) {
+                log::error!("{e:#}");
+                process::exit(1);
+            }
+        }
+        Command::Watch(cmd) => {
+            ctx.future = cmd.future;
+            ctx.drafts = cmd.drafts;
+            let dest_path = PathBuf::from(&args.dest);
+            if let Err(e) = rebuild::watch_and_build(ctx, cmd.threads, dest_path, cmd.watch) {
+                log::error!("{e:#}");
+                process::exit(1);
+            }
+        }
+        Command::Clean(_) => {
+            if let Err(e) = ctx.clean(Path::new(&args.dest)) {
+                log::error!("{e}");
+                process::exit(1);
+            }
+        }
+        Command::Check(_) => {
+            if let Err(e) = ctx.validate_templates() {
+                log::error!("{e:#}");
+                process::exit(1);
+            }
         }
     }
 }
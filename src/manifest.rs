@@ -0,0 +1,139 @@
+//! A record of the files a build generates, for deploy tools and caches.
+
+use serde::Serialize;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Every file a build generated from the source directory.
+#[derive(Debug, Default, Serialize)]
+pub struct BuildManifest {
+    /// Entries for the source files, sorted by path.
+    pub entries: Vec<ManifestEntry>,
+    /// Files generated for the site as a whole, like feeds and the
+    /// stylesheet, sorted by path.
+    pub generated: Vec<OutputFile>,
+}
+
+/// A source file and the files we generated from it.
+#[derive(Debug, Serialize)]
+pub struct ManifestEntry {
+    /// The source file's path, relative to the source directory.
+    pub source: String,
+    pub kind: EntryKind,
+    pub outputs: Vec<OutputFile>,
+}
+
+/// The kinds of source files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    /// A Markdown note, rendered to a page (and maybe other files).
+    Note,
+    /// Any other file, which gets copied.
+    Static,
+}
+
+/// A generated file.
+#[derive(Debug, Serialize)]
+pub struct OutputFile {
+    /// The file's path, relative to the destination directory.
+    pub path: String,
+    /// A hash of the file's contents, as hex. This is only for telling when
+    /// files have changed; it's not cryptographically secure.
+    pub hash: String,
+}
+
+impl OutputFile {
+    pub fn new(path: String, hash: u64) -> Self {
+        Self {
+            path,
+            hash: format!("{hash:016x}"),
+        }
+    }
+}
+
+impl BuildManifest {
+    /// Serialize the manifest as JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("manifest is always serializable")
+    }
+}
+
+/// A writer that hashes everything written through it, with 64-bit FNV-1a.
+pub struct HashWriter<W> {
+    inner: W,
+    hash: u64,
+}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+impl<W: Write> HashWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hash: FNV_OFFSET,
+        }
+    }
+
+    /// The hash of everything written so far.
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    /// Get back the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        for &byte in &buf[..len] {
+            self.hash = (self.hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME);
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Hash some bytes the same way `HashWriter` does.
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut writer = HashWriter::new(io::sink());
+    writer.write_all(bytes).expect("sink never fails");
+    writer.hash()
+}
+
+/// Hash the contents of a file.
+pub fn hash_file(path: &Path) -> io::Result<u64> {
+    let mut writer = HashWriter::new(io::sink());
+    io::copy(&mut fs::File::open(path)?, &mut writer)?;
+    Ok(writer.hash())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv_hash() {
+        assert_eq!(hash_bytes(b""), 0xcbf29ce484222325);
+        assert_eq!(hash_bytes(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(hash_bytes(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn hash_while_writing() {
+        let mut buf = vec![];
+        let mut writer = HashWriter::new(&mut buf);
+        writer.write_all(b"foo").unwrap();
+        writer.write_all(b"bar").unwrap();
+        assert_eq!(writer.hash(), hash_bytes(b"foobar"));
+        assert_eq!(buf, b"foobar");
+    }
+}
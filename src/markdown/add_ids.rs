@@ -1,69 +1,224 @@
+use super::util::take_until;
 use pulldown_cmark::{CowStr, Event, Tag, TagEnd};
-use std::collections::VecDeque;
+use pulldown_cmark_escape::escape_href;
+use std::collections::{HashSet, VecDeque};
 
-/// Slugify a string and append it to a buffer.
-fn slug_append(buf: &mut String, s: &str) {
-    let mut last_is_dash = false;
-    buf.extend(s.chars().filter_map(|c| {
+/// How to handle non-ASCII characters in heading IDs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SlugStyle {
+    /// Keep letters and digits from any script, so `# Café` gets the ID
+    /// `café`.
+    #[default]
+    Unicode,
+
+    /// Transliterate to ASCII, so `# Café` gets the ID `cafe` and `# 日本語`
+    /// gets `ri-ben-yu`.
+    Ascii,
+}
+
+/// How to turn headings' text into IDs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SlugOptions {
+    /// What to do with non-ASCII characters.
+    pub style: SlugStyle,
+
+    /// The character that replaces each run of spaces and punctuation.
+    pub separator: char,
+
+    /// Lowercase ASCII letters, so `# Hi` gets the ID `hi`.
+    pub lowercase: bool,
+
+    /// Drop separators from the start and end, so `# !hi!` gets the ID `hi`
+    /// instead of `-hi-`.
+    pub trim: bool,
+}
+
+impl Default for SlugOptions {
+    fn default() -> Self {
+        Self {
+            style: SlugStyle::default(),
+            separator: '-',
+            lowercase: true,
+            trim: true,
+        }
+    }
+}
+
+/// Slugify a string and append it to a slug that we're building up in `buf`.
+/// Runs of separators collapse into one, even across calls. Finish the slug
+/// with `slug_finish`.
+fn slug_append(buf: &mut String, s: &str, options: &SlugOptions) {
+    let ascii;
+    let s = match options.style {
+        SlugStyle::Unicode => s,
+        SlugStyle::Ascii => {
+            ascii = deunicode::deunicode(s);
+            &ascii
+        }
+    };
+    for c in s.chars() {
         if c.is_alphanumeric() {
-            last_is_dash = false;
-            Some(c.to_ascii_lowercase())
-        } else if last_is_dash {
-            None
+            buf.push(if options.lowercase {
+                c.to_ascii_lowercase()
+            } else {
+                c
+            });
         } else {
-            last_is_dash = true;
-            Some('-')
+            // Collapse runs of separators, and with `trim`, skip leading ones.
+            let skip = buf.ends_with(options.separator) || (options.trim && buf.is_empty());
+            if !skip {
+                buf.push(options.separator);
+            }
         }
-    }));
+    }
+}
+
+/// Finish a slug built up with `slug_append`.
+fn slug_finish(buf: &mut String, options: &SlugOptions) {
+    if options.trim && buf.ends_with(options.separator) {
+        buf.pop();
+    }
+}
+
+/// The ID for headings whose text has nothing to slugify, like `# 🎉`.
+const FALLBACK_ID: &str = "section";
+
+/// Slugify a string in one go.
+pub fn slugify(s: &str, options: &SlugOptions) -> String {
+    let mut slug = String::new();
+    slug_append(&mut slug, s, options);
+    slug_finish(&mut slug, options);
+    slug
 }
 
 /// A pulldown-cmark adapter that adds IDs to headings that don't already have
 /// them by "slugifying" the heading's text.
+///
+/// Optionally, the adapter also adds an anchor link to the end of every
+/// heading that points to the heading's ID.
+///
+/// A prefix can go at the start of every generated ID, so documents embedded
+/// in a larger page don't collide with its IDs. IDs given explicitly (like
+/// `# Title {#id}`) get the prefix only if asked.
+///
+/// Generated IDs are unique within the document: when one is already taken,
+/// a counter goes on the end, so a second `# Notes` gets `notes-1`. Explicit
+/// IDs that come later only count if they're reserved with
+/// `reserve_explicit_ids`.
 pub struct AddHeadingIds<'a, I>
 where
     I: Iterator<Item = Event<'a>>,
 {
     iter: I,
     buffer: VecDeque<Event<'a>>,
+    anchors: bool,
+    slugs: SlugOptions,
+    prefix: String,
+    prefix_explicit: bool,
+    /// The IDs given to headings so far.
+    used: HashSet<String>,
 }
 
 impl<'a, I> AddHeadingIds<'a, I>
 where
     I: Iterator<Item = Event<'a>>,
 {
-    pub fn new(iter: I) -> Self {
+    pub fn new(iter: I, anchors: bool, slugs: SlugOptions) -> Self {
         Self {
             iter,
             buffer: VecDeque::new(),
+            anchors,
+            slugs,
+            prefix: String::new(),
+            prefix_explicit: false,
+            used: HashSet::new(),
+        }
+    }
+
+    /// Put `prefix` at the start of generated IDs, and also explicit ones if
+    /// `explicit` is set.
+    pub fn with_prefix(mut self, prefix: &str, explicit: bool) -> Self {
+        self.prefix = prefix.to_string();
+        self.prefix_explicit = explicit;
+        self
+    }
+
+    /// Reserve the explicit IDs of the headings in `events`, which should be
+    /// the same document, so a generated ID never takes one that comes later.
+    /// Call this after `with_prefix`, since the prefix applies to these IDs.
+    pub fn reserve_explicit_ids<'b>(mut self, events: impl Iterator<Item = Event<'b>>) -> Self {
+        for event in events {
+            if let Event::Start(Tag::Heading { id: Some(id), .. }) = event {
+                let id = if self.prefix_explicit {
+                    format!("{}{id}", self.prefix)
+                } else {
+                    id.to_string()
+                };
+                self.used.insert(id);
+            }
         }
+        self
     }
 
     /// Assuming that `self` is now just after the beginning of a header, buffer
-    /// up all the events until the header in `self.buffer`. Return the
+    /// up all the events until the header's end in `self.buffer`. Return the
     /// slugified version of the header's text contents.
+    ///
+    /// Headings can appear in any block context (e.g., a blockquote or a list
+    /// item), but they only contain inline content, so the first heading end
+    /// we see is always this heading's.
     fn consume_heading(&mut self) -> String {
-        assert!(self.buffer.is_empty(), "nested headings are not allowed");
-        let mut slugbuf = String::new();
+        self.buffer.extend(take_until(self.iter.by_ref(), |e| {
+            matches!(e, Event::End(TagEnd::Heading(_)))
+        }));
 
-        // This is crying out for a `take_until` iterator method; `take_while`
-        // doesn't quite cut it.
-        for future_event in self.iter.by_ref() {
-            let is_end = match &future_event {
-                Event::End(TagEnd::Heading(_)) => true,
-                Event::Text(text) => {
-                    slug_append(&mut slugbuf, text);
-                    false
+        let mut slugbuf = String::new();
+        for event in &self.buffer {
+            match event {
+                Event::Text(text) | Event::Code(text) => {
+                    slug_append(&mut slugbuf, text, &self.slugs);
                 }
-                _ => false,
-            };
-            self.buffer.push_back(future_event);
-            if is_end {
-                break;
+                // Line breaks (in setext headings) separate words.
+                Event::SoftBreak | Event::HardBreak => slug_append(&mut slugbuf, " ", &self.slugs),
+                _ => (),
             }
         }
-
+        slug_finish(&mut slugbuf, &self.slugs);
         slugbuf
     }
+
+    /// Make a generated ID unique by adding a counter if it's already used,
+    /// and record it as used.
+    fn unique_id(&mut self, id: String) -> String {
+        if !self.used.contains(&id) {
+            self.used.insert(id.clone());
+            return id;
+        }
+        let separator = self.slugs.separator;
+        for n in 1.. {
+            let candidate = format!("{id}{separator}{n}");
+            if !self.used.contains(&candidate) {
+                self.used.insert(candidate.clone());
+                return candidate;
+            }
+        }
+        unreachable!()
+    }
+
+    /// If anchors are enabled, insert an anchor link to `id` just before the
+    /// end of the buffered heading.
+    fn add_anchor(&mut self, id: &str) {
+        if !self.anchors {
+            return;
+        }
+        if let Some(Event::End(TagEnd::Heading(_))) = self.buffer.back() {
+            let mut html = String::from("<a class=\"anchor\" href=\"#");
+            escape_href(&mut html, id).unwrap();
+            html.push_str("\">#</a>");
+            self.buffer
+                .insert(self.buffer.len() - 1, Event::Html(CowStr::from(html)));
+        }
+    }
 }
 
 impl<'a, I> Iterator for AddHeadingIds<'a, I>
@@ -86,11 +241,42 @@ where
                 classes,
                 attrs,
             }) => {
-                // It's a heading without an ID. We do our thing.
-                let slug = self.consume_heading();
+                // It's a heading without an ID. We do our thing. Headings
+                // with no letters or digits still need a usable ID.
+                let mut slug = self.consume_heading();
+                if slug.is_empty() {
+                    slug.push_str(FALLBACK_ID);
+                }
+                let slug = CowStr::from(self.unique_id(format!("{}{slug}", self.prefix)));
+                self.add_anchor(&slug);
                 Some(Event::Start(Tag::Heading {
                     level,
-                    id: Some(CowStr::from(slug)),
+                    id: Some(slug),
+                    classes,
+                    attrs,
+                }))
+            }
+            Event::Start(Tag::Heading {
+                level,
+                id: Some(id),
+                classes,
+                attrs,
+            }) => {
+                // The heading already has an ID, but it may still need a
+                // prefix or an anchor.
+                let id = if self.prefix_explicit {
+                    CowStr::from(format!("{}{id}", self.prefix))
+                } else {
+                    id
+                };
+                self.used.insert(id.to_string());
+                if self.anchors {
+                    self.consume_heading();
+                    self.add_anchor(&id);
+                }
+                Some(Event::Start(Tag::Heading {
+                    level,
+                    id: Some(id),
                     classes,
                     attrs,
                 }))
@@ -106,12 +292,37 @@ mod tests {
     use pulldown_cmark::{Options, Parser, html};
 
     fn render_with_ids(source: &str) -> String {
+        render_with_options(source, SlugOptions::default())
+    }
+
+    fn render_with_slugs(source: &str, style: SlugStyle) -> String {
+        let slugs = SlugOptions {
+            style,
+            ..SlugOptions::default()
+        };
+        render_with_options(source, slugs)
+    }
+
+    fn render_with_options(source: &str, slugs: SlugOptions) -> String {
+        let mut options = Options::empty();
+        options.insert(Options::ENABLE_HEADING_ATTRIBUTES);
+        let parser = Parser::new_ext(source, options);
+
+        let mut buf = String::new();
+        html::push_html(&mut buf, AddHeadingIds::new(parser, false, slugs));
+        buf
+    }
+
+    fn render_with_anchors(source: &str) -> String {
         let mut options = Options::empty();
         options.insert(Options::ENABLE_HEADING_ATTRIBUTES);
         let parser = Parser::new_ext(source, options);
 
         let mut buf = String::new();
-        html::push_html(&mut buf, AddHeadingIds::new(parser));
+        html::push_html(
+            &mut buf,
+            AddHeadingIds::new(parser, true, SlugOptions::default()),
+        );
         buf
     }
 
@@ -152,4 +363,180 @@ mod tests {
     fn multi_gap() {
         assert_eq!(render_with_ids("# h ' i"), "<h1 id=\"h-i\">h ' i</h1>\n");
     }
+
+    #[test]
+    fn edge_punctuation() {
+        assert_eq!(render_with_ids("# !hi!"), "<h1 id=\"hi\">!hi!</h1>\n");
+        assert_eq!(
+            render_with_ids("# (Quoted) *words*..."),
+            "<h1 id=\"quoted-words\">(Quoted) <em>words</em>...</h1>\n"
+        );
+        let untrimmed = SlugOptions {
+            trim: false,
+            ..SlugOptions::default()
+        };
+        assert_eq!(
+            render_with_options("# !hi!", untrimmed),
+            "<h1 id=\"-hi-\">!hi!</h1>\n"
+        );
+    }
+
+    #[test]
+    fn nothing_to_slugify() {
+        assert_eq!(render_with_ids("# 🎉"), "<h1 id=\"section\">🎉</h1>\n");
+        assert_eq!(render_with_ids("# ???"), "<h1 id=\"section\">???</h1>\n");
+    }
+
+    #[test]
+    fn duplicate_headings() {
+        assert_eq!(
+            render_with_ids("# Notes\n\n## Notes\n\n# Notes-1 {#notes-2}\n\n# Notes"),
+            "<h1 id=\"notes\">Notes</h1>\n<h2 id=\"notes-1\">Notes</h2>\n\
+             <h1 id=\"notes-2\">Notes-1</h1>\n<h1 id=\"notes-3\">Notes</h1>\n"
+        );
+        assert_eq!(
+            render_with_anchors("# A\n\n# A"),
+            "<h1 id=\"a\">A<a class=\"anchor\" href=\"#a\">#</a></h1>\n\
+             <h1 id=\"a-1\">A<a class=\"anchor\" href=\"#a-1\">#</a></h1>\n"
+        );
+    }
+
+    #[test]
+    fn explicit_id_after_generated() {
+        let render = |source, explicit| {
+            let parser = Parser::new_ext(source, Options::ENABLE_HEADING_ATTRIBUTES);
+            let ids = AddHeadingIds::new(parser, false, SlugOptions::default())
+                .with_prefix("n-", explicit)
+                .reserve_explicit_ids(Parser::new_ext(source, Options::ENABLE_HEADING_ATTRIBUTES));
+            let mut buf = String::new();
+            html::push_html(&mut buf, ids);
+            buf
+        };
+        assert_eq!(
+            render("# Intro\n\n# Other {#intro}", false),
+            "<h1 id=\"n-intro\">Intro</h1>\n<h1 id=\"intro\">Other</h1>\n"
+        );
+        assert_eq!(
+            render("# Intro\n\n# Other {#intro}", true),
+            "<h1 id=\"n-intro-1\">Intro</h1>\n<h1 id=\"n-intro\">Other</h1>\n"
+        );
+    }
+
+    #[test]
+    fn gap_across_events() {
+        assert_eq!(
+            render_with_ids("# one! *two*"),
+            "<h1 id=\"one-two\">one! <em>two</em></h1>\n"
+        );
+    }
+
+    #[test]
+    fn custom_separator() {
+        let slugs = SlugOptions {
+            separator: '_',
+            lowercase: false,
+            ..SlugOptions::default()
+        };
+        assert_eq!(
+            render_with_options("# The `Main` Function!", slugs),
+            "<h1 id=\"The_Main_Function\">The <code>Main</code> Function!</h1>\n"
+        );
+    }
+
+    #[test]
+    fn inline_code() {
+        assert_eq!(
+            render_with_ids("# The `main` function"),
+            "<h1 id=\"the-main-function\">The <code>main</code> function</h1>\n"
+        );
+    }
+
+    #[test]
+    fn in_blockquote() {
+        assert_eq!(
+            render_with_ids("> # h i\n>\n> text"),
+            "<blockquote>\n<h1 id=\"h-i\">h i</h1>\n<p>text</p>\n</blockquote>\n"
+        );
+    }
+
+    #[test]
+    fn in_list_item() {
+        assert_eq!(
+            render_with_ids("- ## h *i*\n  text\n- other"),
+            "<ul>\n<li>\n<h2 id=\"h-i\">h <em>i</em></h2>\ntext</li>\n<li>other</li>\n</ul>\n"
+        );
+    }
+
+    #[test]
+    fn multi_line_heading() {
+        assert_eq!(
+            render_with_ids("Long title\ncontinued\n===\n"),
+            "<h1 id=\"long-title-continued\">Long title\ncontinued</h1>\n"
+        );
+    }
+
+    #[test]
+    fn accented_latin() {
+        assert_eq!(
+            render_with_slugs("# café münü", SlugStyle::Unicode),
+            "<h1 id=\"café-münü\">café münü</h1>\n"
+        );
+        assert_eq!(
+            render_with_slugs("# Café Münü", SlugStyle::Ascii),
+            "<h1 id=\"cafe-munu\">Café Münü</h1>\n"
+        );
+    }
+
+    #[test]
+    fn cjk() {
+        assert_eq!(
+            render_with_slugs("# 日本語", SlugStyle::Unicode),
+            "<h1 id=\"日本語\">日本語</h1>\n"
+        );
+        assert_eq!(
+            render_with_slugs("# 日本語", SlugStyle::Ascii),
+            "<h1 id=\"ri-ben-yu\">日本語</h1>\n"
+        );
+    }
+
+    #[test]
+    fn anchor() {
+        assert_eq!(
+            render_with_anchors("# h i"),
+            "<h1 id=\"h-i\">h i<a class=\"anchor\" href=\"#h-i\">#</a></h1>\n"
+        );
+    }
+
+    #[test]
+    fn prefix() {
+        let render = |source, explicit| {
+            let parser = Parser::new_ext(source, Options::ENABLE_HEADING_ATTRIBUTES);
+            let ids = AddHeadingIds::new(parser, true, SlugOptions::default())
+                .with_prefix("note1-", explicit);
+            let mut buf = String::new();
+            html::push_html(&mut buf, ids);
+            buf
+        };
+        assert_eq!(
+            render("# Introduction", false),
+            "<h1 id=\"note1-introduction\">Introduction\
+             <a class=\"anchor\" href=\"#note1-introduction\">#</a></h1>\n"
+        );
+        assert_eq!(
+            render("# Intro {#start}", false),
+            "<h1 id=\"start\">Intro<a class=\"anchor\" href=\"#start\">#</a></h1>\n"
+        );
+        assert_eq!(
+            render("# Intro {#start}", true),
+            "<h1 id=\"note1-start\">Intro<a class=\"anchor\" href=\"#note1-start\">#</a></h1>\n"
+        );
+    }
+
+    #[test]
+    fn anchor_with_id() {
+        assert_eq!(
+            render_with_anchors("# hi {#x}"),
+            "<h1 id=\"x\">hi<a class=\"anchor\" href=\"#x\">#</a></h1>\n"
+        );
+    }
 }
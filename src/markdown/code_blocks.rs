@@ -0,0 +1,145 @@
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, Tag, TagEnd};
+use pulldown_cmark_escape::escape_html;
+use std::collections::HashMap;
+
+/// A function that renders the contents of a fenced code block to HTML.
+pub type Handler = Box<dyn Fn(&str) -> String + Send + Sync>;
+
+/// A registry of handlers for fenced code blocks, keyed by the first word of
+/// the block's info string. For example, a handler registered for `mermaid`
+/// renders blocks that start with ```` ```mermaid ````.
+pub struct CodeHandlers {
+    handlers: HashMap<String, Handler>,
+}
+
+impl CodeHandlers {
+    /// Create an empty registry, without even the built-in handlers.
+    pub fn empty() -> Self {
+        Self {
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// Register a handler for code blocks with a given info-string keyword,
+    /// replacing any existing handler for that keyword.
+    pub fn register<F>(&mut self, keyword: &str, handler: F)
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        self.handlers.insert(keyword.to_string(), Box::new(handler));
+    }
+
+    /// Look up the handler for a code block's info string, if any.
+    fn get(&self, info: &str) -> Option<&Handler> {
+        let keyword = info.split_whitespace().next()?;
+        self.handlers.get(keyword)
+    }
+}
+
+impl Default for CodeHandlers {
+    /// Create a registry with the built-in handlers.
+    fn default() -> Self {
+        let mut handlers = Self::empty();
+        handlers.register("mermaid", mermaid);
+        handlers
+    }
+}
+
+/// Render a Mermaid diagram as a `<div>` for Mermaid's JavaScript to find.
+fn mermaid(code: &str) -> String {
+    let mut html = String::from("<div class=\"mermaid\">");
+    escape_html(&mut html, code).unwrap();
+    html.push_str("</div>\n");
+    html
+}
+
+/// A pulldown-cmark adapter that renders fenced code blocks using registered
+/// handlers. Code blocks without a matching handler are left unchanged.
+pub struct RenderCodeBlocks<'a, 'h, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    iter: I,
+    handlers: &'h CodeHandlers,
+}
+
+impl<'a, 'h, I> RenderCodeBlocks<'a, 'h, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    pub fn new(iter: I, handlers: &'h CodeHandlers) -> Self {
+        Self { iter, handlers }
+    }
+
+    /// Assuming that `self` is now just after the beginning of a code block,
+    /// consume the rest of the block and return its text.
+    fn consume_code(&mut self) -> String {
+        let mut code = String::new();
+        for event in self.iter.by_ref() {
+            match event {
+                Event::End(TagEnd::CodeBlock) => break,
+                Event::Text(text) => code.push_str(&text),
+                _ => (),
+            }
+        }
+        code
+    }
+}
+
+impl<'a, 'h, I> Iterator for RenderCodeBlocks<'a, 'h, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let event = self.iter.next()?;
+        if let Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) = &event
+            && let Some(handler) = self.handlers.get(info)
+        {
+            let code = self.consume_code();
+            Some(Event::Html(CowStr::from(handler(&code))))
+        } else {
+            Some(event)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{Parser, html};
+
+    fn render_code(source: &str, handlers: &CodeHandlers) -> String {
+        let parser = Parser::new(source);
+        let mut buf = String::new();
+        html::push_html(&mut buf, RenderCodeBlocks::new(parser, handlers));
+        buf
+    }
+
+    #[test]
+    fn unhandled() {
+        assert_eq!(
+            render_code("```rust\nfn\n```", &CodeHandlers::default()),
+            "<pre><code class=\"language-rust\">fn\n</code></pre>\n"
+        );
+    }
+
+    #[test]
+    fn mermaid() {
+        assert_eq!(
+            render_code("```mermaid\na --> b\n```", &CodeHandlers::default()),
+            "<div class=\"mermaid\">a --&gt; b\n</div>\n"
+        );
+    }
+
+    #[test]
+    fn custom_handler() {
+        let mut handlers = CodeHandlers::empty();
+        handlers.register("shout", |code| format!("<p>{}</p>", code.to_uppercase()));
+        assert_eq!(
+            render_code("```shout extra\nhi\n```", &handlers),
+            "<p>HI\n</p>"
+        );
+    }
+}
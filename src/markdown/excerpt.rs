@@ -0,0 +1,124 @@
+use pulldown_cmark::{Event, Tag, TagEnd};
+
+/// The fold marker that ends a note's excerpt, unless the site or note picks a
+/// different one.
+pub const DEFAULT_SEPARATOR: &str = "<!-- more -->";
+
+/// A pulldown-cmark adapter that records a copy of every event that passes
+/// through it, so we can pick out an excerpt after rendering.
+pub struct RecordEvents<'a, 'b, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    iter: I,
+    events: &'b mut Vec<Event<'a>>,
+}
+
+impl<'a, 'b, I> RecordEvents<'a, 'b, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    pub fn new(iter: I, events: &'b mut Vec<Event<'a>>) -> Self {
+        Self { iter, events }
+    }
+}
+
+impl<'a, 'b, I> Iterator for RecordEvents<'a, 'b, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let event = self.iter.next()?;
+        self.events.push(event.clone());
+        Some(event)
+    }
+}
+
+/// Pick out the events for a document's excerpt: everything before the first
+/// fold marker, if there is one, or else the first paragraph. The marker must
+/// be an HTML block (i.e., on a line by itself).
+pub fn select<'e, 'a>(events: &'e [Event<'a>], separator: &str) -> &'e [Event<'a>] {
+    let fold = events.iter().position(|e| match e {
+        Event::Html(html) => html.trim() == separator,
+        _ => false,
+    });
+    if let Some(fold) = fold {
+        return &events[..fold];
+    }
+
+    let start = events
+        .iter()
+        .position(|e| matches!(e, Event::Start(Tag::Paragraph)));
+    match start {
+        Some(start) => {
+            let len = events[start..]
+                .iter()
+                .position(|e| matches!(e, Event::End(TagEnd::Paragraph)))
+                .expect("unterminated paragraph");
+            &events[start..=start + len]
+        }
+        None => &[],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::markdown::{CodeHandlers, RenderOptions, render};
+
+    fn excerpt(source: &str, separator: &str) -> String {
+        let options = RenderOptions {
+            excerpt_separator: separator,
+            ..RenderOptions::default()
+        };
+        render(source, &CodeHandlers::empty(), &options)
+            .unwrap()
+            .excerpt
+    }
+
+    #[test]
+    fn description_wins() {
+        let source = "---\ndescription: A <summary>.\n---\nFirst.\n\n<!-- more -->\n\nRest.\n";
+        assert_eq!(
+            excerpt(source, super::DEFAULT_SEPARATOR),
+            "<p>A &lt;summary&gt;.</p>\n"
+        );
+    }
+
+    #[test]
+    fn fold_over_first_paragraph() {
+        let source = "# Title\n\nFirst.\n\nSecond.\n\n<!-- more -->\n\nRest.\n";
+        assert_eq!(
+            excerpt(source, super::DEFAULT_SEPARATOR),
+            "<h1 id=\"title\">Title<a class=\"anchor\" href=\"#title\">#</a></h1>\n<p>First.</p>\n<p>Second.</p>\n"
+        );
+    }
+
+    #[test]
+    fn first_paragraph() {
+        let source = "# Title\n\nFirst *one*.\n\nSecond.\n";
+        assert_eq!(
+            excerpt(source, super::DEFAULT_SEPARATOR),
+            "<p>First <em>one</em>.</p>\n"
+        );
+        assert_eq!(excerpt("# Just a heading\n", super::DEFAULT_SEPARATOR), "");
+    }
+
+    #[test]
+    fn configured_separator() {
+        let source = "First.\n\nSecond.\n\n<!-- fold -->\n\nRest.\n";
+        assert_eq!(excerpt(source, super::DEFAULT_SEPARATOR), "<p>First.</p>\n");
+        assert_eq!(
+            excerpt(source, "<!-- fold -->"),
+            "<p>First.</p>\n<p>Second.</p>\n"
+        );
+
+        // Notes can pick their own separator.
+        let source = "---\nexcerpt_separator: <!-- cut -->\n---\nA.\n\nB.\n\n<!-- cut -->\n\nC.\n";
+        assert_eq!(
+            excerpt(source, super::DEFAULT_SEPARATOR),
+            "<p>A.</p>\n<p>B.</p>\n"
+        );
+    }
+}
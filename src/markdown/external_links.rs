@@ -0,0 +1,156 @@
+use super::rel_links::is_absolute_url;
+use pulldown_cmark::{CowStr, Event, Tag, TagEnd};
+use pulldown_cmark_escape::{escape_href, escape_html};
+
+/// A pulldown-cmark adapter that makes links to other sites open in a new tab.
+/// Links with absolute URLs on some other host get `target="_blank"` and a
+/// `rel` attribute that keeps the new page from seeing this one.
+///
+/// Links to `site_host` and relative links are left unchanged, as are URLs
+/// without a host, like `mailto:` links. When the adapter isn't `enabled`, it
+/// leaves every link unchanged.
+pub struct ExternalLinks<'a, 's, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    iter: I,
+    enabled: bool,
+    site_host: Option<&'s str>,
+    in_external: bool,
+}
+
+impl<'a, 's, I> ExternalLinks<'a, 's, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    pub fn new(iter: I, enabled: bool, site_host: Option<&'s str>) -> Self {
+        Self {
+            iter,
+            enabled,
+            site_host,
+            in_external: false,
+        }
+    }
+
+    fn is_external(&self, url: &str) -> bool {
+        if !self.enabled || !is_absolute_url(url) {
+            return false;
+        }
+        match (url_host(url), self.site_host) {
+            (Some(host), Some(site_host)) => !host.eq_ignore_ascii_case(site_host),
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+}
+
+impl<'a, 's, I> Iterator for ExternalLinks<'a, 's, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let event = self.iter.next()?;
+        match event {
+            Event::Start(Tag::Link {
+                ref dest_url,
+                ref title,
+                ..
+            }) if self.is_external(dest_url) => {
+                // Links can't contain other links, so we only need to remember
+                // this one to find its end.
+                self.in_external = true;
+                let mut html = String::from("<a href=\"");
+                escape_href(&mut html, dest_url).unwrap();
+                if !title.is_empty() {
+                    html.push_str("\" title=\"");
+                    escape_html(&mut html, title).unwrap();
+                }
+                html.push_str("\" target=\"_blank\" rel=\"noopener noreferrer\">");
+                Some(Event::InlineHtml(CowStr::from(html)))
+            }
+            Event::End(TagEnd::Link) if self.in_external => {
+                self.in_external = false;
+                Some(Event::InlineHtml(CowStr::from("</a>")))
+            }
+            _ => Some(event),
+        }
+    }
+}
+
+/// Get the host name from a URL with an authority part, like
+/// `https://user@example.com:8080/path`.
+pub fn url_host(url: &str) -> Option<&str> {
+    let (_, rest) = url.split_once("//")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit_once('@').map_or(authority, |(_, h)| h);
+    let host = host.split(':').next()?;
+    (!host.is_empty()).then_some(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{Parser, html};
+
+    fn render_external(source: &str, site_host: Option<&str>) -> String {
+        let parser = Parser::new(source);
+
+        let mut buf = String::new();
+        html::push_html(&mut buf, ExternalLinks::new(parser, true, site_host));
+        buf
+    }
+
+    #[test]
+    fn external_link() {
+        assert_eq!(
+            render_external("[*hi*](https://foo.org/a?b&c \"Foo\")", None),
+            "<p><a href=\"https://foo.org/a?b&amp;c\" title=\"Foo\" target=\"_blank\" \
+             rel=\"noopener noreferrer\"><em>hi</em></a></p>\n"
+        );
+    }
+
+    #[test]
+    fn relative_link() {
+        assert_eq!(
+            render_external("[hi](bar.html) [top](/index.html)", None),
+            "<p><a href=\"bar.html\">hi</a> <a href=\"/index.html\">top</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn own_site_link() {
+        assert_eq!(
+            render_external(
+                "[hi](https://Notes.example.com/a.html)",
+                Some("notes.example.com")
+            ),
+            "<p><a href=\"https://Notes.example.com/a.html\">hi</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn disabled() {
+        let parser = Parser::new("[hi](https://foo.org)");
+        let mut buf = String::new();
+        html::push_html(&mut buf, ExternalLinks::new(parser, false, None));
+        assert_eq!(buf, "<p><a href=\"https://foo.org\">hi</a></p>\n");
+    }
+
+    #[test]
+    fn mailto_link() {
+        assert_eq!(
+            render_external("[hi](mailto:me@foo.org)", None),
+            "<p><a href=\"mailto:me@foo.org\">hi</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn hosts() {
+        assert_eq!(url_host("https://foo.org"), Some("foo.org"));
+        assert_eq!(url_host("//foo.org/a//b"), Some("foo.org"));
+        assert_eq!(url_host("http://me@foo.org:8080/"), Some("foo.org"));
+        assert_eq!(url_host("mailto:me@foo.org"), None);
+    }
+}
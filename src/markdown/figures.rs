@@ -0,0 +1,148 @@
+use super::util::take_until;
+use pulldown_cmark::{CowStr, Event, Tag, TagEnd};
+use pulldown_cmark_escape::escape_html;
+use std::collections::VecDeque;
+
+/// A pulldown-cmark adapter that renders images as figures, with their alt text
+/// as a caption. This only applies to an image that is alone in its paragraph
+/// and has some alt text; other images are left as they are.
+pub struct ImageFigures<'a, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    iter: I,
+    buffer: VecDeque<Event<'a>>,
+}
+
+impl<'a, I> ImageFigures<'a, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    pub fn new(iter: I) -> Self {
+        Self {
+            iter,
+            buffer: VecDeque::new(),
+        }
+    }
+}
+
+impl<'a, I> Iterator for ImageFigures<'a, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Unbuffer the next buffered event, if any.
+        if let Some(event) = self.buffer.pop_front() {
+            return Some(event);
+        }
+
+        let event = self.iter.next()?;
+        if !matches!(event, Event::Start(Tag::Paragraph)) {
+            return Some(event);
+        }
+
+        // Paragraphs only contain inline content, so the first paragraph end
+        // is this one's.
+        let mut para: Vec<_> = take_until(self.iter.by_ref(), |e| {
+            matches!(e, Event::End(TagEnd::Paragraph))
+        })
+        .collect();
+        match caption(&para) {
+            Some(caption) => {
+                para.pop(); // The paragraph end.
+                self.buffer
+                    .push_back(Event::Html(CowStr::from("<figure>\n")));
+                self.buffer.extend(para);
+                let mut html = String::from("\n<figcaption>");
+                escape_html(&mut html, &caption).unwrap();
+                html.push_str("</figcaption>\n</figure>\n");
+                self.buffer.push_back(Event::Html(html.into()));
+            }
+            None => {
+                self.buffer.push_back(event);
+                self.buffer.extend(para);
+            }
+        }
+        self.buffer.pop_front()
+    }
+}
+
+/// If the events in a paragraph (up to and including its end) are just one
+/// image with alt text, get that text.
+fn caption(para: &[Event]) -> Option<String> {
+    let [
+        Event::Start(Tag::Image { .. }),
+        inner @ ..,
+        Event::End(TagEnd::Image),
+        _,
+    ] = para
+    else {
+        return None;
+    };
+
+    // Make sure the first image's end is the last one, not one for an image
+    // nested in its alt text or a second image in the same paragraph.
+    let mut depth = 0;
+    let mut text = String::new();
+    for event in inner {
+        match event {
+            Event::Start(Tag::Image { .. }) => depth += 1,
+            Event::End(TagEnd::Image) if depth == 0 => return None,
+            Event::End(TagEnd::Image) => depth -= 1,
+            Event::Text(t) | Event::Code(t) | Event::InlineHtml(t) => text.push_str(t),
+            _ => (),
+        }
+    }
+
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{Parser, html};
+
+    fn render_figures(source: &str) -> String {
+        let parser = Parser::new(source);
+
+        let mut buf = String::new();
+        html::push_html(&mut buf, ImageFigures::new(parser));
+        buf
+    }
+
+    #[test]
+    fn standalone_image() {
+        assert_eq!(
+            render_figures("![A *big* <cat>](cat.png)"),
+            "<figure>\n<img src=\"cat.png\" alt=\"A big &lt;cat&gt;\" />\n\
+             <figcaption>A big &lt;cat&gt;</figcaption>\n</figure>\n"
+        );
+    }
+
+    #[test]
+    fn inline_image() {
+        assert_eq!(
+            render_figures("See ![cat](cat.png) here."),
+            "<p>See <img src=\"cat.png\" alt=\"cat\" /> here.</p>\n"
+        );
+    }
+
+    #[test]
+    fn no_alt_text() {
+        assert_eq!(
+            render_figures("![](cat.png)"),
+            "<p><img src=\"cat.png\" alt=\"\" /></p>\n"
+        );
+    }
+
+    #[test]
+    fn two_images() {
+        assert_eq!(
+            render_figures("![a](a.png)![b](b.png)"),
+            "<p><img src=\"a.png\" alt=\"a\" /><img src=\"b.png\" alt=\"b\" /></p>\n"
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use super::util::take_until;
+use pulldown_cmark::{CowStr, Event, Tag, TagEnd};
+use pulldown_cmark_escape::escape_html;
+use std::collections::{HashMap, VecDeque};
+
+/// A pulldown-cmark adapter that moves footnote definitions out of the body and
+/// into a list at the end of the document. Footnotes are numbered in the order
+/// they are first referenced, and each one links back to every reference to
+/// it. Definitions that are never referenced are dropped.
+pub struct MoveFootnotes<'a, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    iter: I,
+    buffer: VecDeque<Event<'a>>,
+    /// Footnote labels, in order of first reference.
+    order: Vec<CowStr<'a>>,
+    /// The number of references to each footnote so far.
+    refs: HashMap<CowStr<'a>, usize>,
+    /// The contents of each footnote definition.
+    defs: HashMap<CowStr<'a>, Vec<Event<'a>>>,
+    done: bool,
+}
+
+impl<'a, I> MoveFootnotes<'a, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    pub fn new(iter: I) -> Self {
+        Self {
+            iter,
+            buffer: VecDeque::new(),
+            order: vec![],
+            refs: HashMap::new(),
+            defs: HashMap::new(),
+            done: false,
+        }
+    }
+
+    /// Render a reference to a footnote as a numbered superscript link.
+    fn reference(&mut self, label: CowStr<'a>) -> Event<'a> {
+        let count = self.refs.entry(label.clone()).or_insert(0);
+        *count += 1;
+        let count = *count;
+        if count == 1 {
+            self.order.push(label.clone());
+        }
+        let number = self.order.iter().position(|l| *l == label).unwrap() + 1;
+
+        let mut html = String::from("<sup class=\"footnote-ref\"><a href=\"#fn-");
+        escape_html(&mut html, &label).unwrap();
+        html.push_str("\" id=\"");
+        push_ref_id(&mut html, &label, count);
+        html.push_str(&format!("\">{number}</a></sup>"));
+        Event::InlineHtml(html.into())
+    }
+
+    /// Assuming that `self` is now just after the beginning of a footnote
+    /// definition, consume and save its contents.
+    fn define(&mut self, label: CowStr<'a>) {
+        let mut events: Vec<_> = take_until(self.iter.by_ref(), |e| {
+            matches!(e, Event::End(TagEnd::FootnoteDefinition))
+        })
+        .collect();
+        events.pop(); // The definition end.
+        let events = events
+            .into_iter()
+            .map(|event| match event {
+                Event::FootnoteReference(label) => self.reference(label),
+                event => event,
+            })
+            .collect();
+        self.defs.entry(label).or_insert(events);
+    }
+
+    /// Buffer up the list of footnotes for the end of the document.
+    fn finish(&mut self) {
+        if self.order.is_empty() {
+            return;
+        }
+        self.buffer
+            .push_back(Event::Html("<section class=\"footnotes\">\n<ol>\n".into()));
+        for label in &self.order {
+            let Some(mut events) = self.defs.remove(label) else {
+                continue;
+            };
+            let mut html = String::from("<li id=\"fn-");
+            escape_html(&mut html, label).unwrap();
+            html.push_str("\">\n");
+            self.buffer.push_back(Event::Html(html.into()));
+
+            // Put the back-references at the end of the last paragraph, if
+            // the footnote ends with one.
+            let mut backrefs = String::new();
+            for count in 1..=self.refs[label] {
+                backrefs.push_str(" <a href=\"#");
+                push_ref_id(&mut backrefs, label, count);
+                backrefs.push_str("\" class=\"footnote-backref\">↩</a>");
+            }
+            if let Some(Event::End(TagEnd::Paragraph)) = events.last() {
+                let end = events.pop().unwrap();
+                self.buffer.extend(events);
+                self.buffer.push_back(Event::InlineHtml(backrefs.into()));
+                self.buffer.push_back(end);
+            } else {
+                self.buffer.extend(events);
+                backrefs.push('\n');
+                self.buffer.push_back(Event::Html(backrefs.into()));
+            }
+            self.buffer.push_back(Event::Html("</li>\n".into()));
+        }
+        self.buffer
+            .push_back(Event::Html("</ol>\n</section>\n".into()));
+    }
+}
+
+/// Write the HTML id for the `count`th reference to a footnote.
+fn push_ref_id(html: &mut String, label: &str, count: usize) {
+    html.push_str("fnref-");
+    escape_html(&mut *html, label).unwrap();
+    if count > 1 {
+        html.push_str(&format!("-{count}"));
+    }
+}
+
+impl<'a, I> Iterator for MoveFootnotes<'a, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Unbuffer the next buffered event, if any.
+        if let Some(event) = self.buffer.pop_front() {
+            return Some(event);
+        }
+
+        loop {
+            let Some(event) = self.iter.next() else {
+                if self.done {
+                    return None;
+                }
+                self.done = true;
+                self.finish();
+                return self.buffer.pop_front();
+            };
+            match event {
+                Event::Start(Tag::FootnoteDefinition(label)) => self.define(label),
+                Event::FootnoteReference(label) => return Some(self.reference(label)),
+                event => return Some(event),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{Options, Parser, html::push_html};
+
+    fn render(source: &str) -> String {
+        let parser = Parser::new_ext(source, Options::ENABLE_FOOTNOTES);
+        let mut html = String::new();
+        push_html(&mut html, MoveFootnotes::new(parser));
+        html
+    }
+
+    #[test]
+    fn no_footnotes() {
+        assert_eq!(render("Hello.\n"), "<p>Hello.</p>\n");
+    }
+
+    #[test]
+    fn reference_order() {
+        let html = render("[^b]: Bee.\n\nOne[^a] and two[^b].\n\n[^a]: Ay.\n\nAfter.\n");
+        assert_eq!(
+            html,
+            "<p>One<sup class=\"footnote-ref\"><a href=\"#fn-a\" id=\"fnref-a\">1</a></sup> \
+             and two<sup class=\"footnote-ref\"><a href=\"#fn-b\" id=\"fnref-b\">2</a></sup>.</p>\n\
+             <p>After.</p>\n\
+             <section class=\"footnotes\">\n<ol>\n\
+             <li id=\"fn-a\">\n<p>Ay. <a href=\"#fnref-a\" class=\"footnote-backref\">↩</a></p>\n</li>\n\
+             <li id=\"fn-b\">\n<p>Bee. <a href=\"#fnref-b\" class=\"footnote-backref\">↩</a></p>\n</li>\n\
+             </ol>\n</section>\n"
+        );
+    }
+
+    #[test]
+    fn repeated_reference() {
+        let html = render("A[^n], b[^n].\n\n[^n]: Note.\n");
+        assert!(html.starts_with(
+            "<p>A<sup class=\"footnote-ref\"><a href=\"#fn-n\" id=\"fnref-n\">1</a></sup>, \
+             b<sup class=\"footnote-ref\"><a href=\"#fn-n\" id=\"fnref-n-2\">1</a></sup>.</p>\n"
+        ));
+        assert!(html.contains(
+            "<p>Note. <a href=\"#fnref-n\" class=\"footnote-backref\">↩</a> \
+             <a href=\"#fnref-n-2\" class=\"footnote-backref\">↩</a></p>\n"
+        ));
+    }
+
+    #[test]
+    fn unreferenced_definition() {
+        assert_eq!(render("Text.\n\n[^x]: Unused.\n"), "<p>Text.</p>\n");
+    }
+
+    #[test]
+    fn block_footnote() {
+        let html = render("A[^n].\n\n[^n]:\n    ```\n    code\n    ```\n");
+        assert!(html.contains(
+            "<li id=\"fn-n\">\n<pre><code>code\n</code></pre>\n \
+             <a href=\"#fnref-n\" class=\"footnote-backref\">↩</a>\n</li>\n"
+        ));
+    }
+}
@@ -0,0 +1,376 @@
+use super::util::take_until;
+use pulldown_cmark::{Event, MetadataBlockKind, Options, Parser, Tag, TagEnd};
+use serde::Deserialize;
+use std::collections::{BTreeMap, VecDeque};
+
+/// Metadata from a note's front matter block, which can be written in YAML
+/// (between `---` lines) or TOML (between `+++` lines).
+#[derive(Debug, Default, Deserialize)]
+pub struct FrontMatter {
+    /// Drafts are notes that are not ready for public consumption.
+    #[serde(default)]
+    pub draft: bool,
+
+    /// A value for the `robots` meta tag.
+    pub robots: Option<String>,
+
+    /// The publication date.
+    #[serde(default, deserialize_with = "deserialize_date")]
+    pub date: Option<jiff::civil::Date>,
+
+    /// A short summary, which is the note's excerpt if present.
+    pub description: Option<String>,
+
+    /// A fold marker that ends the note's excerpt, instead of the site's.
+    pub excerpt_separator: Option<String>,
+
+    /// Tags for finding related notes.
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    /// Attributes for the page's `<html>` tag, which override any that are
+    /// configured for the whole site.
+    #[serde(default)]
+    pub html_attrs: BTreeMap<String, String>,
+    /// The name of the template to render the note with, instead of the usual
+    /// one.
+    pub template: Option<String>,
+
+    /// Every key and value in the block, including ones that mean nothing to
+    /// us, so templates can use them.
+    #[serde(skip)]
+    pub values: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Dates can be strings or, in TOML, native date values.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DateValue {
+    Str(jiff::civil::Date),
+    Toml(toml::value::Datetime),
+}
+
+fn deserialize_date<'de, D>(deserializer: D) -> Result<Option<jiff::civil::Date>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    match Option::<DateValue>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(DateValue::Str(date)) => Ok(Some(date)),
+        Some(DateValue::Toml(dt)) => match dt.date {
+            Some(d) => jiff::civil::Date::new(d.year as i16, d.month as i8, d.day as i8)
+                .map(Some)
+                .map_err(serde::de::Error::custom),
+            None => Err(serde::de::Error::custom("date value has no date")),
+        },
+    }
+}
+
+impl FrontMatter {
+    /// Parse the YAML source of a front matter block.
+    pub fn parse(source: &str) -> serde_yaml::Result<Self> {
+        // An empty block parses as YAML `null`, which we treat as no metadata.
+        match serde_yaml::from_str::<Option<Self>>(source)? {
+            Some(fm) => Ok(fm),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Parse the TOML source of a front matter block.
+    pub fn parse_toml(source: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(source)
+    }
+
+    /// Is the note dated after `today`? Like drafts, these notes aren't ready to
+    /// be published yet.
+    pub fn is_scheduled(&self, today: jiff::civil::Date) -> bool {
+        self.date.is_some_and(|date| date > today)
+    }
+
+    /// The `robots` meta tag value for the note. Drafts are never indexed;
+    /// otherwise, we use the value from the front matter or allow indexing.
+    pub fn robots(&self) -> &str {
+        if self.draft {
+            "noindex,nofollow"
+        } else {
+            self.robots.as_deref().unwrap_or("index,follow")
+        }
+    }
+}
+
+/// The source text of a document's metadata block, along with the style of
+/// delimiter that marked it.
+#[derive(Debug, Default)]
+pub struct RawFrontMatter {
+    /// The block's delimiter style, or None if the document has no block.
+    pub kind: Option<MetadataBlockKind>,
+
+    /// The contents of the block.
+    pub source: String,
+}
+
+impl RawFrontMatter {
+    /// Parse the block as YAML or TOML, according to its delimiters.
+    pub fn parse(&self) -> anyhow::Result<FrontMatter> {
+        Ok(match self.kind {
+            Some(MetadataBlockKind::PlusesStyle) => {
+                let mut fm = FrontMatter::parse_toml(&self.source)?;
+                let table: toml::Table = toml::from_str(&self.source)?;
+                if let serde_json::Value::Object(values) = toml_to_json(table.into()) {
+                    fm.values = values;
+                }
+                fm
+            }
+            Some(MetadataBlockKind::YamlStyle) | None => {
+                let mut fm = FrontMatter::parse(&self.source)?;
+                fm.values = serde_yaml::from_str::<Option<_>>(&self.source)?.unwrap_or_default();
+                fm
+            }
+        })
+    }
+}
+
+/// Convert a TOML value to JSON. Dates and times become strings.
+fn toml_to_json(value: toml::Value) -> serde_json::Value {
+    use serde_json::Value;
+    match value {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(i) => i.into(),
+        toml::Value::Float(f) => f.into(),
+        toml::Value::Boolean(b) => b.into(),
+        toml::Value::Datetime(dt) => Value::String(dt.to_string()),
+        toml::Value::Array(items) => items.into_iter().map(toml_to_json).collect(),
+        toml::Value::Table(table) => table
+            .into_iter()
+            .map(|(k, v)| (k, toml_to_json(v)))
+            .collect(),
+    }
+}
+
+/// A pulldown-cmark adapter that extracts the source text of a metadata block
+/// from a Markdown document. When this iterator runs, it appends the contents
+/// of any metadata block to a buffer that you supply.
+///
+/// Only a block at the very start of the document counts as front matter.
+/// pulldown-cmark also recognizes blocks later on, but those are ordinary
+/// Markdown that happens to use the same delimiters, so we parse them again as
+/// such, with the same `options` as the rest of the document.
+pub struct ExtractFrontMatter<'a, 'b, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    iter: I,
+    options: Options,
+    raw: &'b mut RawFrontMatter,
+    in_block: bool,
+    started: bool,
+    buffer: VecDeque<Event<'a>>,
+}
+
+impl<'a, 'b, I> ExtractFrontMatter<'a, 'b, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    pub fn new(iter: I, options: Options, raw: &'b mut RawFrontMatter) -> Self {
+        Self {
+            iter,
+            options,
+            raw,
+            in_block: false,
+            started: false,
+            buffer: VecDeque::new(),
+        }
+    }
+
+    /// Assuming that `self` is now just after the beginning of a metadata
+    /// block that isn't front matter, buffer up the events for its source.
+    fn reparse_block(&mut self, kind: MetadataBlockKind) {
+        let delimiter = match kind {
+            MetadataBlockKind::YamlStyle => "---",
+            MetadataBlockKind::PlusesStyle => "+++",
+        };
+        let mut source = format!("{delimiter}\n");
+        for event in take_until(self.iter.by_ref(), |e| {
+            matches!(e, Event::End(TagEnd::MetadataBlock(_)))
+        }) {
+            if let Event::Text(text) = event {
+                source.push_str(&text);
+            }
+        }
+        source.push_str(delimiter);
+        // Without metadata blocks, so the delimiters read as ordinary Markdown.
+        let options = self.options
+            - Options::ENABLE_YAML_STYLE_METADATA_BLOCKS
+            - Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS;
+        self.buffer
+            .extend(Parser::new_ext(&source, options).map(|e| e.into_static()));
+    }
+}
+
+impl<'a, 'b, I> Iterator for ExtractFrontMatter<'a, 'b, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Unbuffer the next buffered event, if any.
+        if let Some(event) = self.buffer.pop_front() {
+            return Some(event);
+        }
+
+        let event = self.iter.next()?;
+        let first = !self.started;
+        self.started = true;
+        match &event {
+            Event::Start(Tag::MetadataBlock(kind)) if !first => {
+                self.reparse_block(*kind);
+                return self.next();
+            }
+            Event::Start(Tag::MetadataBlock(kind)) => {
+                self.raw.kind = Some(*kind);
+                self.in_block = true;
+            }
+            Event::End(TagEnd::MetadataBlock(_)) => self.in_block = false,
+            Event::Text(text) if self.in_block => self.raw.source.push_str(text),
+            _ => (),
+        }
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{Options, Parser};
+
+    fn extract(source: &str) -> RawFrontMatter {
+        let mut options = Options::empty();
+        options.insert(Options::ENABLE_YAML_STYLE_METADATA_BLOCKS);
+        options.insert(Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS);
+        let parser = Parser::new_ext(source, options);
+        let mut raw = RawFrontMatter::default();
+        ExtractFrontMatter::new(parser, options, &mut raw).for_each(|_| {});
+        raw
+    }
+
+    fn get_front_matter(source: &str) -> String {
+        extract(source).source
+    }
+
+    #[test]
+    fn no_block() {
+        assert_eq!(get_front_matter("# hi"), "");
+    }
+
+    #[test]
+    fn yaml_block() {
+        assert_eq!(
+            get_front_matter("---\ndraft: true\n---\n# hi"),
+            "draft: true\n"
+        );
+    }
+
+    #[test]
+    fn toml_block() {
+        let raw = extract("+++\ndraft = true\n+++\n# hi");
+        assert_eq!(raw.kind, Some(MetadataBlockKind::PlusesStyle));
+        assert_eq!(raw.source, "draft = true\n");
+    }
+
+    #[test]
+    fn toml_block_mid_body() {
+        let source = "# hi\n\n+++\ndraft = true\n+++\n";
+        let raw = extract(source);
+        assert_eq!(raw.kind, None);
+        assert!(!raw.parse().unwrap().draft);
+
+        let mut options = Options::empty();
+        options.insert(Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS);
+        let mut raw = RawFrontMatter::default();
+        let parser = ExtractFrontMatter::new(Parser::new_ext(source, options), options, &mut raw);
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(&mut html, parser);
+        assert_eq!(html, "<h1>hi</h1>\n<p>+++\ndraft = true\n+++</p>\n");
+
+        // The block is parsed with the document's options.
+        let source = "# hi\n\n+++\ntitle = \"x\"\n+++\n";
+        options.insert(Options::ENABLE_SMART_PUNCTUATION);
+        let parser = ExtractFrontMatter::new(Parser::new_ext(source, options), options, &mut raw);
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(&mut html, parser);
+        assert_eq!(html, "<h1>hi</h1>\n<p>+++\ntitle = “x”\n+++</p>\n");
+    }
+
+    #[test]
+    fn yaml_block_mid_body() {
+        let raw = extract("# hi\n\n---\ndraft: true\n---\n");
+        assert_eq!(raw.kind, None);
+        assert_eq!(raw.source, "");
+    }
+
+    #[test]
+    fn yaml_and_toml_agree() {
+        let yaml = extract("---\ndraft: true\ndate: 2024-03-01\ntags: [a, b]\n---\n# hi")
+            .parse()
+            .unwrap();
+        let toml =
+            extract("+++\ndraft = true\ndate = 2024-03-01\ntags = [\"a\", \"b\"]\n+++\n# hi")
+                .parse()
+                .unwrap();
+        for fm in [yaml, toml] {
+            assert!(fm.draft);
+            assert_eq!(fm.date, Some(jiff::civil::date(2024, 3, 1)));
+            assert_eq!(fm.tags, ["a", "b"]);
+        }
+    }
+
+    #[test]
+    fn all_values() {
+        let yaml = extract("---\ndraft: true\nnav: [a, b]\n---\n# hi")
+            .parse()
+            .unwrap();
+        let toml = extract("+++\ndraft = true\nnav = [\"a\", \"b\"]\ndate = 2024-03-01\n+++\n# hi")
+            .parse()
+            .unwrap();
+        assert_eq!(yaml.values["nav"], serde_json::json!(["a", "b"]));
+        assert_eq!(yaml.values["draft"], true);
+        assert_eq!(toml.values["nav"], serde_json::json!(["a", "b"]));
+        assert_eq!(toml.values["date"], "2024-03-01");
+        assert!(extract("# hi").parse().unwrap().values.is_empty());
+    }
+
+    #[test]
+    fn parse_draft() {
+        let fm = FrontMatter::parse("draft: true\n").unwrap();
+        assert!(fm.draft);
+        assert_eq!(fm.robots(), "noindex,nofollow");
+    }
+
+    #[test]
+    fn parse_empty() {
+        let fm = FrontMatter::parse("").unwrap();
+        assert!(!fm.draft);
+        assert_eq!(fm.robots(), "index,follow");
+    }
+
+    #[test]
+    fn parse_date() {
+        let fm = FrontMatter::parse("date: 2024-03-01\n").unwrap();
+        assert_eq!(fm.date, Some(jiff::civil::date(2024, 3, 1)));
+    }
+
+    #[test]
+    fn scheduled() {
+        let fm = FrontMatter::parse("date: 2024-03-01\n").unwrap();
+        assert!(fm.is_scheduled(jiff::civil::date(2024, 2, 29)));
+        assert!(!fm.is_scheduled(jiff::civil::date(2024, 3, 1)));
+        assert!(!FrontMatter::default().is_scheduled(jiff::civil::date(2024, 3, 1)));
+    }
+
+    #[test]
+    fn parse_robots() {
+        let fm = FrontMatter::parse("robots: noindex\n").unwrap();
+        assert_eq!(fm.robots(), "noindex");
+    }
+}
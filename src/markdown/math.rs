@@ -0,0 +1,161 @@
+use super::util::take_until;
+use pulldown_cmark::{CowStr, Event, Tag, TagEnd};
+use pulldown_cmark_escape::escape_html;
+use std::collections::VecDeque;
+
+/// A pulldown-cmark adapter that renders `$...$` and `$$...$$` math as HTML
+/// for KaTeX's auto-render extension (or MathJax) to typeset. Inline math
+/// becomes a `<span class="math inline">` and display math becomes a
+/// `<div class="math display">`, each with the standard `\(...\)` or `\[...\]`
+/// delimiters around the escaped TeX source.
+///
+/// A paragraph containing nothing but display math is replaced by its `<div>`.
+/// Display math in the middle of a paragraph becomes a
+/// `<span class="math display">` instead, since a `<div>` can't appear inside
+/// a `<p>`; the stylesheet makes it a block. When this iterator runs, it
+/// records whether the document contained any math.
+pub struct RenderMath<'a, 'b, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    iter: I,
+    buffer: VecDeque<Event<'a>>,
+    found: &'b mut bool,
+}
+
+impl<'a, 'b, I> RenderMath<'a, 'b, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    pub fn new(iter: I, found: &'b mut bool) -> Self {
+        Self {
+            iter,
+            buffer: VecDeque::new(),
+            found,
+        }
+    }
+
+    /// Convert a math event to HTML, leaving other events alone.
+    fn convert(&mut self, event: Event<'a>) -> Event<'a> {
+        match event {
+            Event::InlineMath(tex) => {
+                *self.found = true;
+                Event::InlineHtml(math_html("span", "inline", "\\(", &tex, "\\)").into())
+            }
+            Event::DisplayMath(tex) => {
+                *self.found = true;
+                Event::InlineHtml(math_html("span", "display", "\\[", &tex, "\\]").into())
+            }
+            e => e,
+        }
+    }
+}
+
+impl<'a, I> Iterator for RenderMath<'a, '_, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Unbuffer the next buffered event, if any.
+        if let Some(event) = self.buffer.pop_front() {
+            return Some(event);
+        }
+
+        let event = self.iter.next()?;
+        if !matches!(event, Event::Start(Tag::Paragraph)) {
+            return Some(self.convert(event));
+        }
+
+        // Paragraphs only contain inline content, so the first paragraph end
+        // is this one's.
+        let para: Vec<_> = take_until(self.iter.by_ref(), |e| {
+            matches!(e, Event::End(TagEnd::Paragraph))
+        })
+        .collect();
+        if let [Event::DisplayMath(tex), Event::End(TagEnd::Paragraph)] = para.as_slice() {
+            *self.found = true;
+            let mut html = math_html("div", "display", "\\[", tex, "\\]");
+            html.push('\n');
+            return Some(Event::Html(CowStr::from(html)));
+        }
+
+        self.buffer.push_back(event);
+        for event in para {
+            let event = self.convert(event);
+            self.buffer.push_back(event);
+        }
+        self.buffer.pop_front()
+    }
+}
+
+/// Wrap some escaped TeX in delimiters and an element with the `math` class.
+fn math_html(elem: &str, class: &str, open: &str, tex: &str, close: &str) -> String {
+    let mut html = format!("<{elem} class=\"math {class}\">{open}");
+    escape_html(&mut html, tex).unwrap();
+    html.push_str(close);
+    html.push_str("</");
+    html.push_str(elem);
+    html.push('>');
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{Options, Parser, html};
+
+    fn render_math(source: &str) -> (String, bool) {
+        let parser = Parser::new_ext(source, Options::ENABLE_MATH);
+
+        let mut found = false;
+        let mut buf = String::new();
+        html::push_html(&mut buf, RenderMath::new(parser, &mut found));
+        (buf, found)
+    }
+
+    #[test]
+    fn inline_math() {
+        assert_eq!(
+            render_math("Euler: $e^{i\\pi} < 0$."),
+            (
+                "<p>Euler: <span class=\"math inline\">\\(e^{i\\pi} &lt; 0\\)</span>.</p>\n".into(),
+                true
+            )
+        );
+    }
+
+    #[test]
+    fn display_math() {
+        assert_eq!(
+            render_math("$$\n\\sum_i x_i\n$$"),
+            (
+                "<div class=\"math display\">\\[\n\\sum_i x_i\n\\]</div>\n".into(),
+                true
+            )
+        );
+    }
+
+    #[test]
+    fn display_math_in_text() {
+        assert_eq!(
+            render_math("So $$x$$ holds."),
+            (
+                "<p>So <span class=\"math display\">\\[x\\]</span> holds.</p>\n".into(),
+                true
+            )
+        );
+    }
+
+    #[test]
+    fn dollars_in_code() {
+        assert_eq!(
+            render_math("`$x$`\n\n```\necho $HOME $$\n```"),
+            (
+                "<p><code>$x$</code></p>\n<pre><code>echo $HOME $$\n</code></pre>\n".into(),
+                false
+            )
+        );
+    }
+}
@@ -1,28 +1,405 @@
 mod add_ids;
+mod code_blocks;
+mod excerpt;
+mod external_links;
+mod figures;
+mod footnotes;
+mod front_matter;
+mod math;
+mod raw_html;
 mod rel_links;
 mod toc;
+mod util;
+mod wikilinks;
+mod words;
 
-use pulldown_cmark::{Options, Parser, html::push_html};
+pub use add_ids::{SlugOptions, SlugStyle, slugify};
+pub use code_blocks::CodeHandlers;
+pub use excerpt::DEFAULT_SEPARATOR as DEFAULT_EXCERPT_SEPARATOR;
+pub use external_links::url_host;
+pub use front_matter::FrontMatter;
+pub use pulldown_cmark::HeadingLevel;
+pub use rel_links::LinkStyle;
+pub use toc::{ALL_LEVELS as ALL_TOC_LEVELS, TocNode, nest as nest_toc};
+pub use util::percent_encode_path;
+pub use words::reading_minutes;
 
-pub fn render(source: &str) -> (String, Vec<toc::TocEntry>) {
-    let mut options = Options::empty();
-    options.insert(Options::ENABLE_HEADING_ATTRIBUTES);
-    options.insert(Options::ENABLE_SMART_PUNCTUATION);
-    options.insert(Options::ENABLE_TABLES);
-    options.insert(Options::ENABLE_FOOTNOTES);
-    options.insert(Options::ENABLE_TASKLISTS);
-    options.insert(Options::ENABLE_YAML_STYLE_METADATA_BLOCKS);
+use pulldown_cmark::{Event, Options, Parser, TagEnd, html::push_html};
+use pulldown_cmark_escape::escape_html;
+use std::ops::RangeInclusive;
 
-    // TODO gather top-level heading as title
+/// The results of rendering a Markdown document.
+pub struct Rendered {
+    /// The HTML body.
+    pub body: String,
+
+    /// The document's title: its first heading, if that's an `h1`.
+    pub title: Option<String>,
+
+    /// The headings in the document with levels in `toc_levels`.
+    pub toc: Vec<toc::TocEntry>,
+
+    /// Metadata from the document's front matter, if any.
+    pub front_matter: FrontMatter,
+
+    /// A short HTML summary of the document. This is the `description` from
+    /// the front matter, if any; otherwise, everything before the excerpt
+    /// separator, if the document has one; otherwise, its first paragraph.
+    pub excerpt: String,
+
+    /// The number of words of prose in the document.
+    pub word_count: usize,
+
+    /// The document's prose as plain text, without markup or code.
+    pub text: String,
+
+    /// Whether the document contains any math.
+    pub math: bool,
+
+    /// The relative URLs that the document links to, including images.
+    pub links: Vec<String>,
+}
+
+impl Rendered {
+    /// Wrap a document that is already HTML, without any Markdown processing.
+    /// It has no headings, front matter, or excerpt of its own.
+    pub fn from_html(html: String) -> Self {
+        Self {
+            body: html,
+            title: None,
+            toc: vec![],
+            front_matter: FrontMatter::default(),
+            excerpt: String::new(),
+            word_count: 0,
+            text: String::new(),
+            math: false,
+            links: vec![],
+        }
+    }
+}
+
+/// Settings for how links in a document are rendered.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LinkOptions<'s> {
+    /// Where notes' pages go, which determines how links to them look.
+    pub style: LinkStyle,
+
+    /// Make links to other sites open in a new tab.
+    pub new_tab: bool,
+
+    /// The host where the site itself lives, if known. Links to this host are
+    /// never treated as links to other sites.
+    pub site_host: Option<&'s str>,
+
+    /// Turn `[[Some Note]]` into a link to the note `Some Note.md`.
+    pub wikilinks: bool,
+}
+
+/// Settings for rendering a Markdown document.
+#[derive(Clone, Debug)]
+pub struct RenderOptions<'s> {
+    /// The marker that ends the document's excerpt. Documents can override
+    /// this in their front matter.
+    pub excerpt_separator: &'s str,
+
+    /// How links look.
+    pub links: LinkOptions<'s>,
+
+    /// How headings' IDs are made.
+    pub slugs: SlugOptions,
+
+    /// A prefix for headings' generated IDs, for documents that share a page
+    /// with others.
+    pub id_prefix: &'s str,
+
+    /// Put `id_prefix` on headings' explicit IDs too.
+    pub prefix_explicit_ids: bool,
+
+    /// Remove raw HTML from the document, for untrusted sources. Comments are
+    /// kept.
+    pub sanitize_html: bool,
+
+    /// The levels of the headings that go in the table of contents. The body
+    /// keeps every heading either way.
+    pub toc_levels: RangeInclusive<HeadingLevel>,
+}
+
+impl Default for RenderOptions<'_> {
+    fn default() -> Self {
+        Self {
+            excerpt_separator: DEFAULT_EXCERPT_SEPARATOR,
+            links: LinkOptions::default(),
+            slugs: SlugOptions::default(),
+            id_prefix: "",
+            prefix_explicit_ids: false,
+            sanitize_html: false,
+            toc_levels: toc::ALL_LEVELS,
+        }
+    }
+}
+
+/// The parser extensions to enable for a document.
+fn parse_options(options: &RenderOptions) -> Options {
+    let mut parse_options = Options::empty();
+    parse_options.insert(Options::ENABLE_HEADING_ATTRIBUTES);
+    parse_options.insert(Options::ENABLE_SMART_PUNCTUATION);
+    parse_options.insert(Options::ENABLE_TABLES);
+    parse_options.insert(Options::ENABLE_FOOTNOTES);
+    parse_options.insert(Options::ENABLE_TASKLISTS);
+    parse_options.insert(Options::ENABLE_MATH);
+    parse_options.insert(Options::ENABLE_DEFINITION_LIST);
+    parse_options.insert(Options::ENABLE_YAML_STYLE_METADATA_BLOCKS);
+    parse_options.insert(Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS);
+    if options.links.wikilinks {
+        parse_options.insert(Options::ENABLE_WIKILINKS);
+    }
+    parse_options
+}
+
+/// Render a Markdown document.
+pub fn render(
+    source: &str,
+    code_handlers: &CodeHandlers,
+    options: &RenderOptions,
+) -> anyhow::Result<Rendered> {
+    let link_options = &options.links;
+    let slugs = options.slugs;
+    let parse_options = parse_options(options);
 
     let mut html_buf = String::new();
     let mut toc_entries = vec![];
+    let mut raw_front_matter = front_matter::RawFrontMatter::default();
+    let mut events = vec![];
+    let mut math = false;
+    let mut links = vec![];
 
-    let iter = Parser::new_ext(source, options);
-    let iter = add_ids::AddHeadingIds::new(iter);
-    let iter = toc::TableOfContents::new(iter, &mut toc_entries);
-    let iter = rel_links::RewriteRelativeLinks::new(iter);
+    let iter = Parser::new_ext(source, parse_options);
+    let iter = front_matter::ExtractFrontMatter::new(iter, parse_options, &mut raw_front_matter);
+    // Strip the document's own HTML before other adapters add theirs.
+    let iter = raw_html::StripHtml::new(iter, options.sanitize_html);
+    // Add ids first so the TOC records the same anchors as the body.
+    let iter = add_ids::AddHeadingIds::new(iter, true, slugs)
+        .with_prefix(options.id_prefix, options.prefix_explicit_ids)
+        .reserve_explicit_ids(Parser::new_ext(source, parse_options));
+    // Collect every heading: the title comes from the first one.
+    let iter = toc::TableOfContents::new(iter, &mut toc_entries, toc::ALL_LEVELS);
+    let iter = footnotes::MoveFootnotes::new(iter);
+    let iter = wikilinks::WikiLinks::new(iter, slugs, options.id_prefix);
+    let iter = rel_links::RewriteRelativeLinks::new(iter, link_options.style);
+    let iter = rel_links::CollectLinks::new(iter, &mut links);
+    let iter =
+        external_links::ExternalLinks::new(iter, link_options.new_tab, link_options.site_host);
+    let iter = figures::ImageFigures::new(iter);
+    let iter = code_blocks::RenderCodeBlocks::new(iter, code_handlers);
+    let iter = math::RenderMath::new(iter, &mut math);
+    let iter = excerpt::RecordEvents::new(iter, &mut events);
 
     push_html(&mut html_buf, iter);
-    (html_buf, toc_entries)
+    let front_matter = raw_front_matter.parse()?;
+    let title = title(&toc_entries);
+    toc_entries.retain(|entry| options.toc_levels.contains(&entry.level));
+
+    let mut excerpt = String::new();
+    match &front_matter.description {
+        Some(description) => {
+            excerpt.push_str("<p>");
+            escape_html(&mut excerpt, description)?;
+            excerpt.push_str("</p>\n");
+        }
+        None => {
+            let separator = front_matter
+                .excerpt_separator
+                .as_deref()
+                .unwrap_or(options.excerpt_separator);
+            let events = excerpt::select(&events, separator);
+            push_html(&mut excerpt, events.iter().cloned());
+        }
+    }
+
+    let word_count = words::count(&events);
+    let text = words::plain_text(&events);
+    Ok(Rendered {
+        body: html_buf,
+        title,
+        toc: toc_entries,
+        front_matter,
+        excerpt,
+        word_count,
+        text,
+        math,
+        links,
+    })
+}
+
+/// Read a document's front matter and title without rendering it. The title is
+/// the one that `title` would find in the rendered document's TOC.
+pub fn read_metadata(
+    source: &str,
+    options: &RenderOptions,
+) -> anyhow::Result<(FrontMatter, Option<String>)> {
+    let mut raw_front_matter = front_matter::RawFrontMatter::default();
+    let mut toc_entries = vec![];
+    let parse_options = parse_options(options);
+    let iter = Parser::new_ext(source, parse_options);
+    let iter = front_matter::ExtractFrontMatter::new(iter, parse_options, &mut raw_front_matter);
+    let mut iter = toc::TableOfContents::new(iter, &mut toc_entries, toc::ALL_LEVELS);
+
+    // The front matter comes first, so we can stop after the first heading.
+    for event in iter.by_ref() {
+        if let Event::End(TagEnd::Heading(_)) = event {
+            break;
+        }
+    }
+    Ok((raw_front_matter.parse()?, title(&toc_entries)))
+}
+
+/// Normalize a Markdown document, producing Markdown again instead of HTML.
+///
+/// This applies the adapters that have a Markdown equivalent: headings get
+/// explicit IDs (like `# Title { #title }`), relative links are rewritten to
+/// point at pages, wikilinks become ordinary links, and raw HTML is removed if
+/// `sanitize_html` is set. The front matter is kept as it is. Code blocks,
+/// math, figures, and footnotes are left as Markdown for the next tool to
+/// handle.
+pub fn render_to_markdown(source: &str, options: &RenderOptions) -> anyhow::Result<String> {
+    let parse_options = parse_options(options);
+    let iter = Parser::new_ext(source, parse_options);
+    let iter = raw_html::StripHtml::new(iter, options.sanitize_html);
+    let iter = add_ids::AddHeadingIds::new(iter, false, options.slugs)
+        .with_prefix(options.id_prefix, options.prefix_explicit_ids)
+        .reserve_explicit_ids(Parser::new_ext(source, parse_options));
+    let iter = wikilinks::WikiLinks::new(iter, options.slugs, options.id_prefix);
+    let iter = rel_links::RewriteRelativeLinks::new(iter, options.links.style);
+
+    let mut markdown = String::new();
+    pulldown_cmark_to_cmark::cmark(iter, &mut markdown)?;
+    markdown.push('\n');
+    Ok(markdown)
+}
+
+/// Extract a document's title from its table of contents: the text of its
+/// first heading, if that heading is top-level.
+pub fn title(toc_entries: &[toc::TocEntry]) -> Option<String> {
+    match toc_entries.first() {
+        Some(first_head) if first_head.level as u8 == 1 => Some(first_head.title.clone()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render_doc(source: &str) -> Rendered {
+        render(source, &CodeHandlers::empty(), &RenderOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn sanitize_html() {
+        let source = "Hi<script>alert(1)</script>[^1].\n\n<!-- more -->\n\n\
+                      <div onmouseover=\"x()\">Rest.</div>\n\n[^1]: Note.\n";
+        let doc = render_doc(source);
+        assert!(doc.body.contains("<script>alert(1)</script>"));
+        assert!(doc.body.contains("<div onmouseover"));
+
+        let options = RenderOptions {
+            sanitize_html: true,
+            ..RenderOptions::default()
+        };
+        let doc = render(source, &CodeHandlers::empty(), &options).unwrap();
+        assert!(!doc.body.contains("<script"));
+        assert!(!doc.body.contains("<div"));
+        assert!(doc.body.contains("<section class=\"footnotes\">"));
+        // Inline tags go, but the text between them stays, escaped.
+        assert!(
+            doc.excerpt
+                .starts_with("<p>Hialert(1)<sup class=\"footnote-ref\">")
+        );
+        assert!(!doc.excerpt.contains("Rest."));
+    }
+
+    #[test]
+    fn definition_list() {
+        let doc = render_doc("Note\n: See [the guide](guide.md).\n\n*Term*\n: One.\n: Two.\n");
+        assert_eq!(
+            doc.body,
+            "<dl>\n<dt>Note</dt>\n<dd>See <a href=\"guide.html\">the guide</a>.</dd>\n\
+             <dt><em>Term</em></dt>\n<dd>One.</dd>\n<dd>Two.</dd>\n</dl>\n"
+        );
+    }
+
+    #[test]
+    fn toc_ids_match_body() {
+        let doc = render_doc("# The `main` function\n\n## Explicit {#custom}\n");
+        let ids: Vec<_> = doc.toc.iter().map(|e| e.id.as_deref()).collect();
+        assert_eq!(ids, [Some("the-main-function"), Some("custom")]);
+        for id in ids.into_iter().flatten() {
+            assert!(doc.body.contains(&format!(" id=\"{id}\"")), "{id}");
+        }
+    }
+
+    #[test]
+    fn explicit_id_after_generated() {
+        let doc = render_doc("# Intro\n\n# Other {#intro}\n");
+        let ids: Vec<_> = doc.toc.iter().map(|e| e.id.as_deref()).collect();
+        assert_eq!(ids, [Some("intro-1"), Some("intro")]);
+        assert_eq!(doc.body.matches(" id=\"intro\"").count(), 1);
+    }
+
+    #[test]
+    fn definition_terms_not_in_toc() {
+        let doc = render_doc("# Glossary\n\nTerm\n: Definition.\n");
+        let titles: Vec<_> = doc.toc.iter().map(|e| e.title.as_str()).collect();
+        assert_eq!(titles, ["Glossary"]);
+        assert_eq!(doc.word_count, 3);
+    }
+
+    #[test]
+    fn toc_levels() {
+        let source = "# Title\n\n## Part\n\n### Section\n\n#### Detail\n";
+        let options = RenderOptions {
+            toc_levels: HeadingLevel::H2..=HeadingLevel::H3,
+            ..RenderOptions::default()
+        };
+        let doc = render(source, &CodeHandlers::empty(), &options).unwrap();
+        let titles: Vec<_> = doc.toc.iter().map(|e| e.title.as_str()).collect();
+        assert_eq!(titles, ["Part", "Section"]);
+        assert_eq!(doc.title.as_deref(), Some("Title"));
+        assert!(doc.body.contains("<h1 id=\"title\">Title"));
+        assert!(doc.body.contains("<h4 id=\"detail\">Detail"));
+    }
+
+    #[test]
+    fn metadata() {
+        let source = "---\ntags: [a]\n---\nIntro.\n\n# The `main` *function*\n\n# Another\n";
+        let (front_matter, title) = read_metadata(source, &RenderOptions::default()).unwrap();
+        assert_eq!(front_matter.tags, ["a"]);
+        assert_eq!(title.as_deref(), Some("The main function"));
+        assert_eq!(title, render_doc(source).title);
+
+        let (_, title) = read_metadata("## Not a title\n", &RenderOptions::default()).unwrap();
+        assert_eq!(title, None);
+    }
+
+    #[test]
+    fn normalize_markdown() {
+        let source = "---\ndraft: true\n---\n# The `main` function\n\n\
+                      See [the guide](guide.md#setup) and [elsewhere](https://example.com/a.md).\n\n\
+                      ## Explicit {#custom}\n\n\
+                      - One\n- *Two*\n";
+        let markdown = render_to_markdown(source, &RenderOptions::default()).unwrap();
+        assert_eq!(
+            markdown,
+            "---\ndraft: true\n---\n\n\
+             # The `main` function { #the-main-function }\n\n\
+             See [the guide](guide.html#setup) and [elsewhere](https://example.com/a.md).\n\n\
+             ## Explicit { #custom }\n\n\
+             * One\n* *Two*\n"
+        );
+
+        // Rendering the normalized document gives the same IDs and links.
+        let (original, normalized) = (render_doc(source), render_doc(&markdown));
+        assert_eq!(normalized.body, original.body);
+        assert_eq!(normalized.front_matter.draft, original.front_matter.draft);
+    }
 }
@@ -0,0 +1,188 @@
+use pulldown_cmark::{CowStr, Event, Tag};
+
+/// URL schemes that can run code, or smuggle in a whole document, when a link
+/// or image uses them.
+const UNSAFE_SCHEMES: &[&str] = &["javascript:", "vbscript:", "data:"];
+
+/// A pulldown-cmark adapter that removes raw HTML from a document, for notes
+/// from authors who shouldn't be able to put scripts (or anything else) on the
+/// page. Markdown formatting still works; only the HTML tags themselves are
+/// dropped, so `<b>hi</b>` becomes just `hi`. Links and images with URLs that
+/// can run code, like `javascript:`, lose their URLs.
+///
+/// Plain HTML comments are kept, because they mark excerpts. Ones that a
+/// browser might end early, letting what follows escape the comment, are
+/// dropped. This must come right after the parser so it doesn't remove the
+/// HTML that later adapters generate.
+pub struct StripHtml<I> {
+    iter: I,
+    strip: bool,
+}
+
+impl<'a, I> StripHtml<I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    /// Wrap `iter`, removing its HTML only if `strip` is set.
+    pub fn new(iter: I, strip: bool) -> Self {
+        Self { iter, strip }
+    }
+}
+
+impl<'a, I> Iterator for StripHtml<I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.iter.next()? {
+                Event::Html(html) | Event::InlineHtml(html) if self.strip && !is_comment(&html) => {
+                    continue;
+                }
+                Event::Start(Tag::Link {
+                    link_type,
+                    dest_url,
+                    title,
+                    id,
+                }) if self.strip => {
+                    return Some(Event::Start(Tag::Link {
+                        link_type,
+                        dest_url: safe_url(dest_url),
+                        title,
+                        id,
+                    }));
+                }
+                Event::Start(Tag::Image {
+                    link_type,
+                    dest_url,
+                    title,
+                    id,
+                }) if self.strip => {
+                    return Some(Event::Start(Tag::Image {
+                        link_type,
+                        dest_url: safe_url(dest_url),
+                        title,
+                        id,
+                    }));
+                }
+                event => return Some(event),
+            }
+        }
+    }
+}
+
+/// Is this chunk of HTML nothing but a single comment that every browser ends
+/// at the closing `-->`? Comments like `<!-->` and `<!--->` end right away,
+/// and `--!>` ends one too, so we reject those and any other `--` inside.
+fn is_comment(html: &str) -> bool {
+    let html = html.trim();
+    html.strip_prefix("<!--")
+        .and_then(|rest| rest.strip_suffix("-->"))
+        .is_some_and(|body| {
+            !body.starts_with('>') && !body.starts_with("->") && !body.contains("--")
+        })
+}
+
+/// Blank out a URL if its scheme can run code. Browsers ignore whitespace and
+/// control characters in the scheme, and its case, so we do too.
+fn safe_url(url: CowStr<'_>) -> CowStr<'_> {
+    let scheme: String = url
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_ascii_control())
+        .take(16)
+        .collect::<String>()
+        .to_ascii_lowercase();
+    if UNSAFE_SCHEMES.iter().any(|s| scheme.starts_with(s)) {
+        CowStr::Borrowed("")
+    } else {
+        url
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{Parser, html::push_html};
+
+    fn render(source: &str, strip: bool) -> String {
+        let mut html = String::new();
+        push_html(&mut html, StripHtml::new(Parser::new(source), strip));
+        html
+    }
+
+    #[test]
+    fn script_block() {
+        let source = "Hi.\n\n<script>alert(1)</script>\n\nBye.\n";
+        assert_eq!(render(source, true), "<p>Hi.</p>\n<p>Bye.</p>\n");
+        assert_eq!(
+            render(source, false),
+            "<p>Hi.</p>\n<script>alert(1)</script>\n<p>Bye.</p>\n"
+        );
+    }
+
+    #[test]
+    fn inline_html() {
+        assert_eq!(
+            render("A <b onclick=\"x()\">bold</b> *move*.\n", true),
+            "<p>A bold <em>move</em>.</p>\n"
+        );
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            render("Intro.\n\n<!-- more -->\n\nRest.\n", true),
+            "<p>Intro.</p>\n<!-- more -->\n<p>Rest.</p>\n"
+        );
+        assert_eq!(
+            render("<!-- a --><img src=x onerror=y><!-- b -->\n", true),
+            ""
+        );
+    }
+
+    #[test]
+    fn comment_escapes() {
+        for source in [
+            "<!--><script>alert(1)</script>-->\n",
+            "<!---><script>alert(1)</script>-->\n",
+            "<!-- a --!><script>alert(1)</script> -->\n",
+            "<!-- a -- b --><script>alert(1)</script>\n",
+        ] {
+            assert_eq!(render(source, true), "", "{source}");
+        }
+    }
+
+    #[test]
+    fn unsafe_urls() {
+        assert_eq!(
+            render("[x](javascript:alert(1))\n", true),
+            "<p><a href=\"\">x</a></p>\n"
+        );
+        assert_eq!(
+            render("[x](<JavaScript\t:alert(1)>) [y](VBScript:msgbox)\n", true),
+            "<p><a href=\"\">x</a> <a href=\"\">y</a></p>\n"
+        );
+        assert_eq!(
+            render("![x](data:text/html;base64,PHNjcmlwdD4=)\n", true),
+            "<p><img src=\"\" alt=\"x\" /></p>\n"
+        );
+        assert_eq!(
+            render("[x](https://example.com) [y](notes/data:1.md)\n", true),
+            "<p><a href=\"https://example.com\">x</a> <a href=\"notes/data:1.md\">y</a></p>\n"
+        );
+        assert_eq!(
+            render("[x](javascript:alert(1))\n", false),
+            "<p><a href=\"javascript:alert(1)\">x</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn code_is_text() {
+        assert_eq!(
+            render("`<script>`\n", true),
+            "<p><code>&lt;script&gt;</code></p>\n"
+        );
+    }
+}
@@ -1,21 +1,38 @@
 use pulldown_cmark::{CowStr, Event, Tag};
 
+/// Where notes' pages go in the rendered site, which determines how links to
+/// them look.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LinkStyle {
+    /// The note `foo.md` becomes the page `foo.html`.
+    #[default]
+    Html,
+
+    /// The note `foo.md` becomes the page `foo/index.html`, so links to it
+    /// point to `foo/`. Notes named `index.md` stay put. `own_dir` says
+    /// whether the note being rendered got a directory of its own, in which
+    /// case its relative URLs need to climb out of that directory.
+    Pretty { own_dir: bool },
+}
+
 /// A pulldown_cmark adapter that rewrites relative Markdown links to be HTML
-/// links. So a link to `./foo.md` becomes a link to `./foo.html` when rendered,
-/// but all absolute links are left unchanged.
+/// links. So a link to `./foo.md` becomes a link to `./foo.html` when rendered
+/// (or to `./foo/`, with pretty URLs), but all absolute links are left
+/// unchanged. Image sources follow the same rules.
 pub struct RewriteRelativeLinks<'a, I>
 where
     I: Iterator<Item = Event<'a>>,
 {
     iter: I,
+    style: LinkStyle,
 }
 
 impl<'a, I> RewriteRelativeLinks<'a, I>
 where
     I: Iterator<Item = Event<'a>>,
 {
-    pub fn new(iter: I) -> Self {
-        Self { iter }
+    pub fn new(iter: I, style: LinkStyle) -> Self {
+        Self { iter, style }
     }
 }
 
@@ -32,44 +49,133 @@ where
                 dest_url,
                 title,
                 id,
-            }) => {
-                let url = if is_absolute_url(&dest_url) {
-                    dest_url
-                } else {
-                    rewrite_url(dest_url)
-                };
-                Event::Start(Tag::Link {
-                    link_type,
-                    dest_url: url,
-                    title,
-                    id,
-                })
-            }
+            }) => Event::Start(Tag::Link {
+                link_type,
+                dest_url: rewrite_relative(dest_url, self.style),
+                title,
+                id,
+            }),
+            Event::Start(Tag::Image {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) => Event::Start(Tag::Image {
+                link_type,
+                dest_url: rebase_relative(dest_url, self.style),
+                title,
+                id,
+            }),
             e => e,
         })
     }
 }
 
-/// Check whether a URL is absolute, i.e., starts with a protocol.
-fn is_absolute_url(url: &str) -> bool {
-    let colon = url.find(':');
-    let slash = url.find('/');
-    match (colon, slash) {
-        (Some(c), Some(s)) if c < s => true,
-        (_, Some(s)) => matches!(url.find("//"), Some(ss) if ss <= s),
-        (_, _) => false,
+/// A pulldown-cmark adapter that records the relative link and image targets
+/// in a document, as they appear in the rendered HTML. Absolute URLs and links
+/// to fragments within the same page are skipped.
+pub struct CollectLinks<'a, 'b, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    iter: I,
+    links: &'b mut Vec<String>,
+}
+
+impl<'a, 'b, I> CollectLinks<'a, 'b, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    pub fn new(iter: I, links: &'b mut Vec<String>) -> Self {
+        Self { iter, links }
     }
 }
 
-/// Rewrite any `.md` extension to `.html`. If it doesn't have this extension,
-/// return the path unchanged.
-fn rewrite_url(url: CowStr) -> CowStr {
-    match url.rsplit_once(".") {
-        Some((base, "md")) => format!("{base}.html").into(),
+impl<'a, I> Iterator for CollectLinks<'a, '_, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let event = self.iter.next()?;
+        if let Event::Start(Tag::Link { dest_url, .. } | Tag::Image { dest_url, .. }) = &event
+            && !is_absolute_url(dest_url)
+            && !dest_url.starts_with('#')
+        {
+            self.links.push(dest_url.to_string());
+        }
+        Some(event)
+    }
+}
+
+/// Check whether a URL is absolute, i.e., starts with a scheme (like `http:`
+/// or `mailto:`) or is protocol-relative (like `//foo.org/bar`).
+pub fn is_absolute_url(url: &str) -> bool {
+    if url.starts_with("//") {
+        return true;
+    }
+    match url.split_once(':') {
+        Some((scheme, _)) => is_scheme(scheme),
+        None => false,
+    }
+}
+
+/// Check whether a string is a valid URL scheme: a letter followed by letters,
+/// digits, `+`, `-`, or `.`.
+fn is_scheme(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+}
+
+/// Rewrite a relative URL with `rewrite_url` and then `rebase_relative`,
+/// leaving absolute URLs and links to fragments within the same page alone.
+fn rewrite_relative(url: CowStr, style: LinkStyle) -> CowStr {
+    if is_absolute_url(&url) || url.starts_with('#') {
+        return url;
+    }
+    rebase_relative(rewrite_url(url, style), style)
+}
+
+/// For a pretty-URL note in its own directory, move relative paths up a level.
+/// Absolute URLs and links to fragments are left alone. Images use only this,
+/// since they never point at notes' pages.
+fn rebase_relative(url: CowStr, style: LinkStyle) -> CowStr {
+    if is_absolute_url(&url) || url.starts_with('#') {
+        return url;
+    }
+    match style {
+        LinkStyle::Pretty { own_dir: true } if !url.starts_with('/') => {
+            let url = url.strip_prefix("./").unwrap_or(&url);
+            format!("../{url}").into()
+        }
         _ => url,
     }
 }
 
+/// Rewrite a link to a note, with a `.md` extension, into a link to its page.
+/// Any query or fragment is kept. If the path doesn't have this extension,
+/// return the URL unchanged.
+fn rewrite_url(url: CowStr, style: LinkStyle) -> CowStr {
+    let split = url.find(['?', '#']).unwrap_or(url.len());
+    let (path, suffix) = url.split_at(split);
+    let Some(base) = path.strip_suffix(".md") else {
+        return url;
+    };
+    match style {
+        LinkStyle::Html => format!("{base}.html{suffix}").into(),
+        LinkStyle::Pretty { .. } => {
+            let (dir, name) = base.rsplit_once('/').map_or(("", base), |(d, n)| (d, n));
+            match (dir, name) {
+                ("", "index") => format!("./{suffix}").into(),
+                (dir, "index") => format!("{dir}/{suffix}").into(),
+                _ => format!("{base}/{suffix}").into(),
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -107,6 +213,19 @@ mod tests {
         assert!(!is_absolute_url("foo/bar//baz"));
     }
 
+    #[test]
+    fn scheme_without_slash_is_absolute() {
+        assert!(is_absolute_url("mailto:foo@bar.com"));
+        assert!(is_absolute_url("tel:+1-555-0100"));
+    }
+
+    #[test]
+    fn fragment_is_relative() {
+        assert!(!is_absolute_url("#section"));
+        assert!(!is_absolute_url("#section:two"));
+        assert!(!is_absolute_url("foo.md#section:two"));
+    }
+
     use super::*;
     use pulldown_cmark::{Parser, html};
 
@@ -114,7 +233,16 @@ mod tests {
         let parser = Parser::new(source);
 
         let mut buf = String::new();
-        html::push_html(&mut buf, RewriteRelativeLinks::new(parser));
+        html::push_html(&mut buf, RewriteRelativeLinks::new(parser, LinkStyle::Html));
+        buf
+    }
+
+    fn render_pretty(source: &str, own_dir: bool) -> String {
+        let parser = Parser::new(source);
+        let style = LinkStyle::Pretty { own_dir };
+
+        let mut buf = String::new();
+        html::push_html(&mut buf, RewriteRelativeLinks::new(parser, style));
         buf
     }
 
@@ -142,6 +270,73 @@ mod tests {
         );
     }
 
+    #[test]
+    fn mailto_md_link() {
+        assert_eq!(
+            render_rewrite("[hi](mailto:notes@foo.md)"),
+            "<p><a href=\"mailto:notes@foo.md\">hi</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn fragment_link() {
+        assert_eq!(
+            render_rewrite("[hi](#setup.md)"),
+            "<p><a href=\"#setup.md\">hi</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn md_link_with_fragment() {
+        assert_eq!(
+            render_rewrite("[hi](bar.md#intro)"),
+            "<p><a href=\"bar.html#intro\">hi</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn pretty_md_link() {
+        assert_eq!(
+            render_pretty("[hi](bar.md)", false),
+            "<p><a href=\"bar/\">hi</a></p>\n"
+        );
+        assert_eq!(
+            render_pretty("[hi](sub/bar.md#intro)", false),
+            "<p><a href=\"sub/bar/#intro\">hi</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn pretty_index_link() {
+        assert_eq!(
+            render_pretty("[hi](index.md)", false),
+            "<p><a href=\"./\">hi</a></p>\n"
+        );
+        assert_eq!(
+            render_pretty("[hi](../sub/index.md)", false),
+            "<p><a href=\"../sub/\">hi</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn pretty_own_dir() {
+        assert_eq!(
+            render_pretty("[hi](bar.md) ![p](pic.png) [r](/x.md) [f](#f)", true),
+            "<p><a href=\"../bar/\">hi</a> <img src=\"../pic.png\" alt=\"p\" /> \
+             <a href=\"/x/\">r</a> <a href=\"#f\">f</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn collect_links() {
+        let source = "[a](a.md) [b](https://b.org/) [c](#c) ![d](img/d.png) [e](/e.md#x)";
+        let parser = Parser::new(source);
+        let parser = RewriteRelativeLinks::new(parser, LinkStyle::Html);
+        let mut links = vec![];
+        CollectLinks::new(parser, &mut links).for_each(drop);
+        assert_eq!(links, ["a.html", "img/d.png", "/e.html#x"]);
+    }
+
     #[test]
     fn relative_md_link_refstyle() {
         assert_eq!(
@@ -149,4 +344,29 @@ mod tests {
             "<p><a href=\"./bar.html\">hi</a></p>\n"
         );
     }
+
+    #[test]
+    fn absolute_image() {
+        assert_eq!(
+            render_rewrite("![alt](https://foo.com/pic.md)"),
+            "<p><img src=\"https://foo.com/pic.md\" alt=\"alt\" /></p>\n"
+        );
+    }
+
+    #[test]
+    fn relative_image() {
+        assert_eq!(
+            render_rewrite("![alt](./img/pic.png)"),
+            "<p><img src=\"./img/pic.png\" alt=\"alt\" /></p>\n"
+        );
+        assert_eq!(
+            render_rewrite("![alt](diagram.md)"),
+            "<p><img src=\"diagram.md\" alt=\"alt\" /></p>\n"
+        );
+        assert_eq!(
+            render_pretty("![alt](diagram.md) ![alt](https://example.com/a.png)", true),
+            "<p><img src=\"../diagram.md\" alt=\"alt\" /> \
+             <img src=\"https://example.com/a.png\" alt=\"alt\" /></p>\n"
+        );
+    }
 }
@@ -1,33 +1,82 @@
 use pulldown_cmark::{Event, HeadingLevel, Tag, TagEnd};
+use std::ops::RangeInclusive;
 
-#[derive(Debug, PartialEq, Eq)]
+/// Every heading level, for a table of contents that includes all headings.
+pub const ALL_LEVELS: RangeInclusive<HeadingLevel> = HeadingLevel::H1..=HeadingLevel::H6;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TocEntry {
     pub level: HeadingLevel,
+    /// The heading's anchor. When `AddHeadingIds` runs first, this is the id
+    /// in the rendered body, whether it was written out or generated.
     pub id: Option<String>,
     pub title: String,
 }
 
+/// A heading in a nested table of contents, with all the subheadings that
+/// appear beneath it.
+#[derive(Debug, PartialEq, Eq)]
+pub struct TocNode {
+    pub entry: TocEntry,
+    pub children: Vec<TocNode>,
+}
+
+/// Convert a flat list of TOC entries into a tree based on heading levels.
+///
+/// Each heading is nested under the closest preceding heading with a smaller
+/// level, even if levels are skipped (e.g., an H3 directly under an H1).
+/// Headings with no such predecessor appear at the top level.
+pub fn nest(entries: Vec<TocEntry>) -> Vec<TocNode> {
+    let mut iter = entries.into_iter().peekable();
+    nest_under(&mut iter, None)
+}
+
+/// Consume all the entries that belong beneath a heading of level `parent`
+/// (or everything, if `parent` is `None`) and build them into a tree.
+fn nest_under<I>(iter: &mut std::iter::Peekable<I>, parent: Option<HeadingLevel>) -> Vec<TocNode>
+where
+    I: Iterator<Item = TocEntry>,
+{
+    let mut nodes = vec![];
+    while let Some(entry) = iter.next_if(|e| parent.is_none_or(|p| e.level > p)) {
+        let children = nest_under(iter, Some(entry.level));
+        nodes.push(TocNode { entry, children });
+    }
+    nodes
+}
+
 /// A pulldown-cmark adapter that extracts a table of contents from a Markdown
 /// document, i.e., a list of all the headings. When this iterator runs, it
 /// pushes the TOC entries into a vector that you supply.
+///
+/// Only headings with a level in `levels` are collected. The others still pass
+/// through, so they appear in the rendered document as usual.
 pub struct TableOfContents<'a, 'b, I>
 where
     I: Iterator<Item = Event<'a>>,
 {
     iter: I,
     pub entries: &'b mut Vec<TocEntry>,
+    levels: RangeInclusive<HeadingLevel>,
     in_heading: bool,
+    collecting: bool,
 }
 
 impl<'a, 'b, I> TableOfContents<'a, 'b, I>
 where
     I: Iterator<Item = Event<'a>>,
 {
-    pub fn new(iter: I, entries: &'b mut Vec<TocEntry>) -> Self {
+    pub fn new(
+        iter: I,
+        entries: &'b mut Vec<TocEntry>,
+        levels: RangeInclusive<HeadingLevel>,
+    ) -> Self {
         Self {
             iter,
             entries,
+            levels,
             in_heading: false,
+            collecting: false,
         }
     }
 }
@@ -47,26 +96,34 @@ where
                 classes: _,
                 attrs: _,
             }) => {
-                // Start building a new TOC entry for this heading.
-                self.entries.push(TocEntry {
-                    level: *level,
-                    id: id.as_ref().map(|s| s.to_string()),
-                    title: String::new(),
-                });
+                // Start building a new TOC entry for this heading, if it's one
+                // we want.
                 self.in_heading = true;
+                self.collecting = self.levels.contains(level);
+                if self.collecting {
+                    self.entries.push(TocEntry {
+                        level: *level,
+                        id: id.as_ref().map(|s| s.to_string()),
+                        title: String::new(),
+                    });
+                }
             }
             Event::End(TagEnd::Heading(_)) => {
                 // Finish a TOC entry.
                 assert!(self.in_heading, "heading ended without starting");
                 self.in_heading = false;
+                self.collecting = false;
             }
-            Event::Text(text) => {
-                if self.in_heading {
-                    if let Some(entry) = self.entries.last_mut() {
-                        entry.title += text;
-                    } else {
-                        panic!("no entry created for heading");
-                    }
+            Event::Text(text) | Event::Code(text) if self.collecting => {
+                if let Some(entry) = self.entries.last_mut() {
+                    entry.title += text;
+                } else {
+                    panic!("no entry created for heading");
+                }
+            }
+            Event::SoftBreak | Event::HardBreak if self.collecting => {
+                if let Some(entry) = self.entries.last_mut() {
+                    entry.title.push(' ');
                 }
             }
             _ => (),
@@ -80,14 +137,23 @@ mod tests {
     use super::*;
     use pulldown_cmark::{Options, Parser};
 
-    fn get_toc<'a>(source: &'a str) -> Vec<TocEntry> {
+    fn get_toc(source: &str) -> Vec<TocEntry> {
+        get_toc_levels(source, ALL_LEVELS).0
+    }
+
+    /// Get the TOC entries for some levels, along with the rendered HTML.
+    fn get_toc_levels(
+        source: &str,
+        levels: RangeInclusive<HeadingLevel>,
+    ) -> (Vec<TocEntry>, String) {
         let mut options = Options::empty();
         options.insert(Options::ENABLE_HEADING_ATTRIBUTES);
         let parser = Parser::new_ext(source, options);
         let mut entries = vec![];
-        let mut toc = TableOfContents::new(parser, &mut entries);
-        toc.by_ref().for_each(|_| {}); // Just consume the whole iterator.
-        entries
+        let mut html = String::new();
+        let toc = TableOfContents::new(parser, &mut entries, levels);
+        pulldown_cmark::html::push_html(&mut html, toc);
+        (entries, html)
     }
 
     #[test]
@@ -149,4 +215,114 @@ mod tests {
             }]
         );
     }
+
+    #[test]
+    fn level_range() {
+        let source = "# one\n## two\n### three\n#### four\n## five\n";
+        let (entries, html) = get_toc_levels(source, HeadingLevel::H2..=HeadingLevel::H3);
+        let titles: Vec<_> = entries.iter().map(|e| e.title.as_str()).collect();
+        assert_eq!(titles, ["two", "three", "five"]);
+        assert_eq!(
+            html,
+            "<h1>one</h1>\n<h2>two</h2>\n<h3>three</h3>\n<h4>four</h4>\n<h2>five</h2>\n"
+        );
+    }
+
+    #[test]
+    fn multi_line_heading() {
+        assert_eq!(
+            get_toc("Long title\ncontinued\n===\n"),
+            &[TocEntry {
+                level: HeadingLevel::H1,
+                id: None,
+                title: "Long title continued".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn inline_code() {
+        assert_eq!(
+            get_toc("# The `main` function"),
+            &[TocEntry {
+                level: HeadingLevel::H1,
+                id: None,
+                title: "The main function".to_string(),
+            }]
+        );
+    }
+
+    fn entry(level: HeadingLevel, title: &str) -> TocEntry {
+        TocEntry {
+            level,
+            id: None,
+            title: title.to_string(),
+        }
+    }
+
+    fn leaf(level: HeadingLevel, title: &str) -> TocNode {
+        TocNode {
+            entry: entry(level, title),
+            children: vec![],
+        }
+    }
+
+    #[test]
+    fn nest_well_formed() {
+        assert_eq!(
+            nest(get_toc(
+                "# a
+## b
+### c
+## d
+# e"
+            )),
+            &[
+                TocNode {
+                    entry: entry(HeadingLevel::H1, "a"),
+                    children: vec![
+                        TocNode {
+                            entry: entry(HeadingLevel::H2, "b"),
+                            children: vec![leaf(HeadingLevel::H3, "c")],
+                        },
+                        leaf(HeadingLevel::H2, "d"),
+                    ],
+                },
+                leaf(HeadingLevel::H1, "e"),
+            ]
+        );
+    }
+
+    #[test]
+    fn nest_skipped_level() {
+        assert_eq!(
+            nest(get_toc(
+                "# a
+### b
+## c"
+            )),
+            &[TocNode {
+                entry: entry(HeadingLevel::H1, "a"),
+                children: vec![leaf(HeadingLevel::H3, "b"), leaf(HeadingLevel::H2, "c")],
+            }]
+        );
+    }
+
+    #[test]
+    fn nest_starting_deep() {
+        assert_eq!(
+            nest(get_toc(
+                "### a
+## b
+### c"
+            )),
+            &[
+                leaf(HeadingLevel::H3, "a"),
+                TocNode {
+                    entry: entry(HeadingLevel::H2, "b"),
+                    children: vec![leaf(HeadingLevel::H3, "c")],
+                },
+            ]
+        );
+    }
 }
@@ -0,0 +1,83 @@
+/// An iterator adapter that yields items up to and including the first one that
+/// matches a predicate, and then stops.
+pub struct TakeUntil<I, P> {
+    iter: I,
+    pred: P,
+    done: bool,
+}
+
+/// Take items from `iter` up to and including the first one for which `pred`
+/// is true. This is like `take_while`, except that it keeps the item where it
+/// stops (and with the opposite sense of the predicate).
+pub fn take_until<I, P>(iter: I, pred: P) -> TakeUntil<I, P>
+where
+    I: Iterator,
+    P: FnMut(&I::Item) -> bool,
+{
+    TakeUntil {
+        iter,
+        pred,
+        done: false,
+    }
+}
+
+impl<I, P> Iterator for TakeUntil<I, P>
+where
+    I: Iterator,
+    P: FnMut(&I::Item) -> bool,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let item = self.iter.next()?;
+        self.done = (self.pred)(&item);
+        Some(item)
+    }
+}
+
+/// Percent-encode everything in a URL path that isn't allowed there as it is,
+/// keeping the `/` separators.
+pub fn percent_encode_path(path: &str) -> String {
+    let mut url = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~!$&'()*+,;=:@".contains(&byte) {
+            url.push(byte as char);
+        } else {
+            url.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::take_until;
+
+    #[test]
+    fn includes_match() {
+        let items: Vec<_> = take_until(1..10, |&i| i == 3).collect();
+        assert_eq!(items, [1, 2, 3]);
+    }
+
+    #[test]
+    fn first_item_matches() {
+        let items: Vec<_> = take_until(1..10, |_| true).collect();
+        assert_eq!(items, [1]);
+    }
+
+    #[test]
+    fn no_match() {
+        let items: Vec<_> = take_until(1..4, |_| false).collect();
+        assert_eq!(items, [1, 2, 3]);
+    }
+
+    #[test]
+    fn leaves_rest() {
+        let mut iter = 1..10;
+        assert_eq!(take_until(iter.by_ref(), |&i| i % 4 == 0).count(), 4);
+        assert_eq!(iter.next(), Some(5));
+    }
+}
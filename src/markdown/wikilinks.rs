@@ -0,0 +1,185 @@
+use super::add_ids::{SlugOptions, slugify};
+use super::util::percent_encode_path;
+use pulldown_cmark::{CowStr, Event, LinkType, Tag};
+
+/// A pulldown-cmark adapter that points wikilinks, like `[[Some Note]]` or
+/// `[[Some Note|a label]]`, at the notes they name. The target is a path
+/// relative to the current note, so `[[Some Note]]` links to `Some Note.md`
+/// and `[[sub/Other#A Heading]]` links to the heading's ID in `sub/Other.md`.
+/// Targets with a common file extension, like `[[diagram.png]]`, are left as
+/// they are; other dots, like in `[[v1.2 notes]]`, are part of a note's name.
+/// Headings' IDs get `id_prefix`, like the IDs that `AddHeadingIds` makes.
+///
+/// The parser must have wikilinks enabled. Brackets in code are never
+/// wikilinks because the parser doesn't look for links there.
+pub struct WikiLinks<'a, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    iter: I,
+    slugs: SlugOptions,
+    id_prefix: String,
+}
+
+impl<'a, I> WikiLinks<'a, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    pub fn new(iter: I, slugs: SlugOptions, id_prefix: &str) -> Self {
+        Self {
+            iter,
+            slugs,
+            id_prefix: id_prefix.to_string(),
+        }
+    }
+}
+
+impl<'a, I> Iterator for WikiLinks<'a, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(match self.iter.next()? {
+            Event::Start(Tag::Link {
+                link_type: link_type @ LinkType::WikiLink { .. },
+                dest_url,
+                title,
+                id,
+            }) => Event::Start(Tag::Link {
+                link_type,
+                dest_url: wiki_url(&dest_url, &self.slugs, &self.id_prefix),
+                title,
+                id,
+            }),
+            Event::Start(Tag::Image {
+                link_type: link_type @ LinkType::WikiLink { .. },
+                dest_url,
+                title,
+                id,
+            }) => Event::Start(Tag::Image {
+                link_type,
+                dest_url: wiki_url(&dest_url, &self.slugs, &self.id_prefix),
+                title,
+                id,
+            }),
+            e => e,
+        })
+    }
+}
+
+/// Extensions that make a wikilink target a file to link to as it is, rather
+/// than the name of a note.
+const FILE_EXTENSIONS: &[&str] = &[
+    "md", "html", "htm", "txt", "pdf", "png", "jpg", "jpeg", "gif", "svg", "webp", "avif", "mp3",
+    "mp4", "webm", "zip", "csv", "json",
+];
+
+/// Get the relative URL for a wikilink's target. Notes get a `.md` extension,
+/// which is later rewritten like any other link to a note, and headings are
+/// slugified the same way as their IDs.
+fn wiki_url(target: &str, slugs: &SlugOptions, id_prefix: &str) -> CowStr<'static> {
+    let (page, heading) = match target.split_once('#') {
+        Some((page, heading)) => (page.trim(), Some(heading.trim())),
+        None => (target.trim(), None),
+    };
+    let mut url = percent_encode_path(page);
+    let name = page.rsplit('/').next().unwrap_or_default();
+    let is_file = name
+        .rsplit_once('.')
+        .is_some_and(|(_, ext)| FILE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()));
+    if !page.is_empty() && !is_file {
+        url.push_str(".md");
+    }
+    if let Some(heading) = heading {
+        url.push('#');
+        url.push_str(id_prefix);
+        url.push_str(&slugify(heading, slugs));
+    }
+    url.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{Options, Parser, html::push_html};
+
+    fn render(source: &str) -> String {
+        let parser = Parser::new_ext(source, Options::ENABLE_WIKILINKS);
+        let mut html = String::new();
+        push_html(
+            &mut html,
+            WikiLinks::new(parser, SlugOptions::default(), ""),
+        );
+        html
+    }
+
+    #[test]
+    fn plain() {
+        assert_eq!(
+            render("See [[Some Note]]."),
+            "<p>See <a href=\"Some%20Note.md\">Some Note</a>.</p>\n"
+        );
+        assert_eq!(
+            render("[[sub/page]]"),
+            "<p><a href=\"sub/page.md\">sub/page</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn aliased() {
+        assert_eq!(
+            render("See [[Some Note|this *note*]]."),
+            "<p>See <a href=\"Some%20Note.md\">this <em>note</em></a>.</p>\n"
+        );
+    }
+
+    #[test]
+    fn headings() {
+        assert_eq!(
+            render("[[Guide#Getting Started|start]] [[#Setup]]"),
+            "<p><a href=\"Guide.md#getting-started\">start</a> <a href=\"#setup\">#Setup</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn other_files() {
+        assert_eq!(
+            render("[[notes.pdf]] ![[pic.png]]"),
+            "<p><a href=\"notes.pdf\">notes.pdf</a> <img src=\"pic.png\" alt=\"pic.png\" /></p>\n"
+        );
+    }
+
+    #[test]
+    fn dots_and_special_characters() {
+        assert_eq!(
+            render("[[v1.2 notes]] [[FAQ?]] [[100% done]] [[Café]]"),
+            "<p><a href=\"v1.2%20notes.md\">v1.2 notes</a> \
+             <a href=\"FAQ%3F.md\">FAQ?</a> \
+             <a href=\"100%25%20done.md\">100% done</a> \
+             <a href=\"Caf%C3%A9.md\">Café</a></p>\n"
+        );
+        assert_eq!(
+            render("[[Report.PDF]] [[notes.md#Intro]]"),
+            "<p><a href=\"Report.PDF\">Report.PDF</a> \
+             <a href=\"notes.md#intro\">notes.md#Intro</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn code() {
+        assert_eq!(
+            render("Use `[[Page]]` for links.\n\n    [[Page]]\n"),
+            "<p>Use <code>[[Page]]</code> for links.</p>\n<pre><code>[[Page]]\n</code></pre>\n"
+        );
+    }
+
+    #[test]
+    fn ordinary_links() {
+        assert_eq!(
+            render("[Some Note](other.md)"),
+            "<p><a href=\"other.md\">Some Note</a></p>\n"
+        );
+    }
+}
@@ -0,0 +1,109 @@
+use pulldown_cmark::{Event, Tag, TagEnd};
+
+/// The reading speed we assume for estimating reading times.
+pub const WORDS_PER_MINUTE: usize = 200;
+
+/// Count the words of prose in a document's events. Code blocks, metadata
+/// blocks, image alt text, inline code, and HTML don't count.
+pub fn count(events: &[Event]) -> usize {
+    prose(events)
+        .split_whitespace()
+        .filter(|word| word.chars().any(char::is_alphanumeric))
+        .count()
+}
+
+/// Extract the prose in a document's events as plain text, with the same
+/// exclusions as `count`, and with runs of whitespace collapsed to one space.
+pub fn plain_text(events: &[Event]) -> String {
+    prose(events)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Gather the text of the prose in a document, with spaces (and possibly
+/// extra whitespace) between blocks.
+fn prose(events: &[Event]) -> String {
+    let mut text = String::new();
+    let mut skip_depth = 0;
+    for event in events {
+        match event {
+            Event::Start(Tag::CodeBlock(_) | Tag::MetadataBlock(_) | Tag::Image { .. }) => {
+                skip_depth += 1;
+            }
+            Event::End(TagEnd::CodeBlock | TagEnd::MetadataBlock(_) | TagEnd::Image) => {
+                skip_depth -= 1;
+            }
+            Event::Text(t) if skip_depth == 0 => text.push_str(t),
+
+            // Inline markup can fall in the middle of a word, but other tags
+            // and line breaks separate words.
+            Event::Start(tag) if !is_inline(&tag.clone().into()) => text.push(' '),
+            Event::End(tag) if !is_inline(tag) => text.push(' '),
+            Event::SoftBreak | Event::HardBreak => text.push(' '),
+            _ => (),
+        }
+    }
+    text
+}
+
+/// Is this the end of inline markup, like emphasis or a link?
+fn is_inline(tag: &TagEnd) -> bool {
+    matches!(
+        tag,
+        TagEnd::Emphasis
+            | TagEnd::Strong
+            | TagEnd::Strikethrough
+            | TagEnd::Superscript
+            | TagEnd::Subscript
+            | TagEnd::Link
+    )
+}
+
+/// The estimated time to read a number of words, in whole minutes.
+pub fn reading_minutes(word_count: usize) -> usize {
+    word_count.div_ceil(WORDS_PER_MINUTE)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::markdown::{CodeHandlers, RenderOptions, render};
+
+    fn word_count(source: &str) -> usize {
+        render(source, &CodeHandlers::empty(), &RenderOptions::default())
+            .unwrap()
+            .word_count
+    }
+
+    #[test]
+    fn prose() {
+        assert_eq!(word_count("# Two words\n\nThree *more* wo**rds**.\n"), 5);
+        assert_eq!(word_count("- one\n- two\n\nthree\nfour\n"), 4);
+        assert_eq!(word_count("It's [a link](x.md).\n"), 3);
+    }
+
+    #[test]
+    fn skips_code_and_html() {
+        let source = "---\ntags: [a, b]\n---\nSome prose `code`.\n\n```rust\nfn main() { let x = 1; }\n```\n\n<div>raw html</div>\n\n![alt text](a.png)\n";
+        assert_eq!(word_count(source), 2);
+    }
+
+    #[test]
+    fn plain_text() {
+        let doc = render(
+            "# Title\n\nSome *prose*\nand `code`.\n\n```\nfn main() {}\n```\n\n- a\n- b\n",
+            &CodeHandlers::empty(),
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(doc.text, "Title Some prose and . a b");
+    }
+
+    #[test]
+    fn reading_minutes() {
+        assert_eq!(super::reading_minutes(0), 0);
+        assert_eq!(super::reading_minutes(1), 1);
+        assert_eq!(super::reading_minutes(200), 1);
+        assert_eq!(super::reading_minutes(201), 2);
+    }
+}
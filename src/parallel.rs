@@ -1,4 +1,4 @@
-use crossbeam_channel::{Sender, bounded};
+use crossbeam_channel::{Sender, bounded, unbounded};
 use std::num::NonZero;
 use std::{marker::PhantomData, thread};
 
@@ -9,7 +9,7 @@ use std::{marker::PhantomData, thread};
 pub fn scope<'scope, F, B, R>(body_fn: B) -> R
 where
     F: FnOnce() + Send + 'scope,
-    B: (Fn(ThreadPool<F>) -> R) + 'scope,
+    B: (FnOnce(ThreadPool<F>) -> R) + 'scope,
 {
     scope_with_threads(None, body_fn)
 }
@@ -20,7 +20,7 @@ where
 pub fn scope_with_threads<'scope, F, B, R>(thread_count: Option<NonZero<usize>>, body_fn: B) -> R
 where
     F: FnOnce() + Send + 'scope,
-    B: (Fn(ThreadPool<F>) -> R) + 'scope,
+    B: (FnOnce(ThreadPool<F>) -> R) + 'scope,
 {
     let threads = thread_count
         .or_else(|| thread::available_parallelism().ok())
@@ -35,7 +35,7 @@ where
 pub fn scope_with_sizes<'scope, F, B, R>(thread_count: usize, chan_size: usize, body_fn: B) -> R
 where
     F: FnOnce() + Send + 'scope,
-    B: (Fn(ThreadPool<F>) -> R) + 'scope,
+    B: (FnOnce(ThreadPool<F>) -> R) + 'scope,
 {
     assert!(thread_count > 0);
     assert!(chan_size > 0);
@@ -59,6 +59,93 @@ where
     })
 }
 
+/// Apply a function to every item on a thread pool and collect the results.
+///
+/// The pool is sized like the one for `scope_with_threads`. The results come
+/// back in whatever order the work finishes, not the order of `items`.
+pub fn collect_with_threads<T, R, I, W>(
+    thread_count: Option<NonZero<usize>>,
+    items: I,
+    work: W,
+) -> Vec<R>
+where
+    I: IntoIterator<Item = T>,
+    T: Send,
+    R: Send,
+    W: Fn(T) -> R + Sync,
+{
+    let (tx, rx) = unbounded();
+    let work = &work;
+    scope_with_threads(thread_count, |pool| {
+        for item in items {
+            let tx = tx.clone();
+            pool.spawn(move || {
+                // The receiver outlives the pool, so this can't fail.
+                let _ = tx.send(work(item));
+            });
+        }
+    });
+    drop(tx);
+    rx.into_iter().collect()
+}
+
+/// A way to run work in parallel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// Our own thread pool, built on crossbeam channels.
+    Crossbeam,
+    /// A rayon thread pool.
+    #[cfg(feature = "rayon")]
+    Rayon,
+}
+
+impl Default for Backend {
+    /// Use rayon when the `rayon` feature is enabled, and crossbeam otherwise.
+    fn default() -> Self {
+        #[cfg(feature = "rayon")]
+        return Backend::Rayon;
+        #[cfg(not(feature = "rayon"))]
+        return Backend::Crossbeam;
+    }
+}
+
+/// Apply a function to every item in parallel using the given backend.
+///
+/// The crossbeam pool is sized like the one for `scope_with_threads`; rayon
+/// uses its own default unless there is a specific number of threads.
+pub fn for_each_with_threads<T, I, W>(
+    backend: Backend,
+    thread_count: Option<NonZero<usize>>,
+    items: I,
+    work: W,
+) where
+    I: IntoIterator<Item = T>,
+    T: Send,
+    W: Fn(T) + Send + Sync,
+{
+    match backend {
+        Backend::Crossbeam => {
+            let work = &work;
+            scope_with_threads(thread_count, |pool| {
+                for item in items {
+                    pool.spawn(move || work(item));
+                }
+            });
+        }
+        #[cfg(feature = "rayon")]
+        Backend::Rayon => {
+            use rayon::prelude::*;
+            let mut builder = rayon::ThreadPoolBuilder::new();
+            if let Some(n) = thread_count {
+                builder = builder.num_threads(n.get());
+            }
+            let pool = builder.build().expect("could not create thread pool");
+            let items: Vec<T> = items.into_iter().collect();
+            pool.install(|| items.into_par_iter().for_each(work));
+        }
+    }
+}
+
 /// A running hread pool that can accept work.
 pub struct ThreadPool<'scope, F>
 where
@@ -85,7 +172,7 @@ mod tests {
 
     fn is_prime(i: u64) -> bool {
         for j in 2..i {
-            if i % j == 0 {
+            if i.is_multiple_of(j) {
                 return false;
             }
         }
@@ -112,4 +199,40 @@ mod tests {
         res_pairs.sort();
         assert_eq!(res_pairs, [(5, true), (10, false), (15, false), (19, true)]);
     }
+
+    #[test]
+    fn collect() {
+        let threads = NonZero::new(3);
+        let mut results = collect_with_threads(threads, [5, 10, 15, 19], |i| (i, is_prime(i)));
+        results.sort();
+        assert_eq!(results, [(5, true), (10, false), (15, false), (19, true)]);
+    }
+
+    #[test]
+    fn for_each() {
+        let backends = [
+            Backend::Crossbeam,
+            #[cfg(feature = "rayon")]
+            Backend::Rayon,
+        ];
+        for backend in backends {
+            let results = Mutex::new(vec![]);
+            for_each_with_threads(backend, NonZero::new(2), [5, 10, 15, 19], |i| {
+                results.lock().unwrap().push((i, is_prime(i)));
+            });
+            let mut results = results.into_inner().unwrap();
+            results.sort();
+            assert_eq!(results, [(5, true), (10, false), (15, false), (19, true)]);
+        }
+    }
+
+    #[test]
+    fn collect_borrowed() {
+        let names = vec!["a".to_string(), "bb".to_string(), "ccc".to_string()];
+        let suffix = String::from("!");
+        let mut results = collect_with_threads(None, &names, |name| format!("{name}{suffix}"));
+        results.sort();
+        assert_eq!(results, ["a!", "bb!", "ccc!"]);
+        assert!(collect_with_threads(None, Vec::<u64>::new(), is_prime).is_empty());
+    }
 }
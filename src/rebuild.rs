@@ -0,0 +1,164 @@
+use crate::core::Context;
+use crate::watch::{Watch, ignore_path};
+use anyhow::bail;
+use std::future::Future;
+use std::num::NonZero;
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+
+/// Build the site into `dest_dir`, and then build it again each time the
+/// source directory (or one of the `extra` directories to watch) changes, until
+/// Ctrl-C. Failed builds are logged, and we keep waiting for the next change.
+///
+/// The configuration in `ctx` stays as it was loaded: changes to `_config.toml`
+/// or `_site.yaml` take effect only after a restart.
+#[tokio::main]
+pub async fn watch_and_build(
+    ctx: Context,
+    threads: Option<NonZero<usize>>,
+    dest_dir: PathBuf,
+    extra: Vec<PathBuf>,
+) -> anyhow::Result<()> {
+    // Writing the site into a watched part of the source directory would
+    // trigger a rebuild after every build.
+    let dest = std::path::absolute(&dest_dir)?;
+    for src_dir in ctx.src_dirs() {
+        let src = std::path::absolute(src_dir)?;
+        if dest.starts_with(&src) && !ignore_path(&[src], ctx.config.ignore(), &dest) {
+            bail!(
+                "destination directory {} is inside the source directory {}; \
+                 pick one whose name starts with `_` or is ignored",
+                dest_dir.display(),
+                src_dir.display()
+            );
+        }
+    }
+
+    let watch = Watch::site(&ctx, extra);
+    log::info!("watching for changes; press Ctrl-C to stop");
+    let stop = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            log::warn!("could not listen for Ctrl-C: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+    build_on_changes(Arc::new(ctx), watch, threads, dest_dir, stop).await;
+    log::info!("stopped watching");
+    Ok(())
+}
+
+/// Build the site, and then rebuild it after each change that `watch` reports,
+/// until `stop` finishes. Changes that arrive during a build lead to just one
+/// more build.
+async fn build_on_changes(
+    ctx: Arc<Context>,
+    watch: Watch,
+    threads: Option<NonZero<usize>>,
+    dest_dir: PathBuf,
+    stop: impl Future<Output = ()>,
+) {
+    let mut changes = watch.subscribe();
+    let mut stop = pin!(stop);
+    loop {
+        if watch.take_templates_changed()
+            && let Err(e) = ctx.reload_templates()
+        {
+            log::error!("error reloading templates: {e}");
+        }
+        let ctx = ctx.clone();
+        let dest_dir = dest_dir.clone();
+        if let Err(e) = tokio::task::spawn_blocking(move || build(&ctx, threads, &dest_dir)).await {
+            log::error!("build panicked: {e}");
+        }
+
+        tokio::select! {
+            () = &mut stop => return,
+            change = changes.recv() => {
+                if let Err(RecvError::Closed) = change {
+                    return;
+                }
+            }
+        }
+        while !matches!(changes.try_recv(), Err(TryRecvError::Empty)) {}
+    }
+}
+
+/// Build the site once and log how it went.
+fn build(ctx: &Context, threads: Option<NonZero<usize>>, dest_dir: &Path) {
+    let start = Instant::now();
+    match ctx.render_site(threads, dest_dir) {
+        Ok(report) => {
+            report.log_problems();
+            log::info!(
+                "built {} notes and {} files in {:.2?}",
+                report.notes,
+                report.files,
+                start.elapsed()
+            );
+        }
+        Err(e) => log::error!("build failed: {e:#}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Site;
+    use std::time::Duration;
+
+    /// Wait a little while for a file to appear.
+    fn wait_for_file(path: &Path) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !path.exists() {
+            assert!(
+                Instant::now() < deadline,
+                "{} never appeared",
+                path.display()
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rebuild_on_change() {
+        let site = Site::new(&[("a.md", "# A\n")]);
+        let ctx = site.context(Default::default());
+        let watch = Watch::new(site.src.path(), &[], &[], Duration::from_millis(10));
+        let dest = tempfile::tempdir().unwrap();
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel();
+        let task = tokio::spawn(build_on_changes(
+            Arc::new(ctx),
+            watch,
+            None,
+            dest.path().to_path_buf(),
+            async {
+                let _ = stop_rx.await;
+            },
+        ));
+
+        let src = site.src.path().to_path_buf();
+        let dest_dir = dest.path().to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            wait_for_file(&dest_dir.join("a.html"));
+            std::fs::write(src.join("b.md"), "# B\n").unwrap();
+            wait_for_file(&dest_dir.join("b.html"));
+        })
+        .await
+        .unwrap();
+
+        stop_tx.send(()).unwrap();
+        task.await.unwrap();
+    }
+
+    #[test]
+    fn dest_inside_source() {
+        let site = Site::new(&[("a.md", "# A\n")]);
+        let ctx = site.context(Default::default());
+        let dest = site.src.path().join("site");
+        let err = watch_and_build(ctx, None, dest, vec![]).unwrap_err();
+        assert!(err.to_string().contains("inside the source directory"));
+    }
+}
@@ -0,0 +1,102 @@
+use jiff::civil::Date;
+
+/// The metadata about a note that we need to find related pages.
+#[derive(Debug)]
+pub struct Page {
+    /// The URL path of the note's page, relative to the site root.
+    pub url: String,
+    pub title: String,
+    pub date: Option<Date>,
+    pub tags: Vec<String>,
+}
+
+/// Find up to `count` pages that share at least `min_shared` tags with a page
+/// that has the given URL and tags.
+///
+/// The pages with the most tags in common come first. Among those, newer pages
+/// come before older ones (and undated pages come last). A page with no tags
+/// has no related pages.
+pub fn related<'a>(
+    url: &str,
+    tags: &[String],
+    pages: &'a [Page],
+    count: usize,
+    min_shared: usize,
+) -> Vec<&'a Page> {
+    if tags.is_empty() {
+        return vec![];
+    }
+    let mut scored: Vec<_> = pages
+        .iter()
+        .filter(|page| page.url != url)
+        .map(|page| {
+            let shared = page.tags.iter().filter(|t| tags.contains(t)).count();
+            (shared, page)
+        })
+        .filter(|(shared, _)| *shared > 0 && *shared >= min_shared)
+        .collect();
+
+    // Break remaining ties by URL to keep the order deterministic.
+    scored.sort_by(|(shared_a, a), (shared_b, b)| {
+        shared_b
+            .cmp(shared_a)
+            .then_with(|| b.date.cmp(&a.date))
+            .then_with(|| a.url.cmp(&b.url))
+    });
+    scored
+        .into_iter()
+        .take(count)
+        .map(|(_, page)| page)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(url: &str, date: Option<Date>, tags: &[&str]) -> Page {
+        Page {
+            url: url.into(),
+            title: url.into(),
+            date,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn most_shared_tags_first() {
+        let pages = [
+            page("self.html", None, &["a", "b", "c"]),
+            page("one.html", Some(jiff::civil::date(2024, 1, 1)), &["a"]),
+            page("two.html", None, &["a", "b", "z"]),
+            page("three.html", None, &["a", "b", "c"]),
+            page("newer.html", Some(jiff::civil::date(2024, 6, 1)), &["c"]),
+            page("none.html", None, &["z"]),
+        ];
+        let tags = &pages[0].tags;
+
+        let urls: Vec<_> = related("self.html", tags, &pages, 10, 1)
+            .iter()
+            .map(|p| p.url.as_str())
+            .collect();
+        assert_eq!(urls, ["three.html", "two.html", "newer.html", "one.html"]);
+
+        let urls: Vec<_> = related("self.html", tags, &pages, 1, 1)
+            .iter()
+            .map(|p| p.url.as_str())
+            .collect();
+        assert_eq!(urls, ["three.html"]);
+
+        let urls: Vec<_> = related("self.html", tags, &pages, 10, 2)
+            .iter()
+            .map(|p| p.url.as_str())
+            .collect();
+        assert_eq!(urls, ["three.html", "two.html"]);
+    }
+
+    #[test]
+    fn no_tags() {
+        let pages = [page("other.html", None, &["a"])];
+        assert!(related("self.html", &[], &pages, 10, 0).is_empty());
+    }
+}
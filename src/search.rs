@@ -0,0 +1,116 @@
+use crate::core::{Context, RenderedNote, output_path};
+use anyhow::Result;
+use serde::Serialize;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// A note's entry in the search index.
+#[derive(Debug, Serialize)]
+pub struct SearchEntry {
+    /// The note's URL, relative to the root of the site.
+    pub url: String,
+    pub title: String,
+    /// The note's prose as plain text, without markup or code.
+    pub text: String,
+}
+
+/// Gather search entries for the published notes that the build rendered,
+/// sorted by URL. The 404 page is left out.
+pub fn collect_entries(ctx: &Context, notes: &[RenderedNote]) -> Vec<SearchEntry> {
+    let not_found = ctx.not_found_note();
+    let mut entries: Vec<_> = notes
+        .iter()
+        .filter(|note| not_found.as_ref() != Some(&note.src_path))
+        .map(|note| SearchEntry {
+            url: ctx.note_url(&note.src_path),
+            title: note.title.clone(),
+            text: note.text.clone(),
+        })
+        .collect();
+    entries.sort_by(|a, b| a.url.cmp(&b.url));
+    entries
+}
+
+/// Write a search index, which is a JSON array of entries.
+pub fn write_index<W: Write>(entries: &[SearchEntry], dest: &mut W) -> io::Result<()> {
+    serde_json::to_writer(&mut *dest, entries)?;
+    writeln!(dest)?;
+    Ok(())
+}
+
+/// Generate the search index at `path`, relative to the destination directory.
+pub fn render_index(
+    ctx: &Context,
+    path: &str,
+    notes: &[RenderedNote],
+    dest_dir: &Path,
+) -> Result<()> {
+    let dest_path = output_path(dest_dir, path)?;
+    let entries = collect_entries(ctx, notes);
+    let mut file = io::BufWriter::new(fs::File::create(dest_path)?);
+    write_index(&entries, &mut file)?;
+    file.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::Config;
+    use crate::testing::Site;
+
+    #[test]
+    fn search_index() {
+        let site = Site::new(&[
+            (
+                "b.md",
+                "Intro.\n\n# Real Title\n\nSome *prose*.\n\n```rust\nfn secret() {}\n```\n",
+            ),
+            ("sub/a.md", "## Not a title\n\nText.\n"),
+            ("draft.md", "---\ndraft: true\n---\n# Draft\n"),
+            ("404.md", "# Lost\n"),
+        ]);
+        let config: Config = toml::from_str("search_index = \"search/index.json\"").unwrap();
+        let (_, tree) = site.build(&site.context(config));
+
+        let index: serde_json::Value = serde_json::from_str(&tree["search/index.json"]).unwrap();
+        assert_eq!(
+            index,
+            serde_json::json!([
+                {"url": "b.html", "title": "Real Title", "text": "Intro. Real Title Some prose."},
+                {"url": "sub/a.html", "title": "a", "text": "Not a title Text."},
+            ])
+        );
+    }
+
+    #[test]
+    fn title_from_h1() {
+        let site = Site::new(&[("a.md", "# The Title\n\nBody.\n\n## More\n")]);
+        let config: Config = toml::from_str("search_index = \"search-index.json\"").unwrap();
+        let (_, tree) = site.build(&site.context(config));
+        let index: serde_json::Value = serde_json::from_str(&tree["search-index.json"]).unwrap();
+        assert_eq!(index[0]["title"], "The Title");
+        assert_eq!(index[0]["text"], "The Title Body. More");
+    }
+
+    #[test]
+    fn disabled_by_default() {
+        let site = Site::new(&[("a.md", "# A\n")]);
+        let (_, tree) = site.build(&site.context(Config::default()));
+        assert!(!tree.keys().any(|k| k.ends_with(".json")));
+    }
+
+    #[test]
+    fn unreadable_note() {
+        let site = Site::new(&[
+            ("good.md", "# Good\n"),
+            ("bad.md", "---\ndate: [\n---\n# Bad\n"),
+        ]);
+        let config: Config = toml::from_str("search_index = \"index.json\"").unwrap();
+        let (report, tree) = site.build(&site.context(config));
+        assert_eq!(report.failures.len(), 1);
+        let index: serde_json::Value = serde_json::from_str(&tree["index.json"]).unwrap();
+        assert_eq!(index.as_array().unwrap().len(), 1);
+        assert_eq!(index[0]["title"], "Good");
+    }
+}
@@ -1,109 +1,926 @@
-use crate::Context;
-use crate::core::Resource;
-use crate::watch::Watch;
+use crate::core::Context;
+use crate::core::{Resource, TEMPLATES};
+use crate::markdown::percent_encode_path;
+use crate::watch::{Event, Watch};
+use anyhow::Context as _;
 use axum::{
     Router,
-    extract::{Path, State},
-    http::{StatusCode, header},
-    response::{Html, IntoResponse, Response, sse},
+    body::Body,
+    extract::{Path, Query, RawQuery, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{Html, IntoResponse, Redirect, Response, sse},
     routing::get,
 };
-use axum_extra::body::AsyncReadBody;
+use jiff::{Timestamp, fmt::rfc2822};
+use serde::Deserialize;
+use std::borrow::Cow;
 use std::convert::Infallible;
-use std::path;
-use std::sync::{Arc, RwLock};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::num::NonZero;
+use std::path::{self, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
 use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::Semaphore;
 use tokio_stream::{Stream, StreamExt};
+use tokio_util::io::ReaderStream;
+use tokio_util::sync::CancellationToken;
+
+/// Files larger than this count as "large" for limiting concurrent streams.
+const LARGE_FILE_SIZE: u64 = 1 << 20;
+
+/// Settings for the preview server.
+pub struct ServeOptions {
+    /// The address to listen on.
+    pub addr: SocketAddr,
+
+    /// The size of the chunks to read when streaming static files.
+    pub chunk_size: NonZero<usize>,
+
+    /// The maximum number of large static files to stream at once, if any.
+    pub max_streams: Option<NonZero<usize>>,
+
+    /// Extra directories to watch for changes, besides the source directory.
+    pub watch: Vec<PathBuf>,
+
+    /// Open the site in a web browser once the server is listening.
+    pub open: bool,
+}
 
 #[derive(Clone)]
 struct AppState {
-    ctx: Arc<RwLock<Context>>,
+    ctx: Arc<Context>,
     watch: Arc<Watch>,
+    files: FileStreaming,
+    /// Cancelled when the server is shutting down, to end open event streams.
+    shutdown: CancellationToken,
+}
+
+/// Settings and shared state for sending static files.
+#[derive(Clone)]
+struct FileStreaming {
+    chunk_size: usize,
+    large_streams: Option<Arc<Semaphore>>,
 }
 
+/// Run the preview server until Ctrl-C, reloading pages as sources change.
+/// The configuration in `ctx`, from `_config.toml` and `_site.yaml`, stays
+/// as it was loaded; changing it takes a restart.
 #[tokio::main]
-pub async fn serve(ctx: Context) {
-    // Watch the source directory and, in debug mode, the templates directory.
-    let watch = Watch::new(&[
-        &ctx.src_dir,
-        #[cfg(debug_assertions)]
-        path::Path::new(crate::core::TEMPLATES.dir),
-    ]);
+pub async fn serve(ctx: Context, opts: ServeOptions) -> anyhow::Result<()> {
+    // Catch template mistakes up front rather than on some later request.
+    ctx.validate_templates()?;
+
+    let watch = Watch::site(&ctx, opts.watch);
+    let shutdown = CancellationToken::new();
     let state = AppState {
-        ctx: Arc::new(RwLock::new(ctx)),
+        ctx: Arc::new(ctx),
         watch: Arc::new(watch),
+        files: FileStreaming {
+            chunk_size: opts.chunk_size.get(),
+            large_streams: opts.max_streams.map(|n| Arc::new(Semaphore::new(n.get()))),
+        },
+        shutdown: shutdown.clone(),
     };
 
     let app = Router::new()
         .route("/_notify", get(notify))
+        .route("/", get(root))
         .route("/{*path}", get(resource))
         .with_state(state);
 
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
+    let addr = opts.addr;
+    let listener = tokio::net::TcpListener::bind(addr)
         .await
-        .unwrap();
-    eprintln!("listening on http://{}", listener.local_addr().unwrap());
-    axum::serve(listener, app).await.unwrap();
+        .with_context(|| format!("could not listen on {addr}"))?;
+    let local_addr = listener.local_addr()?;
+    log::info!("listening on http://{local_addr}");
+    if opts.open {
+        open_browser(local_addr);
+    }
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal(shutdown))
+        .await?;
+    Ok(())
+}
+
+/// Wait for Ctrl-C, then cancel `token` to start shutting down. If we can't
+/// listen for the signal, this never finishes.
+async fn shutdown_signal(token: CancellationToken) {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        log::warn!("could not listen for Ctrl-C: {e}");
+        std::future::pending::<()>().await;
+    }
+    log::info!("shutting down");
+    token.cancel();
+}
+
+/// End a stream when `token` is cancelled, so long-lived responses don't hold
+/// up shutdown.
+fn until_shutdown<S>(stream: S, token: CancellationToken) -> impl Stream<Item = S::Item>
+where
+    S: Stream,
+{
+    let stop = tokio_stream::once(())
+        .then(move |()| token.clone().cancelled_owned())
+        .map(|()| None);
+    stream.map(Some).merge(stop).map_while(|item| item)
+}
+
+/// Open the server's root page in the default web browser, without waiting for
+/// it. A server listening on all interfaces is opened via the loopback address.
+/// Failing to find a browser is not fatal.
+fn open_browser(mut addr: SocketAddr) {
+    if addr.ip().is_unspecified() {
+        addr.set_ip(match addr {
+            SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+            SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+        });
+    }
+    let url = format!("http://{addr}/");
+    if let Err(e) = open::that_detached(&url) {
+        log::warn!("could not open {url} in a browser: {e}");
+    }
+}
+
+/// Validators for a version of a static file, for answering conditional
+/// requests.
+struct Validators {
+    etag: String,
+    modified: Timestamp,
+}
+
+impl Validators {
+    /// Identify a version of a file by its modification time and size.
+    fn new(modified: SystemTime, size: u64) -> Option<Self> {
+        let modified = Timestamp::try_from(modified).ok()?;
+        let nanos = modified.as_nanosecond();
+        Some(Self {
+            etag: format!("\"{nanos:x}-{size:x}\""),
+            // HTTP dates only have whole seconds.
+            modified: Timestamp::from_second(modified.as_second()).ok()?,
+        })
+    }
+
+    /// Does a request's `If-None-Match` or `If-Modified-Since` header show
+    /// that the client already has this version? `If-None-Match` takes
+    /// precedence when both are present.
+    fn not_modified(&self, request: &HeaderMap) -> bool {
+        if let Some(tags) = request.get(header::IF_NONE_MATCH) {
+            let Ok(tags) = tags.to_str() else {
+                return false;
+            };
+            return tags.split(',').any(|tag| {
+                let tag = tag.trim();
+                tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == self.etag
+            });
+        }
+        request
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(|date| date.to_str().ok())
+            .and_then(|date| rfc2822::parse(date).ok())
+            .is_some_and(|date| self.modified <= date.timestamp())
+    }
+
+    /// Does a request's `If-Range` header, if it has one, name this version,
+    /// so that its `Range` header applies? An entity tag must match exactly,
+    /// and so must a date.
+    fn range_applies(&self, request: &HeaderMap) -> bool {
+        let Some(value) = request.get(header::IF_RANGE) else {
+            return true;
+        };
+        let Ok(value) = value.to_str() else {
+            return false;
+        };
+        let value = value.trim();
+        if value.starts_with('"') || value.starts_with("W/") {
+            value == self.etag
+        } else {
+            rfc2822::parse(value).is_ok_and(|date| date.timestamp() == self.modified)
+        }
+    }
+
+    /// Add caching headers to a response. Clients may keep the file, but they
+    /// must check with us that it's still current before using it.
+    fn add_headers(&self, headers: &mut HeaderMap) {
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        if let Ok(etag) = HeaderValue::from_str(&self.etag) {
+            headers.insert(header::ETAG, etag);
+        }
+        let date = rfc2822::DateTimePrinter::new().timestamp_to_rfc9110_string(&self.modified);
+        if let Ok(date) = date
+            && let Ok(date) = HeaderValue::from_str(&date)
+        {
+            headers.insert(header::LAST_MODIFIED, date);
+        }
+    }
+}
+
+/// What a `Range` header asks for from a file.
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    /// The whole file, as if there were no `Range` header.
+    Whole,
+    /// The bytes from the first position to the last, inclusive.
+    Part(u64, u64),
+    /// A range that lies outside the file.
+    Unsatisfiable,
+}
+
+/// Parse a `Range` header asking for a single range of bytes from a file of
+/// length `size`, like `bytes=0-99`, `bytes=100-`, or `bytes=-100` (the last
+/// 100 bytes). The last position is clamped to the end of the file.
+///
+/// We ignore headers that we can't make sense of, in other units, or asking
+/// for several ranges, and send the whole file, as RFC 9110 allows.
+fn parse_range(range: &str, size: u64) -> ByteRange {
+    let Some((first, last)) = range
+        .trim()
+        .strip_prefix("bytes=")
+        .filter(|spec| !spec.contains(','))
+        .and_then(|spec| spec.split_once('-'))
+    else {
+        return ByteRange::Whole;
+    };
+    let (first, last) = (first.trim(), last.trim());
+    if first.is_empty() {
+        return match last.parse::<u64>() {
+            Ok(0) => ByteRange::Unsatisfiable,
+            Ok(suffix) if size > 0 => ByteRange::Part(size.saturating_sub(suffix), size - 1),
+            Ok(_) => ByteRange::Unsatisfiable,
+            Err(_) => ByteRange::Whole,
+        };
+    }
+    let Ok(first) = first.parse::<u64>() else {
+        return ByteRange::Whole;
+    };
+    let last = match last {
+        "" => u64::MAX,
+        last => match last.parse::<u64>() {
+            Ok(last) if last >= first => last,
+            _ => return ByteRange::Whole,
+        },
+    };
+    if first >= size {
+        ByteRange::Unsatisfiable
+    } else {
+        ByteRange::Part(first, last.min(size - 1))
+    }
+}
+
+/// Respond with a rendered page, which clients shouldn't cache, since it
+/// changes whenever its source or the templates do.
+fn send_page(html: Vec<u8>) -> Response {
+    ([(header::CACHE_CONTROL, "no-store")], Html(html)).into_response()
 }
 
 /// Respond with the contents of a file on the filesystem.
-async fn send_file(path: &path::Path) -> Result<Response, (StatusCode, String)> {
+///
+/// The file is streamed in chunks of the configured size. If there's a limit on
+/// concurrent streams of large files, we wait for a slot before starting and
+/// hold onto it until the body is finished.
+///
+/// Responses carry an `ETag` and `Last-Modified` date, and when the request's
+/// conditional headers show that the client's copy is current, we send
+/// `304 Not Modified` with no body. A `Range` header for a single range of bytes
+/// gets `206 Partial Content` with just those bytes, or `416 Range Not
+/// Satisfiable` if they lie outside the file. With an `If-Range` header that
+/// doesn't name the current version, the `Range` header doesn't count.
+async fn send_file(
+    path: &path::Path,
+    opts: &FileStreaming,
+    request: &HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
     let mime = mime_guess::from_path(path)
         .first_raw()
         .unwrap_or(mime_guess::mime::OCTET_STREAM.as_str());
 
-    let file = fs::File::open(path)
-        .await
-        .map_err(|e| (StatusCode::NOT_FOUND, format!("not found: {e}")))?;
+    let not_found = |e| (StatusCode::NOT_FOUND, format!("not found: {e}"));
+    let mut file = fs::File::open(path).await.map_err(not_found)?;
+    let metadata = file.metadata().await.map_err(not_found)?;
+    let size = metadata.len();
+
+    let validators = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| Validators::new(modified, size));
+    if let Some(validators) = &validators
+        && validators.not_modified(request)
+    {
+        let mut response = StatusCode::NOT_MODIFIED.into_response();
+        validators.add_headers(response.headers_mut());
+        return Ok(response);
+    }
+
+    let range_applies = match &validators {
+        Some(validators) => validators.range_applies(request),
+        None => !request.contains_key(header::IF_RANGE),
+    };
+    let range = request
+        .get(header::RANGE)
+        .filter(|_| range_applies)
+        .and_then(|range| range.to_str().ok())
+        .map_or(ByteRange::Whole, |range| parse_range(range, size));
+    let range = match range {
+        ByteRange::Whole => None,
+        ByteRange::Part(first, last) => Some((first, last)),
+        ByteRange::Unsatisfiable => {
+            let content_range = format!("bytes */{size}");
+            let headers = [(header::CONTENT_RANGE, content_range)];
+            return Ok((StatusCode::RANGE_NOT_SATISFIABLE, headers).into_response());
+        }
+    };
+    let length = match range {
+        Some((first, last)) => {
+            let seek_failed = |e| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("read error: {e}"),
+                )
+            };
+            file.seek(std::io::SeekFrom::Start(first))
+                .await
+                .map_err(seek_failed)?;
+            last - first + 1
+        }
+        None => size,
+    };
+
+    let permit = match &opts.large_streams {
+        Some(sem) if length > LARGE_FILE_SIZE => Some(
+            sem.clone()
+                .acquire_owned()
+                .await
+                .expect("semaphore is never closed"),
+        ),
+        _ => None,
+    };
 
     let headers = [(header::CONTENT_TYPE, mime)];
-    let body = AsyncReadBody::new(file);
-    Ok((headers, body).into_response())
+    let stream =
+        ReaderStream::with_capacity(file.take(length), opts.chunk_size).map(move |chunk| {
+            // Keep the permit alive for as long as the stream.
+            let _ = &permit;
+            chunk
+        });
+    let mut response = (headers, Body::from_stream(stream)).into_response();
+    let response_headers = response.headers_mut();
+    response_headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    response_headers.insert(header::CONTENT_LENGTH, HeaderValue::from(length));
+    if let Some((first, last)) = range
+        && let Ok(content_range) = HeaderValue::from_str(&format!("bytes {first}-{last}/{size}"))
+    {
+        response_headers.insert(header::CONTENT_RANGE, content_range);
+        *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+    }
+    if let Some(validators) = &validators {
+        validators.add_headers(response.headers_mut());
+    }
+    Ok(response)
+}
+
+#[derive(Deserialize)]
+struct PageParams {
+    /// If present, send only a note's rendered body, without the template.
+    fragment: Option<String>,
+}
+
+/// Serve the index of the site's root directory.
+async fn root(
+    State(state): State<AppState>,
+    Query(params): Query<PageParams>,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    serve_path(state, "", params, query.as_deref(), &headers).await
 }
 
 /// Serve a resource from the site.
 async fn resource(
     State(state): State<AppState>,
     Path(path): Path<String>,
+    Query(params): Query<PageParams>,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
 ) -> Result<Response, (StatusCode, String)> {
-    eprintln!("GET {path}");
+    serve_path(state, &path, params, query.as_deref(), &headers).await
+}
 
-    let rsrc = {
-        let ctx = &mut state.ctx.read().unwrap();
-        ctx.resolve_resource(&path)
-    };
+/// Redirect to the slash-terminated URL for a (decoded) path, keeping the
+/// request's query string.
+fn redirect_to_dir(path: &str, query: Option<&str>) -> Response {
+    let mut url = format!("/{}/", percent_encode_path(path.trim_start_matches('/')));
+    if let Some(query) = query {
+        url.push('?');
+        url.push_str(query);
+    }
+    Redirect::temporary(&url).into_response()
+}
+
+/// Look up and respond with the resource at a given path within the site.
+async fn serve_path(
+    state: AppState,
+    path: &str,
+    params: PageParams,
+    query: Option<&str>,
+    headers: &HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    log::debug!("GET /{path}");
+
+    let rsrc = state.ctx.resolve_resource(path);
     match rsrc {
+        // Like directories, pretty note URLs are canonically slash-terminated.
+        Some(Resource::Note(_))
+            if state.ctx.config.pretty_urls()
+                && !path.is_empty()
+                && !path.ends_with('/')
+                && !path.ends_with(".html") =>
+        {
+            Ok(redirect_to_dir(path, query))
+        }
+        Some(Resource::Note(src_path)) if params.fragment.is_some() => {
+            match state.ctx.render_body(&src_path) {
+                Ok(body) => Ok(Html(body).into_response()),
+                Err(e) => Err(render_failed("note", &src_path, e)),
+            }
+        }
         Some(Resource::Note(src_path)) => {
-            // In debug mode, reload templates before rendering.
-            #[cfg(debug_assertions)]
-            state.ctx.write().unwrap().reload_templates();
+            refresh_templates(&state.ctx, &state.watch);
+            if state.watch.take_notes_changed() {
+                state.ctx.forget_notes();
+            }
 
             // Render and send the note.
             let mut buf: Vec<u8> = vec![];
-            match state.ctx.read().unwrap().render_note(&src_path, &mut buf) {
-                Ok(()) => Ok(Html(buf).into_response()),
-                Err(e) => Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    format!("note rendering failed: {e}"),
-                )),
+            match state.ctx.render_note(&src_path, &mut buf) {
+                Ok(()) => Ok(send_page(buf)),
+                Err(e) => Err(render_failed("note", &src_path, e)),
+            }
+        }
+        Some(Resource::Static(src_path)) => send_file(&src_path, &state.files, headers).await,
+        Some(Resource::Directory(src_path)) => {
+            // Redirect to the canonical, slash-terminated URL so relative
+            // links on the page resolve within the directory.
+            if !path.is_empty() && !path.ends_with('/') {
+                return Ok(redirect_to_dir(path, query));
+            }
+
+            refresh_templates(&state.ctx, &state.watch);
+
+            let mut buf: Vec<u8> = vec![];
+            match state.ctx.render_directory(&src_path, &mut buf) {
+                Ok(()) => Ok(send_page(buf)),
+                Err(e) => Err(render_failed("directory", &src_path, e)),
             }
         }
-        Some(Resource::Static(src_path)) => send_file(&src_path).await,
-        Some(Resource::Directory(_)) => Err((
-            StatusCode::NOT_IMPLEMENTED,
-            "directory listings not implemented".into(),
-        )),
-        None => Err((StatusCode::NOT_FOUND, "not found".into())),
+        None => match send_asset(path) {
+            Some(response) => Ok(response),
+            None => Ok(not_found(&state.ctx)),
+        },
+    }
+}
+
+/// Log a failure to render a page and make the error response for it.
+fn render_failed(what: &str, src_path: &path::Path, e: anyhow::Error) -> (StatusCode, String) {
+    log::error!("error rendering {what} {}: {e:#}", src_path.display());
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("{what} rendering failed: {e}"),
+    )
+}
+
+/// Respond with the site's 404 page, if it has one, or else a plain message.
+fn not_found(ctx: &Context) -> Response {
+    if let Some(src_path) = ctx.not_found_note() {
+        let mut buf: Vec<u8> = vec![];
+        match ctx.render_note(&src_path, &mut buf) {
+            Ok(()) => return (StatusCode::NOT_FOUND, Html(buf)).into_response(),
+            Err(e) => log::error!("error rendering 404 page: {e:#}"),
+        }
+    }
+    (StatusCode::NOT_FOUND, "not found").into_response()
+}
+
+/// Respond with one of our built-in assets, like the stylesheet, if there is
+/// one with the given name. These are embedded in release builds, so the
+/// server doesn't need them on disk. HTML templates are not served.
+fn send_asset(name: &str) -> Option<Response> {
+    if name.ends_with(".html") {
+        return None;
     }
+    let contents: Cow<'static, str> = match TEMPLATES.get(name) {
+        Some(contents) => contents.into(),
+        None => TEMPLATES.read(name).ok()??.into(),
+    };
+    let mime = mime_guess::from_path(name)
+        .first_raw()
+        .unwrap_or(mime_guess::mime::OCTET_STREAM.as_str());
+    Some(([(header::CONTENT_TYPE, mime)], contents).into_response())
+}
+
+#[derive(Deserialize)]
+struct NotifyParams {
+    /// The path of the page the client is viewing.
+    path: Option<String>,
 }
 
 /// Server-Sent Events endpoint for getting change notifications.
+///
+/// A `reload` event means that every page should reload, and a `reload-page`
+/// event names the one page that changed. Clients can say which page they're
+/// viewing with a `path` query parameter, in which case they're only notified
+/// about changes that affect that page.
 async fn notify(
     State(state): State<AppState>,
+    Query(params): Query<NotifyParams>,
 ) -> sse::Sse<impl Stream<Item = Result<sse::Event, Infallible>>> {
-    let stream = state.watch.stream().map(|_| {
-        eprintln!("sending reload event");
-        Ok(sse::Event::default().event("reload").data("_"))
-    });
-    sse::Sse::new(stream)
+    // Find the URL of the changed page, if there's just one.
+    let ctx = state.ctx.clone();
+    let stream = state
+        .watch
+        .stream()
+        .map(move |event| match event {
+            Ok(Event::ReloadPage(rel_path)) => Some(ctx.note_url(&ctx.src_dir.join(rel_path))),
+            _ => None,
+        })
+        .filter(move |url| match (url, &params.path) {
+            (Some(url), Some(page)) => affects(page, url),
+            _ => true,
+        })
+        .map(|url| {
+            log::debug!("sending reload event");
+            Ok(match url {
+                Some(url) => sse::Event::default().event("reload-page").data(url),
+                None => sse::Event::default().event("reload").data("_"),
+            })
+        });
+    sse::Sse::new(until_shutdown(stream, state.shutdown))
+}
+
+/// Reload templates before rendering if the watch has seen a template change
+/// since the last reload, and report whether we did. Other requests can keep
+/// rendering with the old templates in the meantime, and if the new ones are
+/// broken, we log the error and keep the old ones.
+fn refresh_templates(ctx: &Context, watch: &Watch) -> bool {
+    if watch.take_templates_changed() {
+        if let Err(e) = ctx.reload_templates() {
+            log::error!("error reloading templates: {e}");
+        }
+        true
+    } else {
+        false
+    }
+}
+
+/// Check whether a change to the note whose page is at `changed` (a URL path
+/// relative to the site root) affects the page being viewed at `page`.
+///
+/// A note affects its own page and the index of the directory containing it.
+fn affects(page: &str, changed: &str) -> bool {
+    let page = page.trim_start_matches('/');
+    if page == changed {
+        true
+    } else if page.is_empty() || page.ends_with('/') {
+        // With pretty URLs, the changed page's own URL ends in a slash too.
+        let changed = changed.trim_end_matches('/');
+        let dir = changed.rsplit_once('/').map_or("", |(dir, _)| dir);
+        page.trim_end_matches('/') == dir
+    } else {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn note_affects_own_page() {
+        assert!(affects("/a.html", "a.html"));
+        assert!(affects("/dir/a.html", "dir/a.html"));
+    }
+
+    #[test]
+    fn note_does_not_affect_other_page() {
+        assert!(!affects("/a.html", "b.html"));
+        assert!(!affects("/dir/a.html", "a.html"));
+    }
+
+    #[test]
+    fn note_affects_directory_index() {
+        assert!(affects("/", "a.html"));
+        assert!(affects("/dir/", "dir/a.html"));
+        assert!(!affects("/dir/", "a.html"));
+        assert!(!affects("/", "dir/a.html"));
+    }
+
+    #[test]
+    fn pretty_note_affects_own_page() {
+        assert!(affects("/a/", "a/"));
+        assert!(affects("/", "a/"));
+        assert!(affects("/dir/", "dir/a/"));
+        assert!(!affects("/dir/", "a/"));
+        assert!(!affects("/a/", "b/"));
+    }
+
+    #[test]
+    fn builtin_assets() {
+        let response = send_asset("style.css").unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/css");
+        assert!(send_asset("note.html").is_none());
+        assert!(send_asset("missing.css").is_none());
+    }
+
+    #[tokio::test]
+    async fn not_found_page() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = Context::new(
+            dir.path().to_str().unwrap(),
+            false,
+            Default::default(),
+            None,
+        );
+        let response = not_found(&ctx);
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/plain; charset=utf-8"
+        );
+
+        std::fs::write(dir.path().join("404.md"), "# Nothing Here\n").unwrap();
+        let response = not_found(&ctx);
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("<h1 id=\"nothing-here\">"));
+    }
+
+    #[tokio::test]
+    async fn file_chunk_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("big.bin");
+        std::fs::write(&path, vec![0u8; 10_000]).unwrap();
+
+        let opts = FileStreaming {
+            chunk_size: 4_000,
+            large_streams: Some(Arc::new(Semaphore::new(1))),
+        };
+        let response = send_file(&path, &opts, &HeaderMap::new()).await.unwrap();
+        let mut body = response.into_body().into_data_stream();
+        let mut sizes = vec![];
+        while let Some(chunk) = body.next().await {
+            sizes.push(chunk.unwrap().len());
+        }
+        assert_eq!(sizes, [4_000, 4_000, 2_000]);
+    }
+
+    #[tokio::test]
+    async fn conditional_requests() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("style.css");
+        std::fs::write(&path, "body {}").unwrap();
+        let opts = FileStreaming {
+            chunk_size: 4_000,
+            large_streams: None,
+        };
+
+        let response = send_file(&path, &opts, &HeaderMap::new()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-cache");
+        let etag = response.headers()[header::ETAG].clone();
+        let modified = response.headers()[header::LAST_MODIFIED].clone();
+
+        // A matching ETag gets an empty 304 response.
+        let mut request = HeaderMap::new();
+        request.insert(header::IF_NONE_MATCH, etag.clone());
+        let response = send_file(&path, &opts, &request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[header::ETAG], etag);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body.is_empty());
+
+        // A stale ETag wins over a current date.
+        let mut request = HeaderMap::new();
+        request.insert(header::IF_NONE_MATCH, HeaderValue::from_static("\"old\""));
+        request.insert(header::IF_MODIFIED_SINCE, modified.clone());
+        let response = send_file(&path, &opts, &request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The date works on its own.
+        let mut request = HeaderMap::new();
+        request.insert(header::IF_MODIFIED_SINCE, modified);
+        let response = send_file(&path, &opts, &request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        request.insert(
+            header::IF_MODIFIED_SINCE,
+            HeaderValue::from_static("Sun, 06 Nov 1994 08:49:37 GMT"),
+        );
+        let response = send_file(&path, &opts, &request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn range_headers() {
+        use ByteRange::*;
+        assert_eq!(parse_range("bytes=0-99", 1000), Part(0, 99));
+        assert_eq!(parse_range("bytes=900-", 1000), Part(900, 999));
+        assert_eq!(parse_range("bytes=-100", 1000), Part(900, 999));
+        assert_eq!(parse_range("bytes=-5000", 1000), Part(0, 999));
+        assert_eq!(parse_range("bytes=990-5000", 1000), Part(990, 999));
+        assert_eq!(parse_range("bytes=1000-", 1000), Unsatisfiable);
+        assert_eq!(parse_range("bytes=-0", 1000), Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-", 0), Unsatisfiable);
+        assert_eq!(parse_range("bytes=-5", 0), Unsatisfiable);
+        assert_eq!(parse_range("bytes=5-2", 1000), Whole);
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), Whole);
+        assert_eq!(parse_range("items=0-1", 1000), Whole);
+        assert_eq!(parse_range("bytes=oops", 1000), Whole);
+    }
+
+    #[tokio::test]
+    async fn byte_ranges() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("digits.txt");
+        std::fs::write(&path, "0123456789").unwrap();
+        let opts = FileStreaming {
+            chunk_size: 4,
+            large_streams: None,
+        };
+        let get = async |range: &'static str| {
+            let mut request = HeaderMap::new();
+            request.insert(header::RANGE, HeaderValue::from_static(range));
+            let response = send_file(&path, &opts, &request).await.unwrap();
+            let status = response.status();
+            let headers = response.headers().clone();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            (status, headers, String::from_utf8(body.to_vec()).unwrap())
+        };
+
+        let (status, headers, body) = get("bytes=2-5").await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(headers[header::CONTENT_RANGE], "bytes 2-5/10");
+        assert_eq!(headers[header::CONTENT_LENGTH], "4");
+        assert_eq!(body, "2345");
+
+        let (status, headers, body) = get("bytes=7-").await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(headers[header::CONTENT_RANGE], "bytes 7-9/10");
+        assert_eq!(body, "789");
+
+        let (status, headers, body) = get("bytes=20-30").await;
+        assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(headers[header::CONTENT_RANGE], "bytes */10");
+        assert!(body.is_empty());
+
+        // Ranges we don't handle get the whole file.
+        for range in ["bytes=oops", "bytes=0-1,5-6", "items=0-1"] {
+            let (status, headers, body) = get(range).await;
+            assert_eq!(status, StatusCode::OK);
+            assert!(!headers.contains_key(header::CONTENT_RANGE));
+            assert_eq!(body, "0123456789");
+        }
+
+        let response = send_file(&path, &opts, &HeaderMap::new()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::ACCEPT_RANGES], "bytes");
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "10");
+    }
+
+    #[tokio::test]
+    async fn if_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("digits.txt");
+        std::fs::write(&path, "0123456789").unwrap();
+        let opts = FileStreaming {
+            chunk_size: 4,
+            large_streams: None,
+        };
+        let response = send_file(&path, &opts, &HeaderMap::new()).await.unwrap();
+        let etag = response.headers()[header::ETAG].clone();
+        let modified = response.headers()[header::LAST_MODIFIED].clone();
+
+        let status = async |if_range: HeaderValue| {
+            let mut request = HeaderMap::new();
+            request.insert(header::RANGE, HeaderValue::from_static("bytes=2-5"));
+            request.insert(header::IF_RANGE, if_range);
+            send_file(&path, &opts, &request).await.unwrap().status()
+        };
+        assert_eq!(status(etag.clone()).await, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(status(modified).await, StatusCode::PARTIAL_CONTENT);
+
+        // A different version, or a weak match, gets the whole file.
+        let stale = HeaderValue::from_static("\"0-a\"");
+        assert_eq!(status(stale).await, StatusCode::OK);
+        let weak = HeaderValue::from_str(&format!("W/{}", etag.to_str().unwrap())).unwrap();
+        assert_eq!(status(weak).await, StatusCode::OK);
+        let old = HeaderValue::from_static("Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(status(old).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn pretty_url_redirect() {
+        let site = crate::testing::Site::new(&[
+            ("a.md", "# A\n"),
+            ("sub/b.md", "# B\n"),
+            ("why? #2/c.md", "# C\n"),
+        ]);
+        let ctx = site.context(toml::from_str("pretty_urls = true").unwrap());
+        let state = AppState {
+            watch: Arc::new(Watch::new(
+                site.src.path(),
+                &[],
+                &[],
+                std::time::Duration::from_millis(10),
+            )),
+            ctx: Arc::new(ctx),
+            files: FileStreaming {
+                chunk_size: 4_000,
+                large_streams: None,
+            },
+            shutdown: CancellationToken::new(),
+        };
+        for (path, query, location) in [
+            ("a", None, "/a/"),
+            ("sub", None, "/sub/"),
+            ("sub", Some("x=1"), "/sub/?x=1"),
+            ("/sub", None, "/sub/"),
+            ("why? #2", None, "/why%3F%20%232/"),
+        ] {
+            let response = serve_path(
+                state.clone(),
+                path,
+                PageParams { fragment: None },
+                query,
+                &HeaderMap::new(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+            assert_eq!(response.headers()[header::LOCATION], location);
+        }
+    }
+
+    #[tokio::test]
+    async fn reload_templates_once() {
+        let site =
+            crate::testing::Site::new(&[("_note.html", "old: {{ title }}"), ("a.md", "# Hi\n")]);
+        let ctx = site.context(Default::default());
+        let watch = Watch::new(
+            site.src.path(),
+            &[],
+            &[],
+            std::time::Duration::from_millis(10),
+        );
+        let mut rx = watch.subscribe();
+        let render = || {
+            ctx.render_note_to_string(&site.src.path().join("a.md"))
+                .unwrap()
+        };
+        assert_eq!(render(), "old: Hi");
+        assert!(!refresh_templates(&ctx, &watch));
+
+        // Edit the template, with a later modification time to be sure it
+        // looks changed, and wait for the watch to notice.
+        let path = site.src.path().join("_note.html");
+        std::fs::write(&path, "new: {{ title }}").unwrap();
+        let later = SystemTime::now() + std::time::Duration::from_secs(10);
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        let reload = async { while !matches!(rx.recv().await, Ok(Event::ReloadTemplates)) {} };
+        tokio::time::timeout(std::time::Duration::from_secs(5), reload)
+            .await
+            .expect("no reload event");
+
+        // Only the first request after the change reloads the templates.
+        let reloads: Vec<_> = (0..3)
+            .map(|_| {
+                let reloaded = refresh_templates(&ctx, &watch);
+                assert_eq!(render(), "new: Hi");
+                reloaded
+            })
+            .collect();
+        assert_eq!(reloads, [true, false, false]);
+    }
+
+    #[tokio::test]
+    async fn stream_ends_on_shutdown() {
+        let token = CancellationToken::new();
+        let stream = tokio_stream::iter([1, 2]).chain(tokio_stream::pending());
+        let mut stream = Box::pin(until_shutdown(stream, token.clone()));
+        assert_eq!(stream.next().await, Some(1));
+        assert_eq!(stream.next().await, Some(2));
+        token.cancel();
+        assert_eq!(stream.next().await, None);
+    }
 }
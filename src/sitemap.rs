@@ -0,0 +1,140 @@
+use crate::core::{Context, output_path};
+use crate::feed::{absolute_url, escape_xml, last_modified};
+use anyhow::Result;
+use jiff::civil::Date;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// A page's entry in the sitemap.
+#[derive(Debug)]
+pub struct SitemapEntry {
+    pub url: String,
+    pub lastmod: Date,
+}
+
+/// Gather sitemap entries for the site's published notes, sorted by URL.
+///
+/// Notes that ask not to be indexed (including drafts) are left out, as is the
+/// 404 page. So are notes that can't be read; the build reports them anyway.
+pub fn collect_entries(ctx: &Context, base_url: &str) -> Result<Vec<SitemapEntry>> {
+    let not_found = ctx.not_found_note();
+    let mut entries = vec![];
+    for note in ctx.notes() {
+        let Ok(note) = note else {
+            continue;
+        };
+        if not_found.as_ref() == Some(&note.src_path)
+            || !ctx.is_published(&note.front_matter)
+            || note.front_matter.robots().contains("noindex")
+        {
+            continue;
+        }
+        entries.push(SitemapEntry {
+            url: absolute_url(base_url, &ctx.note_url(&note.src_path)),
+            lastmod: last_modified(ctx, &note.src_path)?,
+        });
+    }
+    entries.sort_by(|a, b| a.url.cmp(&b.url));
+    Ok(entries)
+}
+
+/// Write a [sitemap](https://www.sitemaps.org/protocol.html) containing
+/// `entries`.
+pub fn write_sitemap<W: Write>(entries: &[SitemapEntry], dest: &mut W) -> io::Result<()> {
+    writeln!(dest, r#"<?xml version="1.0" encoding="utf-8"?>"#)?;
+    writeln!(
+        dest,
+        r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#
+    )?;
+    for entry in entries {
+        writeln!(dest, "  <url>")?;
+        writeln!(dest, "    <loc>{}</loc>", escape_xml(&entry.url))?;
+        writeln!(dest, "    <lastmod>{}</lastmod>", entry.lastmod)?;
+        writeln!(dest, "  </url>")?;
+    }
+    writeln!(dest, "</urlset>")?;
+    Ok(())
+}
+
+/// Generate the sitemap in the destination directory.
+pub fn render_sitemap(ctx: &Context, base_url: &str, dest_dir: &Path) -> Result<()> {
+    let dest_path = output_path(dest_dir, &ctx.site().sitemap_path)?;
+    let entries = collect_entries(ctx, base_url)?;
+    let mut file = io::BufWriter::new(fs::File::create(dest_path)?);
+    write_sitemap(&entries, &mut file)?;
+    file.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::Config;
+    use crate::testing::Site;
+    use std::fs;
+
+    #[test]
+    fn sitemap() {
+        let site = Site::new(&[
+            ("b.md", "# B\n"),
+            ("sub/a.md", "# A\n"),
+            ("draft.md", "---\ndraft: true\n---\n# Draft\n"),
+            ("hidden.md", "---\nrobots: noindex\n---\n# Hidden\n"),
+            ("404.md", "# Lost\n"),
+            ("pic.png", "not really a PNG"),
+        ]);
+        // Without git, the date comes from the file's modification time.
+        let mtime: jiff::Timestamp = "2021-03-04T12:00:00Z".parse().unwrap();
+        for path in ["b.md", "sub/a.md"] {
+            let file = fs::File::options()
+                .write(true)
+                .open(site.src.path().join(path))
+                .unwrap();
+            file.set_modified(mtime.into()).unwrap();
+        }
+
+        let config: Config = toml::from_str("base_url = \"https://example.com/notes/\"").unwrap();
+        let mut ctx = site.context(config);
+        ctx.git_info = false;
+        let (_, tree) = site.build(&ctx);
+
+        let doc = roxmltree::Document::parse(&tree["sitemap.xml"]).unwrap();
+        let root = doc.root_element();
+        assert!(root.has_tag_name(("http://www.sitemaps.org/schemas/sitemap/0.9", "urlset")));
+        let urls: Vec<_> = root.children().filter(|n| n.has_tag_name("url")).collect();
+        let locs: Vec<_> = urls
+            .iter()
+            .map(|url| {
+                let loc = url.children().find(|c| c.has_tag_name("loc")).unwrap();
+                loc.text().unwrap()
+            })
+            .collect();
+        assert_eq!(
+            locs,
+            [
+                "https://example.com/notes/b.html",
+                "https://example.com/notes/sub/a.html",
+            ]
+        );
+        for url in urls {
+            let lastmod = url.children().find(|c| c.has_tag_name("lastmod")).unwrap();
+            assert_eq!(lastmod.text(), Some("2021-03-04"));
+        }
+    }
+
+    #[test]
+    fn unreadable_note() {
+        let site = Site::new(&[
+            ("good.md", "# Good\n"),
+            ("bad.md", "---\ndate: [\n---\n# Bad\n"),
+        ]);
+        let config: Config = toml::from_str("base_url = \"https://example.com/\"").unwrap();
+        let mut ctx = site.context(config);
+        ctx.git_info = false;
+        let (report, tree) = site.build(&ctx);
+        assert_eq!(report.failures.len(), 1);
+        assert!(tree["sitemap.xml"].contains("good.html"));
+        assert!(!tree["sitemap.xml"].contains("bad.html"));
+        assert!(tree["atom.xml"].contains("<title>Good</title>"));
+    }
+}
@@ -0,0 +1,276 @@
+use crate::core::{Context, output_path, replace_atomically};
+use crate::markdown;
+use anyhow::{Result, bail};
+use jiff::civil::Date;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The directory in the built site for tag pages.
+pub const TAGS_DIR: &str = "tags";
+
+/// A tag and the notes that have it.
+#[derive(Debug)]
+pub struct Tag {
+    /// The tag as it's written in the first note that has it.
+    pub name: String,
+    /// The tag's name in URLs, which is the same however the tag is
+    /// capitalized.
+    pub slug: String,
+    pub notes: Vec<TaggedNote>,
+}
+
+impl Tag {
+    /// The URL path of the tag's page, relative to the site root.
+    pub fn url(&self) -> String {
+        format!("{TAGS_DIR}/{}/", self.slug)
+    }
+}
+
+/// A note on a tag's page.
+#[derive(Debug)]
+pub struct TaggedNote {
+    /// The URL path of the note's page, relative to the site root.
+    pub url: String,
+    pub title: String,
+    pub date: Option<Date>,
+}
+
+/// Group the site's published notes by tag, sorted by slug. Tags that differ
+/// only in case are the same tag.
+///
+/// Each tag's notes are listed newest first, with undated notes last. Notes
+/// that can't be read are left out; the build reports them anyway.
+pub fn collect_tags(ctx: &Context) -> Vec<Tag> {
+    let slugs = markdown::SlugOptions {
+        lowercase: true,
+        ..ctx.config.slug_options()
+    };
+    let not_found = ctx.not_found_note();
+    let mut tags = BTreeMap::new();
+    for note in ctx.notes() {
+        let Ok(note) = note else {
+            continue;
+        };
+        if not_found.as_ref() == Some(&note.src_path) || !ctx.is_published(&note.front_matter) {
+            continue;
+        }
+        let url = ctx.note_url(&note.src_path);
+        let title = note.title.unwrap_or_else(|| {
+            let stem = note.src_path.file_stem().expect("no filename");
+            stem.to_string_lossy().into_owned()
+        });
+        for name in &note.front_matter.tags {
+            let slug = markdown::slugify(&name.to_lowercase(), &slugs);
+            if slug.is_empty() {
+                log::warn!(
+                    "skipping tag {name:?} in {}, which has no characters for a URL",
+                    note.src_path.display()
+                );
+                continue;
+            }
+            let tag = tags.entry(slug.clone()).or_insert_with(|| Tag {
+                name: name.clone(),
+                slug,
+                notes: vec![],
+            });
+            // A note can list the same tag twice, in different cases.
+            if tag.notes.last().is_none_or(|n| n.url != url) {
+                tag.notes.push(TaggedNote {
+                    url: url.clone(),
+                    title: title.clone(),
+                    date: note.front_matter.date,
+                });
+            }
+        }
+    }
+
+    let mut tags: Vec<_> = tags.into_values().collect();
+    for tag in &mut tags {
+        tag.notes
+            .sort_by(|a, b| b.date.cmp(&a.date).then_with(|| a.url.cmp(&b.url)));
+    }
+    tags
+}
+
+/// The template context for the overview page, which lists every tag.
+pub fn overview_context(ctx: &Context, tags: &[Tag]) -> minijinja::Value {
+    let entries: Vec<_> = tags
+        .iter()
+        .map(|tag| {
+            minijinja::context! {
+                title => tag.name,
+                href => format!("{}/", tag.slug),
+                count => tag.notes.len(),
+            }
+        })
+        .collect();
+    page_context(ctx, "Tags", format!("{TAGS_DIR}/"), None, entries)
+}
+
+/// The template context for a tag's page, which lists its notes.
+pub fn tag_context(ctx: &Context, tag: &Tag) -> minijinja::Value {
+    let url = tag.url();
+    let depth = url.matches('/').count();
+    let entries: Vec<_> = tag
+        .notes
+        .iter()
+        .map(|note| {
+            minijinja::context! {
+                title => note.title,
+                href => format!("{}{}", "../".repeat(depth), note.url),
+                count => None::<usize>,
+            }
+        })
+        .collect();
+    page_context(ctx, &tag.name, url, Some(&tag.name), entries)
+}
+
+fn page_context(
+    ctx: &Context,
+    title: &str,
+    url: String,
+    tag: Option<&str>,
+    entries: Vec<minijinja::Value>,
+) -> minijinja::Value {
+    minijinja::context! {
+        title => title,
+        tag => tag,
+        url => url,
+        entries => entries,
+        livereload => ctx.livereload,
+        html_attrs => ctx.config.html_attrs(),
+        site => ctx.config.site(),
+    }
+}
+
+/// Generate a page for each tag, plus an overview of all the tags, in the
+/// destination directory. Return the paths of the pages.
+///
+/// A tag page replaces whatever the source directory has at the same URL,
+/// like a `tags.md` note with pretty URLs. We warn about that, or fail in
+/// strict mode.
+pub fn render_tag_pages(ctx: &Context, dest_dir: &Path) -> Result<Vec<PathBuf>> {
+    let tags = collect_tags(ctx);
+    let env = ctx.templates();
+    let tmpl = env.get_template("tag.html")?;
+    let write_page = |url: &str, context| -> Result<PathBuf> {
+        if let Some(rsrc) = ctx.resolve_resource(url) {
+            let msg = format!("the tag page at {url} replaces {}", rsrc.path().display());
+            if ctx.strict {
+                bail!(msg);
+            }
+            log::warn!("{msg}");
+        }
+        let dest_path = output_path(dest_dir, &format!("{url}index.html"))?;
+        let html = tmpl.render(context)?;
+        replace_atomically(&dest_path, |tmp| fs::write(tmp, &html))?;
+        Ok(dest_path)
+    };
+    let mut paths = vec![write_page(
+        &format!("{TAGS_DIR}/"),
+        overview_context(ctx, &tags),
+    )?];
+    for tag in &tags {
+        paths.push(write_page(&tag.url(), tag_context(ctx, tag))?);
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Config;
+    use crate::testing::Site;
+
+    fn tag_config() -> Config {
+        toml::from_str("tag_pages = true\ngit = false\n").unwrap()
+    }
+
+    #[test]
+    fn two_tags() {
+        let site = Site::new(&[
+            ("a.md", "---\ntags: [rust, web]\n---\n# Alpha\n"),
+            ("sub/b.md", "---\ntags: [web]\n---\n# Beta\n"),
+            ("c.md", "# Untagged\n"),
+        ]);
+        let ctx = site.context(tag_config());
+        let (report, tree) = site.build(&ctx);
+        assert!(report.failures.is_empty());
+
+        let rust = &tree["tags/rust/index.html"];
+        assert!(rust.contains("<title>rust</title>"));
+        assert!(rust.contains("href=\"..&#x2f;..&#x2f;a.html\""));
+        assert!(!rust.contains("b.html"));
+
+        let web = &tree["tags/web/index.html"];
+        assert!(web.contains("href=\"..&#x2f;..&#x2f;a.html\""));
+        assert!(web.contains("href=\"..&#x2f;..&#x2f;sub&#x2f;b.html\""));
+
+        let overview = &tree["tags/index.html"];
+        assert!(overview.contains("href=\"rust&#x2f;\""));
+        assert!(overview.contains("href=\"web&#x2f;\""));
+        assert!(overview.contains("(2)"));
+        assert!(!overview.contains("Untagged"));
+    }
+
+    #[test]
+    fn case_folding() {
+        let site = Site::new(&[
+            ("a.md", "---\ntags: [Rust]\n---\n# A\n"),
+            ("b.md", "---\ntags: [rust, RUST]\n---\n# B\n"),
+            ("c.md", "---\ntags: [Big Ideas]\n---\n# C\n"),
+        ]);
+        let ctx = site.context(tag_config());
+        let tags = collect_tags(&ctx);
+        let summary: Vec<_> = tags
+            .iter()
+            .map(|t| (t.name.as_str(), t.slug.as_str(), t.notes.len()))
+            .collect();
+        assert_eq!(
+            summary,
+            [("Big Ideas", "big-ideas", 1), ("Rust", "rust", 2)]
+        );
+
+        let (_, tree) = site.build(&ctx);
+        assert!(tree.contains_key("tags/rust/index.html"));
+        assert!(tree.contains_key("tags/big-ideas/index.html"));
+        assert!(!tree.contains_key("tags/Rust/index.html"));
+    }
+
+    #[test]
+    fn unpublished_and_off() {
+        let site = Site::new(&[
+            ("a.md", "---\ntags: [x]\ndraft: true\n---\n# A\n"),
+            ("b.md", "---\ntags: [y]\n---\n# B\n"),
+        ]);
+        let ctx = site.context(tag_config());
+        let tags: Vec<_> = collect_tags(&ctx).into_iter().map(|t| t.slug).collect();
+        assert_eq!(tags, ["y"]);
+
+        let ctx = site.context(Config::default());
+        let (_, tree) = site.build(&ctx);
+        assert!(!tree.keys().any(|k| k.starts_with("tags/")));
+    }
+
+    #[test]
+    fn source_at_tags_url() {
+        let site = Site::new(&[
+            ("tags.md", "# My Tags\n"),
+            ("a.md", "---\ntags: [x]\n---\n# A\n"),
+        ]);
+        let mut ctx = site.context(toml::from_str("tag_pages = true\npretty_urls = true").unwrap());
+        let (_, tree) = site.build(&ctx);
+        assert!(tree["tags/index.html"].contains("<title>Tags</title>"));
+
+        ctx.strict = true;
+        let dest = tempfile::tempdir().unwrap();
+        let err = ctx.render_site(None, dest.path()).unwrap_err();
+        assert!(err.to_string().contains("tags.md"));
+
+        // Without pretty URLs, the note's page is `tags.html`, out of the way.
+        let mut ctx = site.context(tag_config());
+        ctx.strict = true;
+        assert!(ctx.render_site(None, dest.path()).is_ok());
+    }
+}
@@ -0,0 +1,77 @@
+//! Helpers for end-to-end tests that build whole sites in temporary directories.
+
+use crate::core::{BuildReport, Config, Context, RenderedNote, Resource};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use walkdir::WalkDir;
+
+/// The contents of a directory tree, mapping paths (relative to the root, with
+/// `/` separators) to file contents. Directories appear with a trailing `/` and
+/// no contents.
+pub type Tree = BTreeMap<String, String>;
+
+/// A temporary source directory for a site.
+pub struct Site {
+    pub src: tempfile::TempDir,
+}
+
+impl Site {
+    /// Set up a source directory containing `files`, which are pairs of
+    /// relative paths and contents. Parent directories are created as needed.
+    pub fn new(files: &[(&str, &str)]) -> Self {
+        let src = tempfile::tempdir().unwrap();
+        for (path, contents) in files {
+            let path = src.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        Self { src }
+    }
+
+    /// Make a context for building the site with a given configuration.
+    pub fn context(&self, config: Config) -> Context {
+        Context::new(self.src.path().to_str().unwrap(), false, config, None)
+    }
+
+    /// Build the site with `ctx` into a fresh destination directory and read
+    /// back everything it produced.
+    pub fn build(&self, ctx: &Context) -> (BuildReport, Tree) {
+        let dest = tempfile::tempdir().unwrap();
+        let report = ctx.render_site(None, dest.path()).unwrap();
+        (report, read_tree(dest.path()))
+    }
+}
+
+/// Read all the files and directories under `root`.
+pub fn read_tree(root: &Path) -> Tree {
+    let mut tree = Tree::new();
+    for entry in WalkDir::new(root).min_depth(1) {
+        let entry = entry.unwrap();
+        let rel_path = entry.path().strip_prefix(root).unwrap();
+        let mut name = crate::core::url_path(rel_path);
+        let contents = if entry.file_type().is_dir() {
+            name.push('/');
+            String::new()
+        } else {
+            String::from_utf8_lossy(&fs::read(entry.path()).unwrap()).into_owned()
+        };
+        tree.insert(name, contents);
+    }
+    tree
+}
+
+/// Render the published notes in `ctx`'s site the way the page pass does, for
+/// testing the files generated from them.
+pub fn rendered_notes(ctx: &Context) -> Vec<RenderedNote> {
+    let mut notes = vec![];
+    for rsrc in ctx.read_resources() {
+        if let Ok(Resource::Note(src_path)) = rsrc {
+            let doc = ctx.read_note(&src_path).unwrap();
+            if ctx.is_published(&doc.front_matter) {
+                notes.push(RenderedNote::new(&src_path, &doc));
+            }
+        }
+    }
+    notes
+}
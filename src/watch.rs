@@ -1,21 +1,55 @@
+use crate::core::{Context, NOTE_OVERRIDE, TEMPLATES};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, unbounded};
 use notify::{
     Config, EventHandler, EventKind, RecommendedWatcher, RecursiveMode, Watcher, event::ModifyKind,
 };
 use std::path::{Component, Path, PathBuf};
-use std::time::{Duration, Instant};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
 use tokio::sync::broadcast;
 use tokio_stream::wrappers::BroadcastStream;
 
-const DEBOUNCE_INTERVAL: Duration = Duration::from_millis(100);
+/// A reasonable amount of quiet time to wait for before reporting changes.
+pub const DEBOUNCE_INTERVAL: Duration = Duration::from_millis(100);
 
 /// An event telling a client what to do.
-///
-/// This looks silly, but this is an enum to allow for future extensibility: for
-/// example, a future command could reload only a single page instead of
-/// everything.
 #[derive(Debug, Clone)]
 pub enum Event {
+    /// Something changed that could affect every page.
     Reload,
+
+    /// A single note changed, so only its page (and its directory's index)
+    /// needs to be reloaded. This is the path of the note, relative to the
+    /// source directory.
+    ReloadPage(PathBuf),
+
+    /// A template changed, so every page needs to be rendered again with the
+    /// new templates.
+    ReloadTemplates,
+}
+
+/// The kinds of filesystem changes we react to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    /// A file's contents changed.
+    Contents,
+
+    /// A file was created, removed, or renamed, which can change directory
+    /// listings and links as well as the file's own page.
+    Files,
+}
+
+/// Decide whether a notify event kind is a change we care about.
+fn change_kind(kind: &EventKind) -> Option<Change> {
+    match kind {
+        EventKind::Modify(ModifyKind::Data(_)) => Some(Change::Contents),
+        EventKind::Create(_) | EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(_)) => {
+            Some(Change::Files)
+        }
+        _ => None,
+    }
 }
 
 /// An active filesystem watch that emits `Event`s on changes via a Tokio
@@ -23,19 +57,47 @@ pub enum Event {
 pub struct Watch {
     _watcher: RecommendedWatcher,
     channel: broadcast::Sender<Event>,
+    /// Set when a template changes, until someone reloads the templates.
+    templates_changed: Arc<AtomicBool>,
+    /// Set on any change, until someone forgets what they knew about the
+    /// notes.
+    notes_changed: Arc<AtomicBool>,
 }
 
 impl Watch {
-    pub fn new(paths: &[&Path]) -> Self {
+    /// Watch a source directory, whose notes are reloaded page by page, and
+    /// any number of other directories, whose changes reload everything.
+    ///
+    /// Files whose names match the `ignore` glob patterns are skipped, along
+    /// with hidden ones. A burst of changes produces a single event, once the
+    /// files have been quiet for the `debounce` interval.
+    pub fn new(source: &Path, others: &[&Path], ignore: &[String], debounce: Duration) -> Self {
         let (tx, _) = broadcast::channel(16);
 
+        let paths: Vec<&Path> = std::iter::once(source)
+            .chain(others.iter().copied())
+            .collect();
+        let bases: Vec<PathBuf> = paths
+            .iter()
+            .map(|p| std::path::absolute(p).expect("need absolute base path"))
+            .collect();
+
+        // The debouncing thread exits when the watcher (and its handler) drop.
+        let (changes_tx, changes_rx) = unbounded();
+        let source = bases[0].clone();
+        let channel = tx.clone();
+        let templates_changed = Arc::new(AtomicBool::new(false));
+        let notes_changed = Arc::new(AtomicBool::new(false));
+        let flags = [templates_changed.clone(), notes_changed.clone()];
+        thread::spawn(move || {
+            let [templates, notes] = &flags;
+            debounce_changes(&source, changes_rx, channel, templates, notes, debounce)
+        });
+
         let handler = Handler {
-            bases: paths
-                .iter()
-                .map(|p| std::path::absolute(p).expect("need absolute base path"))
-                .collect(),
-            channel: tx.clone(),
-            last_event: Instant::now(),
+            bases,
+            ignore: ignore.to_vec(),
+            changes: changes_tx,
         };
         let mut watcher = RecommendedWatcher::new(handler, Config::default()).unwrap();
         for path in paths {
@@ -45,56 +107,166 @@ impl Watch {
         Self {
             _watcher: watcher,
             channel: tx,
+            templates_changed,
+            notes_changed,
         }
     }
 
+    /// Watch everything that goes into a site: its source directories, the
+    /// `extra` directories, the ones listed in the configuration, and, in debug
+    /// mode, the templates directory. Only notes in the main source directory
+    /// get reloaded page by page.
+    pub fn site(ctx: &Context, extra: Vec<PathBuf>) -> Self {
+        let mut others: Vec<PathBuf> = ctx
+            .src_dirs()
+            .filter(|dir| *dir != ctx.src_dir)
+            .map(Path::to_path_buf)
+            .collect();
+        others.extend(extra);
+        others.extend(ctx.config.watch().iter().map(|p| ctx.src_dir.join(p)));
+        #[cfg(debug_assertions)]
+        others.push(TEMPLATES.dir.into());
+        let others: Vec<&Path> = others.iter().map(PathBuf::as_path).collect();
+        Self::new(
+            &ctx.src_dir,
+            &others,
+            ctx.config.ignore(),
+            DEBOUNCE_INTERVAL,
+        )
+    }
+
+    /// Have any templates changed since the last time we asked? This is set
+    /// before the change's event goes out, so anyone who hears about the
+    /// change will see it.
+    pub fn take_templates_changed(&self) -> bool {
+        self.templates_changed.swap(false, Ordering::SeqCst)
+    }
+
+    /// Has anything changed since the last time we asked? Like
+    /// `take_templates_changed`, this is set before the change's event goes
+    /// out.
+    pub fn take_notes_changed(&self) -> bool {
+        self.notes_changed.swap(false, Ordering::SeqCst)
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.channel.subscribe()
+    }
+
     pub fn stream(&self) -> BroadcastStream<Event> {
-        let rx = self.channel.subscribe();
-        BroadcastStream::new(rx)
+        BroadcastStream::new(self.subscribe())
     }
 }
 
 struct Handler {
-    channel: broadcast::Sender<Event>,
+    /// Absolute paths to the watched directories. The first is the source
+    /// directory.
     bases: Vec<PathBuf>,
-    last_event: Instant,
+    /// Configured glob patterns for names to ignore.
+    ignore: Vec<String>,
+    /// Where to send changed paths for debouncing.
+    changes: Sender<(Change, Vec<PathBuf>)>,
 }
 
 impl EventHandler for Handler {
     fn handle_event(&mut self, res: notify::Result<notify::Event>) {
-        // Ignore events that happen close together.
-        if self.last_event.elapsed() < DEBOUNCE_INTERVAL {
-            return;
-        }
-
-        // Is this a modification of a file we care about?
+        // Is this a change to a file we care about?
         if let Ok(event) = res
-            && let EventKind::Modify(ModifyKind::Data(_)) = event.kind
-            && !event.paths.iter().any(|p| ignore_path(&self.bases, p))
+            && let Some(change) = change_kind(&event.kind)
+            && !event
+                .paths
+                .iter()
+                .any(|p| ignore_path(&self.bases, &self.ignore, p))
         {
-            self.last_event = Instant::now();
+            let _ = self.changes.send((change, event.paths));
+        }
+    }
+}
 
-            // We ignore errors when sending events: it's OK to
-            // silently drop messages when there are no subscribers.
-            let _ = self.channel.send(Event::Reload);
+/// Collect bursts of changed paths and broadcast one event for each burst.
+///
+/// A burst ends when no changes arrive for `interval`, so the event always
+/// reflects the last change in the burst. A burst that creates or removes any
+/// files reloads everything, and one that touches a template sets
+/// `templates_changed` too. Every burst sets `notes_changed`. This runs until
+/// `changes` is disconnected.
+fn debounce_changes(
+    source: &Path,
+    changes: Receiver<(Change, Vec<PathBuf>)>,
+    channel: broadcast::Sender<Event>,
+    templates_changed: &AtomicBool,
+    notes_changed: &AtomicBool,
+    interval: Duration,
+) {
+    while let Ok((mut change, mut burst)) = changes.recv() {
+        loop {
+            match changes.recv_timeout(interval) {
+                Ok((next, paths)) => {
+                    if next == Change::Files {
+                        change = Change::Files;
+                    }
+                    burst.extend(paths);
+                }
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
         }
+        burst.sort();
+        burst.dedup();
+
+        // We ignore errors when sending events: it's OK to
+        // silently drop messages when there are no subscribers.
+        notes_changed.store(true, Ordering::SeqCst);
+        let event = if burst.iter().any(|path| is_template(path)) {
+            templates_changed.store(true, Ordering::SeqCst);
+            Event::ReloadTemplates
+        } else {
+            match change {
+                Change::Contents => classify(source, &burst),
+                Change::Files => Event::Reload,
+            }
+        };
+        let _ = channel.send(event);
+    }
+}
+
+/// Decide what clients should do about a change to some files. Anything other
+/// than a single changed note (e.g., a template, stylesheet, or image) could
+/// appear on any page.
+fn classify(source: &Path, paths: &[PathBuf]) -> Event {
+    if let [path] = paths
+        && path.extension().is_some_and(|e| e == "md")
+        && let Ok(rel) = path.strip_prefix(source)
+    {
+        Event::ReloadPage(rel.into())
+    } else {
+        Event::Reload
     }
 }
 
+/// Is this a template file: a built-in one (in debug mode, where we read them
+/// from disk) or a note template override?
+fn is_template(path: &Path) -> bool {
+    path.starts_with(TEMPLATES.dir) || path.file_name().is_some_and(|n| n == NOTE_OVERRIDE)
+}
+
 /// Check whether we should ignore a given path inside of base directories.
 ///
 /// Anything outside `bases` is ignored. Inside of the base directories, any
-/// file or directory with an ignored pattern is (recursively) ignored. All
-/// paths must be provided in absolute form.
-fn ignore_path(bases: &[PathBuf], path: &Path) -> bool {
+/// file or directory that is hidden or matches one of the `ignore` patterns is
+/// (recursively) ignored, except for note template overrides. All paths must
+/// be provided in absolute form.
+pub(crate) fn ignore_path(bases: &[PathBuf], ignore: &[String], path: &Path) -> bool {
     for base in bases {
         let frag = match path.strip_prefix(base) {
             Ok(p) => p,
             Err(_) => continue,
         };
-        for comp in frag.components() {
+        let mut comps = frag.components().peekable();
+        while let Some(comp) = comps.next() {
             if let Component::Normal(name) = comp
-                && crate::core::ignore_filename(name)
+                && (crate::core::ignore_filename(name) || crate::core::matches_any(ignore, name))
+                && !(comps.peek().is_none() && name == crate::core::NOTE_OVERRIDE)
             {
                 return true;
             }
@@ -103,3 +275,153 @@ fn ignore_path(bases: &[PathBuf], path: &Path) -> bool {
     }
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_note() {
+        let event = classify(Path::new("/src"), &["/src/dir/a.md".into()]);
+        assert!(matches!(event, Event::ReloadPage(path) if path == Path::new("dir/a.md")));
+    }
+
+    #[test]
+    fn classify_other() {
+        let src = Path::new("/src");
+        assert!(matches!(
+            classify(src, &["/src/img.png".into()]),
+            Event::Reload
+        ));
+        assert!(matches!(
+            classify(src, &["/templates/note.html".into()]),
+            Event::Reload
+        ));
+        assert!(matches!(
+            classify(src, &["/src/a.md".into(), "/src/b.md".into()]),
+            Event::Reload
+        ));
+    }
+
+    #[test]
+    fn change_kinds() {
+        use notify::event::{
+            AccessKind, CreateKind, DataChange, MetadataKind, RemoveKind, RenameMode,
+        };
+        assert_eq!(
+            change_kind(&EventKind::Modify(ModifyKind::Data(DataChange::Content))),
+            Some(Change::Contents)
+        );
+        assert_eq!(
+            change_kind(&EventKind::Create(CreateKind::File)),
+            Some(Change::Files)
+        );
+        assert_eq!(
+            change_kind(&EventKind::Remove(RemoveKind::Any)),
+            Some(Change::Files)
+        );
+        assert_eq!(
+            change_kind(&EventKind::Modify(ModifyKind::Name(RenameMode::Both))),
+            Some(Change::Files)
+        );
+        assert_eq!(
+            change_kind(&EventKind::Modify(ModifyKind::Metadata(MetadataKind::Any))),
+            None
+        );
+        assert_eq!(change_kind(&EventKind::Access(AccessKind::Any)), None);
+    }
+
+    /// Wait a little while for a watch to send an event.
+    async fn next_event(rx: &mut broadcast::Receiver<Event>) -> Event {
+        tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .expect("no event")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn create_note() {
+        let src = tempfile::tempdir().unwrap();
+        let watch = Watch::new(src.path(), &[], &[], Duration::from_millis(10));
+        let mut rx = watch.channel.subscribe();
+
+        std::fs::File::create(src.path().join("new.md")).unwrap();
+        assert!(matches!(next_event(&mut rx).await, Event::Reload));
+    }
+
+    #[tokio::test]
+    async fn extra_directory() {
+        let src = tempfile::tempdir().unwrap();
+        let extra = tempfile::tempdir().unwrap();
+        let watch = Watch::new(src.path(), &[extra.path()], &[], Duration::from_millis(10));
+        let mut rx = watch.channel.subscribe();
+
+        std::fs::write(extra.path().join("shared.css"), "body {}").unwrap();
+        assert!(matches!(next_event(&mut rx).await, Event::Reload));
+    }
+
+    #[tokio::test]
+    async fn debounce_burst() {
+        let (changes_tx, changes_rx) = unbounded();
+        let (tx, mut rx) = broadcast::channel(16);
+        let interval = Duration::from_millis(50);
+        let flag = Arc::new(AtomicBool::new(false));
+        let templates_changed = flag.clone();
+        let notes_flag = Arc::new(AtomicBool::new(false));
+        let notes_changed = notes_flag.clone();
+        let debouncer = thread::spawn(move || {
+            debounce_changes(
+                Path::new("/src"),
+                changes_rx,
+                tx,
+                &flag,
+                &notes_flag,
+                interval,
+            )
+        });
+
+        // Two quick saves of the same note produce one event.
+        changes_tx
+            .send((Change::Contents, vec!["/src/a.md".into()]))
+            .unwrap();
+        changes_tx
+            .send((Change::Contents, vec!["/src/a.md".into()]))
+            .unwrap();
+        let event = next_event(&mut rx).await;
+        assert!(matches!(event, Event::ReloadPage(path) if path == Path::new("a.md")));
+        assert!(rx.try_recv().is_err());
+        assert!(notes_changed.swap(false, Ordering::SeqCst));
+
+        // Changes to two different files reload everything.
+        changes_tx
+            .send((Change::Contents, vec!["/src/a.md".into()]))
+            .unwrap();
+        changes_tx
+            .send((Change::Contents, vec!["/src/b.md".into()]))
+            .unwrap();
+        assert!(matches!(next_event(&mut rx).await, Event::Reload));
+
+        // Adding a note reloads everything, even if it's edited right after.
+        changes_tx
+            .send((Change::Files, vec!["/src/c.md".into()]))
+            .unwrap();
+        changes_tx
+            .send((Change::Contents, vec!["/src/c.md".into()]))
+            .unwrap();
+        assert!(matches!(next_event(&mut rx).await, Event::Reload));
+        assert!(!templates_changed.load(Ordering::SeqCst));
+
+        // Changing a template override reloads the templates.
+        changes_tx
+            .send((Change::Contents, vec!["/src/a.md".into()]))
+            .unwrap();
+        changes_tx
+            .send((Change::Contents, vec!["/src/dir/_note.html".into()]))
+            .unwrap();
+        assert!(matches!(next_event(&mut rx).await, Event::ReloadTemplates));
+        assert!(templates_changed.load(Ordering::SeqCst));
+
+        drop(changes_tx);
+        debouncer.join().unwrap();
+    }
+}
@@ -1,4 +1,14 @@
-const source = new EventSource("/_notify");
+const page = decodeURIComponent(location.pathname);
+const source = new EventSource("/_notify?path=" + encodeURIComponent(page));
 source.addEventListener("reload", (event) => {
   location.reload();
 });
+source.addEventListener("reload-page", (event) => {
+  // Reload if the changed page is this one or is listed in this directory.
+  const changed = "/" + event.data;
+  // (Pretty page URLs end in a slash, so skip that when finding the directory.)
+  const dir = changed.slice(0, changed.lastIndexOf("/", changed.length - 2) + 1);
+  if (changed === page || dir === page) {
+    location.reload();
+  }
+});